@@ -0,0 +1,381 @@
+use serde::Deserialize;
+
+use super::{span, Entry, EntryKind, ImportError};
+use crate::epub::{self, BookError, BookStructure, Cfi, CfiLocation, EpubArchive};
+use crate::library::{Book, Library};
+use crate::timestamp;
+
+/// Annotations exported from Calibre's viewer, a
+/// `calibre_annotation_collection`. The viewer's export doesn't say which
+/// book it came from; other tools add the book's details alongside.
+#[derive(Debug, Default, Deserialize)]
+pub struct Collection {
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+    /// SHA-256 of the book file, as the library stores it.
+    book_hash: Option<String>,
+    /// A map of scheme to value, as in Calibre's metadata, or a list.
+    identifiers: Option<serde_json::Value>,
+    title: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Annotation {
+    Highlight {
+        start_cfi: Option<String>,
+        end_cfi: Option<String>,
+        spine_index: Option<usize>,
+        /// Path of the content document inside the book.
+        spine_name: Option<String>,
+        highlighted_text: Option<String>,
+        notes: Option<String>,
+        style: Option<serde_json::Value>,
+        timestamp: Option<String>,
+        /// Set on highlights deleted since the file was synced.
+        #[serde(default)]
+        removed: bool,
+    },
+    Bookmark {
+        title: Option<String>,
+        /// A CFI whose first step is the spine position.
+        pos: Option<String>,
+        timestamp: Option<String>,
+        #[serde(default)]
+        removed: bool,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// Accepts the collection as exported, or a bare list of annotations.
+pub fn parse(source: &str) -> Result<Collection, serde_json::Error> {
+    if source.trim_start().starts_with('[') {
+        let annotations = serde_json::from_str(source)?;
+        return Ok(Collection {
+            annotations,
+            ..Collection::default()
+        });
+    }
+    serde_json::from_str(source)
+}
+
+/// The library book a collection names, by its hash, then identifiers, then
+/// title. A collection naming none is an error, as there's nothing to match.
+pub fn find_book(library: &Library, collection: &Collection) -> Result<Option<Book>, ImportError> {
+    let identifiers = collection.identifiers();
+    let title = collection
+        .title
+        .as_deref()
+        .map(str::trim)
+        .filter(|t| !t.is_empty());
+    if collection.book_hash.is_none() && identifiers.is_empty() && title.is_none() {
+        return Err(ImportError::BookNotNamed);
+    }
+    if let Some(hash) = &collection.book_hash {
+        if let Some(book) = library.find_by_hash(&hash.trim().to_ascii_lowercase())? {
+            return Ok(Some(book));
+        }
+    }
+    if let Some(book) = library.find_by_identifier(&identifiers)? {
+        return Ok(Some(book));
+    }
+    match title {
+        Some(title) => Ok(library.find_by_title(title)?),
+        None => Ok(None),
+    }
+}
+
+impl Collection {
+    fn identifiers(&self) -> Vec<String> {
+        let strings = |values: Vec<&serde_json::Value>| {
+            values
+                .into_iter()
+                .filter_map(|v| v.as_str())
+                .map(str::to_string)
+                .collect()
+        };
+        match &self.identifiers {
+            Some(serde_json::Value::Object(map)) => strings(map.values().collect()),
+            Some(serde_json::Value::Array(list)) => strings(list.iter().collect()),
+            Some(serde_json::Value::String(id)) => vec![id.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn entries(
+        &self,
+        archive: &mut EpubArchive,
+        structure: &BookStructure,
+    ) -> Result<Vec<Entry>, BookError> {
+        let mut entries = Vec::new();
+        for annotation in &self.annotations {
+            match annotation {
+                Annotation::Highlight {
+                    start_cfi,
+                    end_cfi,
+                    spine_index,
+                    spine_name,
+                    highlighted_text,
+                    notes,
+                    style,
+                    timestamp,
+                    removed: false,
+                } => {
+                    let index = spine_name
+                        .as_deref()
+                        .and_then(|name| structure.spine.iter().position(|s| s.full_path == name))
+                        .or(spine_index.filter(|&i| i < structure.spine.len()));
+                    let location = match index {
+                        Some(index) => {
+                            let start = resolve(archive, structure, index, start_cfi.as_deref())?;
+                            let end = resolve(archive, structure, index, end_cfi.as_deref())?;
+                            span(start, end)
+                        }
+                        None => None,
+                    };
+                    let created_at = timestamp.as_deref().and_then(timestamp::parse_utc);
+                    entries.push(Entry {
+                        kind: EntryKind::Annotation,
+                        location,
+                        text: highlighted_text.clone(),
+                        note: notes.clone().filter(|n| !n.trim().is_empty()),
+                        color: style.as_ref().and_then(color),
+                        created_at,
+                        updated_at: created_at,
+                    });
+                }
+                Annotation::Bookmark {
+                    title,
+                    pos,
+                    timestamp,
+                    removed: false,
+                } => {
+                    let location = match pos.as_deref().and_then(split_position) {
+                        Some((index, path)) => resolve(archive, structure, index, Some(path))?,
+                        None => None,
+                    };
+                    let created_at = timestamp.as_deref().and_then(timestamp::parse_utc);
+                    entries.push(Entry {
+                        kind: EntryKind::Bookmark,
+                        location,
+                        text: None,
+                        note: title.clone(),
+                        color: None,
+                        created_at,
+                        updated_at: created_at,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Splits a bookmark's `epubcfi(/8/2/4:0)` into a spine index and the path
+/// inside that document.
+fn split_position(pos: &str) -> Option<(usize, &str)> {
+    let inner = pos.trim().strip_prefix("epubcfi(")?.strip_suffix(')')?;
+    let rest = inner.strip_prefix('/')?;
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let step: usize = rest[..digits].parse().ok()?;
+    Some(((step / 2).checked_sub(1)?, &rest[digits..]))
+}
+
+/// Resolves a path inside the content document at `index`. Calibre counts
+/// the root element as a step, so its paths start `/2/4/...` where a
+/// package CFI would have `!/4/...`.
+fn resolve(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    index: usize,
+    path: Option<&str>,
+) -> Result<Option<CfiLocation>, BookError> {
+    let Some(path) = path
+        .and_then(|p| p.strip_prefix("/2"))
+        .filter(|rest| !rest.starts_with(|c: char| c.is_ascii_digit()))
+    else {
+        return Ok(None);
+    };
+    let cfi = format!(
+        "epubcfi(/{}/{}!{path})",
+        structure.spine_step,
+        (index + 1) * 2
+    );
+    let Ok(cfi) = cfi.parse::<Cfi>() else {
+        return Ok(None);
+    };
+    match epub::resolve_cfi(archive, structure, &cfi) {
+        Ok(location) => Ok(Some(location)),
+        Err(BookError::Cfi(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Highlight colour of a style like `{"kind": "color", "which": "yellow"}`.
+/// Underlines and strike-throughs keep the default colour.
+fn color(style: &serde_json::Value) -> Option<String> {
+    if style.get("kind")?.as_str()? != "color" {
+        return None;
+    }
+    style
+        .get("which")
+        .or_else(|| style.get("background-color"))?
+        .as_str()
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::NewBook;
+    use crate::test_support::{write_epub, ScratchDir};
+
+    /// A two-chapter book in a scratch directory.
+    struct Book {
+        dir: ScratchDir,
+    }
+
+    impl Book {
+        fn new(name: &str) -> Self {
+            let dir = ScratchDir::new(&format!("calibre-{name}"));
+            write_epub(
+                &dir.join("book.epub"),
+                "Calibre",
+                &["<p>First chapter.</p>", "<p>Second.</p><p>Hello world.</p>"],
+            );
+            Self { dir }
+        }
+
+        fn open(&self) -> (EpubArchive, BookStructure) {
+            let mut archive = EpubArchive::open(&self.dir.join("book.epub")).unwrap();
+            let structure = epub::read_structure(&mut archive).unwrap();
+            (archive, structure)
+        }
+    }
+
+    #[test]
+    fn splits_bookmark_positions() {
+        assert_eq!(split_position("epubcfi(/8/2/4:0)"), Some((3, "/2/4:0")));
+        assert_eq!(split_position(" epubcfi(/2) "), Some((0, "")));
+        assert_eq!(split_position("epubcfi(/0/2)"), None);
+        assert_eq!(split_position("epubcfi(/x/2)"), None);
+        assert_eq!(split_position("epubcfi(8/2)"), None);
+        assert_eq!(split_position("/8/2"), None);
+    }
+
+    #[test]
+    fn resolves_paths_within_a_chapter() {
+        let book = Book::new("resolve");
+        let (mut archive, structure) = book.open();
+        let mut at = |index, path| resolve(&mut archive, &structure, index, path).unwrap();
+
+        // The second paragraph of the second chapter, after "Second."
+        let location = at(1, Some("/2/4/4/1:6")).unwrap();
+        assert_eq!((location.spine_index, location.offset), (1, 13));
+        let location = at(0, Some("/2/4/2/1:0")).unwrap();
+        assert_eq!((location.spine_index, location.offset), (0, 0));
+
+        // Paths that don't start at the root element, or aren't CFIs.
+        assert!(at(1, Some("/4/4/1:6")).is_none());
+        assert!(at(1, Some("/24/4")).is_none());
+        assert!(at(1, Some("/2/4/x")).is_none());
+        assert!(at(1, None).is_none());
+    }
+
+    #[test]
+    fn imports_highlights_and_bookmarks() {
+        let book = Book::new("entries");
+        let (mut archive, structure) = book.open();
+        let collection = parse(
+            r#"[
+                {"type": "highlight", "start_cfi": "/2/4/4/1:0", "end_cfi": "/2/4/4/1:5",
+                 "spine_name": "OEBPS/ch2.xhtml", "highlighted_text": "Hello",
+                 "style": {"kind": "color", "which": "yellow"},
+                 "timestamp": "2024-05-01T09:30:00.000Z"},
+                {"type": "highlight", "start_cfi": "/2/4/2/1:0", "end_cfi": "/2/4/2/1:5",
+                 "spine_index": 0, "removed": true},
+                {"type": "bookmark", "title": "Here", "pos": "epubcfi(/4/2/4/2/1:3)"},
+                {"type": "something else"}
+            ]"#,
+        )
+        .unwrap();
+        let entries = collection.entries(&mut archive, &structure).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let highlight = &entries[0];
+        let location = highlight.location.unwrap();
+        assert_eq!(
+            (location.spine_index, location.offset, location.length),
+            (1, 7, 5)
+        );
+        assert_eq!(highlight.color.as_deref(), Some("yellow"));
+        assert_eq!(highlight.created_at, Some(1_714_555_800_000));
+
+        let bookmark = &entries[1];
+        assert!(matches!(bookmark.kind, EntryKind::Bookmark));
+        let location = bookmark.location.unwrap();
+        assert_eq!((location.spine_index, location.offset), (1, 3));
+        assert_eq!(bookmark.note.as_deref(), Some("Here"));
+    }
+
+    #[test]
+    fn finds_the_book_a_collection_names() {
+        let dir = ScratchDir::new("calibre-find");
+        let library = Library::open(&dir.join("library.sqlite3")).unwrap();
+        let add = |path: &str, title: &str, hash: &str, identifier: &str| {
+            let book = NewBook {
+                path: dir.join(path).to_string_lossy().into_owned(),
+                title: title.to_string(),
+                author: String::new(),
+            };
+            let book = library.add(book, Some(hash)).unwrap();
+            library
+                .set_identifiers(&book.id, &[identifier.to_string()])
+                .unwrap();
+            book
+        };
+        let first = add("a.epub", "Persuasion", "aa11", "urn:isbn:978-0-14-143968-8");
+        let second = add(
+            "b.epub",
+            "Emma",
+            "bb22",
+            "urn:uuid:0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0",
+        );
+        add("c.epub", "Emma", "cc33", "urn:isbn:978-0-14-143958-9");
+        let find = |json: &str| find_book(&library, &parse(json).unwrap()).map(|b| b.map(|b| b.id));
+
+        assert_eq!(
+            find(r#"{"book_hash": "BB22"}"#).unwrap(),
+            Some(second.id.clone())
+        );
+        assert_eq!(
+            find(r#"{"identifiers": {"isbn": "9780141439688", "calibre": "12"}}"#).unwrap(),
+            Some(first.id.clone())
+        );
+        assert_eq!(
+            find(r#"{"identifiers": ["uuid:0F1E2D3C-4B5A-6978-8796-A5B4C3D2E1F0"]}"#).unwrap(),
+            Some(second.id)
+        );
+        assert_eq!(
+            find(r#"{"title": " persuasion "}"#).unwrap(),
+            Some(first.id)
+        );
+        // Two books share this title, so it says nothing.
+        assert_eq!(find(r#"{"title": "Emma"}"#).unwrap(), None);
+        assert_eq!(
+            find(r#"{"book_hash": "ff00", "title": "Unknown"}"#).unwrap(),
+            None
+        );
+
+        for unnamed in [r#"{"annotations": []}"#, "[]", r#"{"title": " "}"#] {
+            assert!(
+                matches!(find(unnamed), Err(ImportError::BookNotNamed)),
+                "{unnamed}"
+            );
+        }
+    }
+}
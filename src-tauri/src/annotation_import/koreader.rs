@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+
+use super::lua::{self, LuaError, Table, Value};
+use super::{span, Entry, EntryKind, ImportError};
+use crate::epub::{self, BookError, BookStructure, CfiLocation, EpubArchive};
+use crate::library::{hash_file, Book, Library};
+use crate::timestamp;
+
+/// The settings KOReader keeps for a book in `<book>.sdr/metadata.epub.lua`.
+pub struct Sidecar {
+    settings: Table,
+}
+
+pub fn parse(source: &str) -> Result<Sidecar, LuaError> {
+    match lua::parse(source)? {
+        Value::Table(settings) => Ok(Sidecar { settings }),
+        _ => Err(LuaError {
+            position: 0,
+            reason: "expected a table",
+        }),
+    }
+}
+
+/// The library book a sidecar belongs to: the book file next to its `.sdr`
+/// folder, or failing that (the sidecar was copied elsewhere, or the book
+/// was) one with the same identifiers.
+pub fn find_book(
+    library: &Library,
+    path: &Path,
+    sidecar: &Sidecar,
+) -> Result<Option<Book>, ImportError> {
+    if let Some(book_path) = sidecar.book_path(path) {
+        let book_path_str = book_path.to_string_lossy();
+        if let Some(book) = library.find_by_path(&book_path_str)? {
+            return Ok(Some(book));
+        }
+        if book_path.is_file() {
+            if let Some(book) = library.find_by_hash(&hash_file(&book_path)?)? {
+                return Ok(Some(book));
+            }
+        }
+    }
+    Ok(library.find_by_identifier(&sidecar.identifiers())?)
+}
+
+impl Sidecar {
+    /// Where the book would be if the sidecar is still in its `.sdr` folder.
+    /// KOReader names that folder after the book minus its extension.
+    fn book_path(&self, path: &Path) -> Option<PathBuf> {
+        let sdr = path.parent()?;
+        let stem = sdr.file_name()?.to_str()?.strip_suffix(".sdr")?;
+        let dir = sdr.parent()?;
+        let recorded = self
+            .settings
+            .str("doc_path")
+            .and_then(|p| Path::new(p).file_name())
+            .map(|name| dir.join(name))
+            .filter(|p| p.is_file());
+        Some(recorded.unwrap_or_else(|| dir.join(format!("{stem}.epub"))))
+    }
+
+    /// `doc_props.identifiers`, which holds one identifier per line.
+    fn identifiers(&self) -> Vec<String> {
+        self.settings
+            .table("doc_props")
+            .and_then(|props| props.str("identifiers"))
+            .map(|ids| {
+                ids.lines()
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn entries(
+        &self,
+        archive: &mut EpubArchive,
+        structure: &BookStructure,
+    ) -> Result<Vec<Entry>, BookError> {
+        match self.settings.table("annotations") {
+            Some(annotations) => annotation_entries(annotations, archive, structure),
+            None => self.legacy_entries(archive, structure),
+        }
+    }
+
+    /// Before 2024 KOReader kept highlights in `highlight`, keyed by page,
+    /// and a `bookmarks` list holding page bookmarks as well as an entry per
+    /// highlight, which is where its note is.
+    fn legacy_entries(
+        &self,
+        archive: &mut EpubArchive,
+        structure: &BookStructure,
+    ) -> Result<Vec<Entry>, BookError> {
+        let bookmarks: Vec<&Table> = self
+            .settings
+            .table("bookmarks")
+            .map(|b| {
+                b.sequence()
+                    .into_iter()
+                    .filter_map(Value::as_table)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut entries = Vec::new();
+        let pages = self.settings.table("highlight").map(|h| &h.entries[..]);
+        for (_, page) in pages.unwrap_or_default() {
+            let Some(page) = page.as_table() else {
+                continue;
+            };
+            for item in page.sequence().into_iter().filter_map(Value::as_table) {
+                let note = bookmarks
+                    .iter()
+                    .find(|b| {
+                        b.get("highlighted").is_some_and(Value::is_truthy)
+                            && b.str("pos0") == item.str("pos0")
+                    })
+                    .and_then(|b| user_text(b));
+                let location = locate(archive, structure, item.str("pos0"), item.str("pos1"))?;
+                let created_at = item.str("datetime").and_then(timestamp::parse_local);
+                entries.push(Entry {
+                    kind: EntryKind::Annotation,
+                    location,
+                    text: item.str("text").map(str::to_string),
+                    note,
+                    color: item.str("color").map(str::to_string),
+                    created_at,
+                    updated_at: created_at,
+                });
+            }
+        }
+        for bookmark in bookmarks {
+            if bookmark.get("highlighted").is_some_and(Value::is_truthy) {
+                continue;
+            }
+            let location = locate(archive, structure, bookmark.str("page"), None)?;
+            let created_at = bookmark.str("datetime").and_then(timestamp::parse_local);
+            entries.push(Entry {
+                kind: EntryKind::Bookmark,
+                location,
+                text: None,
+                note: user_text(bookmark),
+                color: None,
+                created_at,
+                updated_at: created_at,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// The current format: one list in which highlights have `pos0` and `pos1`,
+/// and page bookmarks only a `page`.
+fn annotation_entries(
+    annotations: &Table,
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+) -> Result<Vec<Entry>, BookError> {
+    let mut entries = Vec::new();
+    for item in annotations
+        .sequence()
+        .into_iter()
+        .filter_map(Value::as_table)
+    {
+        let created_at = item.str("datetime").and_then(timestamp::parse_local);
+        let updated_at = item
+            .str("datetime_updated")
+            .and_then(timestamp::parse_local)
+            .or(created_at);
+        let note = item
+            .str("note")
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(str::to_string);
+        let entry = match item.str("pos0") {
+            Some(pos0) => Entry {
+                kind: EntryKind::Annotation,
+                location: locate(archive, structure, Some(pos0), item.str("pos1"))?,
+                text: item.str("text").map(str::to_string),
+                note,
+                color: item.str("color").map(str::to_string),
+                created_at,
+                updated_at,
+            },
+            None => Entry {
+                kind: EntryKind::Bookmark,
+                location: locate(archive, structure, item.str("page"), None)?,
+                text: None,
+                note,
+                color: None,
+                created_at,
+                updated_at,
+            },
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn locate(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    pos0: Option<&str>,
+    pos1: Option<&str>,
+) -> Result<Option<CfiLocation>, BookError> {
+    let mut resolve = |pointer: Option<&str>| match pointer {
+        Some(pointer) => epub::resolve_xpointer(archive, structure, pointer),
+        None => Ok(None),
+    };
+    let start = resolve(pos0)?;
+    let end = resolve(pos1)?;
+    Ok(span(start, end))
+}
+
+/// A legacy bookmark's `text`, unless it's the description KOReader fills in
+/// by default, which ends with the bookmark's date.
+fn user_text(bookmark: &Table) -> Option<String> {
+    let text = bookmark.str("text")?.trim();
+    let generated = bookmark
+        .str("datetime")
+        .is_some_and(|date| text.ends_with(date));
+    (!text.is_empty() && !generated).then(|| text.to_string())
+}
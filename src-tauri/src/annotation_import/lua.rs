@@ -0,0 +1,510 @@
+/// Tables nested deeper than this are rejected rather than risk running out
+/// of stack; KOReader's own files nest a handful deep.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Malformed Lua at byte {position}: {reason}")]
+pub struct LuaError {
+    pub position: usize,
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Table(Table),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Self::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Bool(false))
+    }
+}
+
+/// Table entries in source order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table {
+    pub entries: Vec<(Value, Value)>,
+}
+
+impl Table {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v)
+    }
+
+    pub fn str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(Value::as_str)
+    }
+
+    pub fn table(&self, key: &str) -> Option<&Table> {
+        self.get(key).and_then(Value::as_table)
+    }
+
+    /// Values under numeric keys, in key order, as `ipairs` would see them
+    /// give or take gaps.
+    pub fn sequence(&self) -> Vec<&Value> {
+        let mut items: Vec<(f64, &Value)> = self
+            .entries
+            .iter()
+            .filter_map(|(k, v)| match k {
+                Value::Number(n) => Some((*n, v)),
+                _ => None,
+            })
+            .collect();
+        items.sort_by(|a, b| a.0.total_cmp(&b.0));
+        items.into_iter().map(|(_, v)| v).collect()
+    }
+}
+
+/// Parses a settings file the way KOReader writes them: `return { ... }`
+/// made of table constructors and literals. Expressions aren't supported.
+pub fn parse(source: &str) -> Result<Value, LuaError> {
+    let mut parser = Parser {
+        src: source.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    parser.skip_space()?;
+    if parser.keyword("return") {
+        parser.skip_space()?;
+    }
+    let value = parser.value()?;
+    parser.skip_space()?;
+    parser.eat(b';');
+    parser.skip_space()?;
+    if parser.pos < parser.src.len() {
+        return Err(parser.error("unexpected trailing input"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    /// Tables currently open.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &'static str) -> LuaError {
+        LuaError {
+            position: self.pos,
+            reason,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, byte: u8, reason: &'static str) -> Result<(), LuaError> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(self.error(reason))
+        }
+    }
+
+    /// Consumes `word` if it appears here as a whole identifier.
+    fn keyword(&mut self, word: &str) -> bool {
+        let end = self.pos + word.len();
+        let matches = self.src.get(self.pos..end) == Some(word.as_bytes())
+            && !self
+                .src
+                .get(end)
+                .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_');
+        if matches {
+            self.pos = end;
+        }
+        matches
+    }
+
+    fn skip_space(&mut self) -> Result<(), LuaError> {
+        loop {
+            while self.peek().is_some_and(|b| b.is_ascii_whitespace()) {
+                self.pos += 1;
+            }
+            if !self.src[self.pos..].starts_with(b"--") {
+                return Ok(());
+            }
+            self.pos += 2;
+            if let Some(level) = self.long_bracket_level() {
+                self.long_string(level)?;
+            } else {
+                while self.peek().is_some_and(|b| b != b'\n') {
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, LuaError> {
+        match self.peek() {
+            Some(b'{') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error("tables nested too deeply"));
+                }
+                self.depth += 1;
+                let table = self.table();
+                self.depth -= 1;
+                table.map(Value::Table)
+            }
+            Some(b'"' | b'\'') => self.string().map(Value::String),
+            Some(b'[') => match self.long_bracket_level() {
+                Some(level) => self.long_string(level).map(Value::String),
+                None => Err(self.error("expected a value")),
+            },
+            Some(b'-' | b'.' | b'0'..=b'9') => self.number().map(Value::Number),
+            _ if self.keyword("nil") => Ok(Value::Nil),
+            _ if self.keyword("true") => Ok(Value::Bool(true)),
+            _ if self.keyword("false") => Ok(Value::Bool(false)),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn table(&mut self) -> Result<Table, LuaError> {
+        self.expect(b'{', "expected '{'")?;
+        let mut table = Table::default();
+        let mut next_index = 1.0;
+        loop {
+            self.skip_space()?;
+            if self.eat(b'}') {
+                return Ok(table);
+            }
+            let key = if self.peek() == Some(b'[') && self.long_bracket_level().is_none() {
+                self.pos += 1;
+                self.skip_space()?;
+                let key = self.value()?;
+                self.skip_space()?;
+                self.expect(b']', "expected ']'")?;
+                self.skip_space()?;
+                self.expect(b'=', "expected '='")?;
+                Some(key)
+            } else {
+                self.name_before_equals().map(Value::String)
+            };
+            self.skip_space()?;
+            let value = self.value()?;
+            let key = key.unwrap_or_else(|| {
+                let key = Value::Number(next_index);
+                next_index += 1.0;
+                key
+            });
+            table.entries.push((key, value));
+            self.skip_space()?;
+            if !self.eat(b',') && !self.eat(b';') {
+                self.skip_space()?;
+                self.expect(b'}', "expected ',' or '}'")?;
+                return Ok(table);
+            }
+        }
+    }
+
+    /// An identifier key as in `{ name = value }`; leaves the input alone
+    /// when there isn't one.
+    fn name_before_equals(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self
+            .peek()
+            .is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+        {
+            return None;
+        }
+        while self
+            .peek()
+            .is_some_and(|b| b.is_ascii_alphanumeric() || b == b'_')
+        {
+            self.pos += 1;
+        }
+        let name = String::from_utf8_lossy(&self.src[start..self.pos]).into_owned();
+        let _ = self.skip_space();
+        if self.peek() == Some(b'=') && self.src.get(self.pos + 1) != Some(&b'=') {
+            self.pos += 1;
+            Some(name)
+        } else {
+            self.pos = start;
+            None
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, LuaError> {
+        let negative = self.eat(b'-');
+        let start = self.pos;
+        if self.src[self.pos..].starts_with(b"0x") || self.src[self.pos..].starts_with(b"0X") {
+            self.pos += 2;
+            let digits = self.pos;
+            while self.peek().is_some_and(|b| b.is_ascii_hexdigit()) {
+                self.pos += 1;
+            }
+            let hex = std::str::from_utf8(&self.src[digits..self.pos]).unwrap_or_default();
+            let n = i64::from_str_radix(hex, 16).map_err(|_| self.error("bad number"))? as f64;
+            return Ok(if negative { -n } else { n });
+        }
+        while self
+            .peek()
+            .is_some_and(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E'))
+        {
+            let exponent = matches!(self.peek(), Some(b'e' | b'E'));
+            self.pos += 1;
+            if exponent && !self.eat(b'-') {
+                self.eat(b'+');
+            }
+        }
+        let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap_or_default();
+        let n: f64 = text.parse().map_err(|_| self.error("bad number"))?;
+        Ok(if negative { -n } else { n })
+    }
+
+    fn string(&mut self) -> Result<String, LuaError> {
+        let quote = self.src[self.pos];
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            let Some(b) = self.peek() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match b {
+                _ if b == quote => break,
+                b'\n' => return Err(self.error("unterminated string")),
+                b'\\' => self.escape(&mut out)?,
+                _ => out.push(b),
+            }
+        }
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    fn escape(&mut self, out: &mut Vec<u8>) -> Result<(), LuaError> {
+        let Some(b) = self.peek() else {
+            return Err(self.error("unterminated string"));
+        };
+        self.pos += 1;
+        match b {
+            b'n' | b'\n' => out.push(b'\n'),
+            b't' => out.push(b'\t'),
+            b'r' => out.push(b'\r'),
+            b'a' => out.push(0x07),
+            b'b' => out.push(0x08),
+            b'f' => out.push(0x0c),
+            b'v' => out.push(0x0b),
+            b'\\' | b'"' | b'\'' => out.push(b),
+            b'z' => {
+                while self.peek().is_some_and(|b| b.is_ascii_whitespace()) {
+                    self.pos += 1;
+                }
+            }
+            b'x' => {
+                let hex = self
+                    .src
+                    .get(self.pos..self.pos + 2)
+                    .and_then(|h| std::str::from_utf8(h).ok())
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| self.error("bad escape"))?;
+                self.pos += 2;
+                out.push(hex);
+            }
+            b'u' => {
+                self.expect(b'{', "bad escape")?;
+                let start = self.pos;
+                while self.peek().is_some_and(|b| b.is_ascii_hexdigit()) {
+                    self.pos += 1;
+                }
+                let c = std::str::from_utf8(&self.src[start..self.pos])
+                    .ok()
+                    .and_then(|h| u32::from_str_radix(h, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error("bad escape"))?;
+                self.expect(b'}', "bad escape")?;
+                out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            b'0'..=b'9' => {
+                // Up to three decimal digits, as `string.format("%q")` writes
+                // control characters.
+                let mut n = u32::from(b - b'0');
+                for _ in 0..2 {
+                    match self.peek() {
+                        Some(d @ b'0'..=b'9') => {
+                            n = n * 10 + u32::from(d - b'0');
+                            self.pos += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                out.push(u8::try_from(n).map_err(|_| self.error("bad escape"))?);
+            }
+            _ => return Err(self.error("bad escape")),
+        }
+        Ok(())
+    }
+
+    /// Level of a long bracket (`[[`, `[==[`) starting here, if there is one.
+    fn long_bracket_level(&self) -> Option<usize> {
+        let rest = &self.src[self.pos..];
+        if rest.first() != Some(&b'[') {
+            return None;
+        }
+        let level = rest[1..].iter().take_while(|&&b| b == b'=').count();
+        (rest.get(level + 1) == Some(&b'[')).then_some(level)
+    }
+
+    fn long_string(&mut self, level: usize) -> Result<String, LuaError> {
+        self.pos += level + 2;
+        // A newline straight after the opening bracket isn't part of it.
+        self.eat(b'\r');
+        self.eat(b'\n');
+        let close = format!("]{}]", "=".repeat(level));
+        let rest = &self.src[self.pos..];
+        let end = rest
+            .windows(close.len())
+            .position(|w| w == close.as_bytes())
+            .ok_or_else(|| self.error("unterminated long string"))?;
+        let text = String::from_utf8_lossy(&rest[..end]).into_owned();
+        self.pos += end + close.len();
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn table(source: &str) -> Table {
+        match parse(source).unwrap() {
+            Value::Table(table) => table,
+            other => panic!("expected a table, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_literals() {
+        assert_eq!(parse("return nil").unwrap(), Value::Nil);
+        assert_eq!(parse("true").unwrap(), Value::Bool(true));
+        assert_eq!(parse("return false;").unwrap(), Value::Bool(false));
+        assert_eq!(parse("42").unwrap(), Value::Number(42.0));
+        assert_eq!(parse("-1.5e3").unwrap(), Value::Number(-1500.0));
+        assert_eq!(parse("0x1F").unwrap(), Value::Number(31.0));
+        assert_eq!(parse(".5").unwrap(), Value::Number(0.5));
+        assert!(parse("returned").is_err());
+    }
+
+    #[test]
+    fn parses_strings_and_escapes() {
+        assert_eq!(parse(r#""double""#).unwrap(), string("double"));
+        assert_eq!(parse("'single'").unwrap(), string("single"));
+        assert_eq!(
+            parse(r#""a\nb\tc\\d\"e\'f""#).unwrap(),
+            string("a\nb\tc\\d\"e'f")
+        );
+        assert_eq!(parse(r#""\65\066\x43""#).unwrap(), string("ABC"));
+        assert_eq!(parse(r#""caf\u{E9}""#).unwrap(), string("café"));
+        assert_eq!(parse("\"a\\z   \n  b\"").unwrap(), string("ab"));
+        assert_eq!(parse("\"line\\\nbreak\"").unwrap(), string("line\nbreak"));
+        assert_eq!(parse("'日本語'").unwrap(), string("日本語"));
+
+        assert!(parse(r#""unterminated"#).is_err());
+        assert!(parse("\"new\nline\"").is_err());
+        assert!(parse(r#""\q""#).is_err());
+        assert!(parse(r#""\256""#).is_err());
+    }
+
+    #[test]
+    fn parses_long_brackets() {
+        assert_eq!(parse("[[two\nlines]]").unwrap(), string("two\nlines"));
+        assert_eq!(
+            parse("[[\nskips the first newline]]").unwrap(),
+            string("skips the first newline")
+        );
+        assert_eq!(
+            parse("[==[has ]] and ]=] inside]==]").unwrap(),
+            string("has ]] and ]=] inside")
+        );
+        assert!(parse("[[unterminated").is_err());
+        assert!(parse("[=[mismatched]]").is_err());
+    }
+
+    #[test]
+    fn skips_comments() {
+        let source = "-- settings\nreturn { -- trailing\n  a = 1, --[[ long\ncomment ]] b = 2,\n--[==[\n]]\n]==]\n}\n-- done";
+        let t = table(source);
+        assert_eq!(t.get("a"), Some(&Value::Number(1.0)));
+        assert_eq!(t.get("b"), Some(&Value::Number(2.0)));
+        assert!(parse("-- only a comment").is_err());
+        assert!(parse("{ --[[ unterminated }").is_err());
+    }
+
+    #[test]
+    fn parses_table_keys() {
+        let t = table(
+            r#"{ "first", name = "n", ["quoted key"] = 1, [3] = "three", "second"; [ [[long]] ] = true, }"#,
+        );
+        assert_eq!(t.get("name"), Some(&string("n")));
+        assert_eq!(t.get("quoted key"), Some(&Value::Number(1.0)));
+        assert_eq!(t.get("long"), Some(&Value::Bool(true)));
+        assert_eq!(
+            t.sequence(),
+            [&string("first"), &string("second"), &string("three")]
+        );
+
+        let nested = table(r#"{ ["annotations"] = { [1] = { ["text"] = "x" } } }"#);
+        let first = nested.table("annotations").unwrap().sequence()[0];
+        assert_eq!(first.as_table().unwrap().str("text"), Some("x"));
+
+        // `==` is a comparison, not a key.
+        assert!(parse("{ a == 1 }").is_err());
+        assert!(parse("{ [1] 2 }").is_err());
+        assert!(parse("{ 1 2 }").is_err());
+        assert!(parse("{ 1,").is_err());
+    }
+
+    #[test]
+    fn rejects_trailing_input() {
+        assert!(parse("{} {}").is_err());
+        assert!(parse("return 1; 2").is_err());
+        assert!(parse("return {};  -- fine\n").is_ok());
+        let err = parse("{} x").unwrap_err();
+        assert_eq!(err.position, 3);
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let within = format!("{}{}", "{".repeat(MAX_DEPTH), "}".repeat(MAX_DEPTH));
+        assert!(parse(&within).is_ok());
+        let deeper = format!("{}{}", "{".repeat(MAX_DEPTH + 1), "}".repeat(MAX_DEPTH + 1));
+        assert!(parse(&deeper).is_err());
+        assert!(parse(&"{".repeat(1_000_000)).is_err());
+        assert!(parse(&"{[".repeat(1_000_000)).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+mod calibre;
+mod koreader;
+mod lua;
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::epub::{self, BookError, CfiLocation, EpubArchive};
+use crate::library::{now_millis, AnnotationError, Book, Library, NewAnnotation};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("Failed to read annotations: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not a Calibre annotations file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Not a KOReader sidecar file: {0}")]
+    Lua(#[from] lua::LuaError),
+    #[error(transparent)]
+    Book(#[from] BookError),
+    #[error(transparent)]
+    Annotation(#[from] AnnotationError),
+    #[error("Annotation store error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("No book in the library matches these annotations")]
+    NoMatchingBook,
+    #[error("These annotations don't say which book they belong to; open the book and import them from there")]
+    BookNotNamed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotationImport {
+    pub book: Book,
+    pub annotations: usize,
+    pub bookmarks: usize,
+    /// Entries the book already had, from an earlier import.
+    pub duplicates: usize,
+    /// Entries whose position couldn't be found in this copy of the book.
+    pub unplaced: usize,
+}
+
+/// A highlight, note or bookmark read from another reader's file.
+struct Entry {
+    kind: EntryKind,
+    /// Where the file puts it, if that could be mapped onto this book.
+    location: Option<CfiLocation>,
+    /// The highlighted text, which also corrects `location` when the other
+    /// reader counted text differently.
+    text: Option<String>,
+    /// A highlight's note, or a bookmark's name.
+    note: Option<String>,
+    color: Option<String>,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EntryKind {
+    Annotation,
+    Bookmark,
+}
+
+enum Source {
+    KoReader(koreader::Sidecar),
+    Calibre(calibre::Collection),
+}
+
+/// Imports a KOReader `metadata.epub.lua` sidecar or a Calibre annotations
+/// file into `book_id`, or without one, into the library book the file
+/// belongs to. Calibre files only say which book that is when they carry
+/// its hash, identifiers or title.
+pub fn import_file(
+    library: &Library,
+    path: &Path,
+    book_id: Option<&str>,
+) -> Result<AnnotationImport, ImportError> {
+    let contents = std::fs::read_to_string(path)?;
+    let source = if contents.trim_start().starts_with(['{', '[']) {
+        Source::Calibre(calibre::parse(&contents)?)
+    } else {
+        Source::KoReader(koreader::parse(&contents)?)
+    };
+    let book = match (book_id, &source) {
+        (Some(id), _) => library.get(id)?,
+        (None, Source::KoReader(sidecar)) => koreader::find_book(library, path, sidecar)?,
+        (None, Source::Calibre(collection)) => calibre::find_book(library, collection)?,
+    }
+    .ok_or(ImportError::NoMatchingBook)?;
+
+    let mut archive = EpubArchive::open(Path::new(&book.path))?;
+    let structure = epub::read_structure(&mut archive)?;
+    let entries = match &source {
+        Source::KoReader(sidecar) => sidecar.entries(&mut archive, &structure)?,
+        Source::Calibre(collection) => collection.entries(&mut archive, &structure)?,
+    };
+
+    let mut summary = AnnotationImport {
+        book,
+        annotations: 0,
+        bookmarks: 0,
+        duplicates: 0,
+        unplaced: 0,
+    };
+    for entry in entries {
+        let found = match (entry.kind, &entry.text) {
+            (EntryKind::Annotation, Some(text)) => {
+                epub::find_passage(&mut archive, &structure, text, entry.location)?
+            }
+            _ => None,
+        };
+        let Some(location) = found.or(entry.location) else {
+            summary.unplaced += 1;
+            continue;
+        };
+        let cfi = epub::cfi_at(
+            &mut archive,
+            &structure,
+            location.spine_index,
+            location.offset,
+            location.length,
+        )?;
+        let created_at = entry.created_at.unwrap_or_else(now_millis);
+        let updated_at = entry.updated_at.unwrap_or(created_at);
+        let added = match entry.kind {
+            EntryKind::Annotation => {
+                let new = NewAnnotation {
+                    book_id: summary.book.id.clone(),
+                    cfi: cfi.to_string(),
+                    text: entry.text,
+                    note: entry.note,
+                    color: entry.color,
+                    tags: Vec::new(),
+                };
+                let added = library.import_annotation(new, created_at, updated_at)?;
+                summary.annotations += usize::from(added.is_some());
+                added.is_some()
+            }
+            EntryKind::Bookmark => {
+                let passage = epub::passage_at(&mut archive, &structure, &cfi)?;
+                let added = library.import_bookmark(
+                    &summary.book.id,
+                    &cfi,
+                    entry.note.as_deref(),
+                    &passage,
+                    created_at,
+                )?;
+                summary.bookmarks += usize::from(added.is_some());
+                added.is_some()
+            }
+        };
+        summary.duplicates += usize::from(!added);
+    }
+    Ok(summary)
+}
+
+/// The span between two positions, or just the first when they aren't in
+/// the same chapter and order.
+fn span(start: Option<CfiLocation>, end: Option<CfiLocation>) -> Option<CfiLocation> {
+    let start = start?;
+    Some(match end {
+        Some(end) if end.spine_index == start.spine_index && end.offset >= start.offset => {
+            CfiLocation {
+                length: end.offset - start.offset,
+                ..start
+            }
+        }
+        _ => start,
+    })
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::epub::{self, BookError, BookStructure, EpubArchive, WordIndex};
+
+/// An archive kept open between commands so chapters can be read on demand.
+pub struct OpenBook {
+    pub archive: EpubArchive,
+    pub structure: BookStructure,
+    pub words: WordIndex,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenedBook {
+    pub id: String,
+    pub structure: BookStructure,
+}
+
+#[derive(Default)]
+pub struct OpenBooks {
+    books: Mutex<HashMap<String, Arc<Mutex<OpenBook>>>>,
+}
+
+impl OpenBooks {
+    /// Opens library book `id`, whose file is at `path`, reusing the cached
+    /// handle if it is already open.
+    pub fn open(&self, id: &str, path: &Path) -> Result<OpenedBook, BookError> {
+        let id = id.to_string();
+        if let Some(book) = self.get(&id) {
+            let structure = book.lock().unwrap().structure.clone();
+            return Ok(OpenedBook { id, structure });
+        }
+
+        let mut archive = EpubArchive::open(path)?;
+        let structure = epub::read_structure(&mut archive)?;
+        let book = OpenBook {
+            archive,
+            structure: structure.clone(),
+            words: WordIndex::default(),
+        };
+        self.books
+            .lock()
+            .unwrap()
+            .insert(id.clone(), Arc::new(Mutex::new(book)));
+        Ok(OpenedBook { id, structure })
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Mutex<OpenBook>>> {
+        self.books.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn close(&self, id: &str) {
+        self.books.lock().unwrap().remove(id);
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use serde::Deserialize;
+
+use crate::epub::{self, BookError};
+
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CoverError {
+    #[error(transparent)]
+    Book(#[from] BookError),
+    #[error("Failed to encode thumbnail: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Failed to write thumbnail: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ThumbnailSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    const ALL: [Self; 3] = [Self::Small, Self::Medium, Self::Large];
+
+    /// Width in pixels; the height follows the cover's aspect ratio.
+    fn width(self) -> u32 {
+        match self {
+            Self::Small => 120,
+            Self::Medium => 300,
+            Self::Large => 600,
+        }
+    }
+}
+
+/// Cover thumbnails on disk, keyed by the book's content hash so copies of
+/// a book share them and moving a file doesn't invalidate anything.
+pub struct CoverCache {
+    dir: PathBuf,
+}
+
+impl CoverCache {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Path of the `size` thumbnail for the book with content hash `hash`,
+    /// generating every size from the file at `book_path` on first use.
+    /// `None` means the book has no cover we can decode.
+    pub fn thumbnail(
+        &self,
+        book_path: &Path,
+        hash: &str,
+        size: ThumbnailSize,
+    ) -> Result<Option<PathBuf>, CoverError> {
+        let path = self.path_for(hash, size);
+        if path.exists() {
+            return Ok(Some(path));
+        }
+        // Remember books without a usable cover so they aren't reopened
+        // every time the library is shown.
+        let no_cover = self.dir.join(format!("{hash}.none"));
+        if no_cover.exists() {
+            return Ok(None);
+        }
+
+        let image =
+            epub::read_cover(book_path)?.and_then(|bytes| image::load_from_memory(&bytes).ok());
+        let Some(image) = image else {
+            File::create(&no_cover)?;
+            return Ok(None);
+        };
+        for size in ThumbnailSize::ALL {
+            let thumbnail = if image.width() > size.width() {
+                image.resize(size.width(), u32::MAX, FilterType::Lanczos3)
+            } else {
+                image.clone()
+            };
+            // Concurrent requests for the same book may race here; writing
+            // to a unique name and renaming keeps readers from seeing a
+            // partial file.
+            let target = self.path_for(hash, size);
+            let temp = self.dir.join(format!("{}.tmp", uuid::Uuid::new_v4()));
+            let mut out = BufWriter::new(File::create(&temp)?);
+            JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)
+                .encode_image(&thumbnail.to_rgb8())?;
+            out.flush()?;
+            drop(out);
+            fs::rename(&temp, &target)?;
+        }
+        Ok(Some(path))
+    }
+
+    fn path_for(&self, hash: &str, size: ThumbnailSize) -> PathBuf {
+        self.dir.join(format!("{hash}-{}.jpg", size.width()))
+    }
+}
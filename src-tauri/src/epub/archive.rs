@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use super::BookError;
+
+/// The most an entry may decompress to. Well above any real chapter, image
+/// or font, but it stops a crafted archive from exhausting memory.
+const MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The most reserved up front for an entry, whatever size its header claims.
+const MAX_PREALLOCATION: u64 = 8 * 1024 * 1024;
+
+pub struct EpubArchive {
+    zip: ZipArchive<BufReader<File>>,
+}
+
+impl EpubArchive {
+    pub fn open(path: &Path) -> Result<Self, BookError> {
+        let file = File::open(path)?;
+        let zip = ZipArchive::new(BufReader::new(file))?;
+        Ok(Self { zip })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.zip.index_for_name(name).is_some()
+    }
+
+    /// CRC-32 of an entry's contents, read from the zip directory.
+    pub fn crc32(&mut self, name: &str) -> Option<u32> {
+        self.zip.by_name(name).ok().map(|entry| entry.crc32())
+    }
+
+    /// Reads an entry fully, returning `None` if it does not exist. Entries
+    /// over [`MAX_ENTRY_SIZE`] are an error.
+    pub fn read_bytes(&mut self, name: &str) -> Result<Option<Vec<u8>>, BookError> {
+        let entry = match self.zip.by_name(name) {
+            Ok(entry) => entry,
+            Err(ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut buf = Vec::with_capacity(entry.size().min(MAX_PREALLOCATION) as usize);
+        // The size in the header can't be trusted, so the limit is on what
+        // actually comes out.
+        entry.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut buf)?;
+        if buf.len() as u64 > MAX_ENTRY_SIZE {
+            return Err(BookError::EntryTooLarge(name.to_string()));
+        }
+        Ok(Some(buf))
+    }
+
+    /// Reads an entry as UTF-8 text, dropping a leading byte-order mark.
+    pub fn read_text(&mut self, name: &str) -> Result<Option<String>, BookError> {
+        Ok(self.read_bytes(name)?.map(|bytes| {
+            let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+            String::from_utf8_lossy(bytes).into_owned()
+        }))
+    }
+}
@@ -0,0 +1,868 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+use std::sync::LazyLock;
+
+use kuchikiki::NodeRef;
+use regex::Regex;
+use serde::Serialize;
+
+use super::content::{body_of, parse_html};
+use super::sanitize::is_dropped;
+use super::{BookError, BookStructure, EpubArchive};
+
+/// Characters that must be escaped with `^` inside an assertion.
+const SPECIAL: &[char] = &['^', '[', ']', '(', ')', ',', ';', '='];
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid CFI {cfi:?}: {reason}")]
+pub struct CfiError {
+    cfi: String,
+    reason: &'static str,
+}
+
+/// One `/N` step. Even indices select the (N/2)th child element; odd ones
+/// the run of text before, between or after the child elements.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub index: usize,
+    /// `[id]` assertion, used to find the element again if the document
+    /// changed since the CFI was made.
+    pub id: Option<String>,
+    /// Whether the step follows a `!`, crossing from the element the
+    /// previous step selected into the document it references.
+    pub indirect: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CfiPath {
+    pub steps: Vec<Step>,
+    /// Offset into the text the last step selects, in UTF-16 code units.
+    pub offset: Option<usize>,
+    /// Temporal (`~`) and spatial (`@`) offsets into media, kept verbatim.
+    pub media: Option<String>,
+    /// Text location assertion (`[before,after;s=b]`), kept escaped.
+    pub text_assertion: Option<String>,
+}
+
+/// A parsed `epubcfi(...)`. A range shares a parent path and adds start and
+/// end paths relative to it.
+///
+/// CFIs are ordered, and compare equal, by the position they point at;
+/// assertions don't take part.
+#[derive(Debug, Clone)]
+pub struct Cfi {
+    pub path: CfiPath,
+    pub range: Option<(CfiPath, CfiPath)>,
+}
+
+/// Where a CFI points, in the coordinates the reader and search use.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CfiLocation {
+    pub spine_index: usize,
+    /// Offset into the chapter's text content, in UTF-16 code units as the
+    /// DOM counts them.
+    pub offset: usize,
+    /// Length of a range in the same units; zero for a point.
+    pub length: usize,
+}
+
+impl Cfi {
+    /// The whole path to the start of the location.
+    pub fn start(&self) -> CfiPath {
+        match &self.range {
+            Some((start, _)) => self.path.join(start),
+            None => self.path.clone(),
+        }
+    }
+
+    /// The whole path to the end of the location; the start for a point.
+    pub fn end(&self) -> CfiPath {
+        match &self.range {
+            Some((_, end)) => self.path.join(end),
+            None => self.path.clone(),
+        }
+    }
+}
+
+impl CfiPath {
+    fn join(&self, local: &CfiPath) -> CfiPath {
+        CfiPath {
+            steps: self.steps.iter().chain(&local.steps).cloned().collect(),
+            ..local.clone()
+        }
+    }
+
+    /// Step indices, then the offset: the position in document order.
+    fn position(&self) -> (Vec<usize>, usize) {
+        let indices = self.steps.iter().map(|s| s.index).collect();
+        (indices, self.offset.unwrap_or(0))
+    }
+}
+
+impl Ord for Cfi {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let start = self.start().position().cmp(&other.start().position());
+        start.then_with(|| self.end().position().cmp(&other.end().position()))
+    }
+}
+
+impl PartialOrd for Cfi {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Cfi {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Cfi {}
+
+impl FromStr for Cfi {
+    type Err = CfiError;
+
+    fn from_str(s: &str) -> Result<Self, CfiError> {
+        let error = |reason| CfiError {
+            cfi: s.to_string(),
+            reason,
+        };
+        let inner = s
+            .trim()
+            .strip_prefix("epubcfi(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .ok_or_else(|| error("expected epubcfi(...)"))?;
+        match split_range(inner).as_slice() {
+            [path] => Ok(Cfi {
+                path: parse_path(path).map_err(error)?,
+                range: None,
+            }),
+            [parent, start, end] => Ok(Cfi {
+                path: parse_path(parent).map_err(error)?,
+                range: Some((
+                    parse_path(start).map_err(error)?,
+                    parse_path(end).map_err(error)?,
+                )),
+            }),
+            _ => Err(error("a range needs a start and an end")),
+        }
+    }
+}
+
+impl fmt::Display for CfiPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in &self.steps {
+            if step.indirect {
+                f.write_str("!")?;
+            }
+            write!(f, "/{}", step.index)?;
+            if let Some(id) = &step.id {
+                write!(f, "[{}]", escape(id))?;
+            }
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ":{offset}")?;
+        }
+        if let Some(media) = &self.media {
+            f.write_str(media)?;
+        }
+        if let Some(assertion) = &self.text_assertion {
+            write!(f, "[{assertion}]")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cfi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "epubcfi({}", self.path)?;
+        if let Some((start, end)) = &self.range {
+            write!(f, ",{start},{end}")?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Splits on the commas that separate a range's parts, skipping escaped
+/// ones and those inside assertions.
+fn split_range(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut part_start = 0;
+    let mut in_assertion = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '^' => escaped = true,
+            '[' => in_assertion = true,
+            ']' => in_assertion = false,
+            ',' if !in_assertion => {
+                parts.push(&s[part_start..i]);
+                part_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[part_start..]);
+    parts
+}
+
+fn parse_path(s: &str) -> Result<CfiPath, &'static str> {
+    let mut chars = s.chars().peekable();
+    let mut path = CfiPath::default();
+    let mut indirect = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '!' if !indirect && !path.steps.is_empty() => indirect = true,
+            '/' => {
+                if path.offset.is_some() || path.media.is_some() {
+                    return Err("step after an offset");
+                }
+                let index = number(&mut chars)
+                    .filter(|&i| i > 0)
+                    .ok_or("expected a step index")?;
+                let id = match chars.next_if_eq(&'[') {
+                    Some(_) => Some(unescape(&assertion(&mut chars)?)),
+                    None => None,
+                };
+                path.steps.push(Step {
+                    index,
+                    id,
+                    indirect,
+                });
+                indirect = false;
+            }
+            ':' if path.offset.is_none() && path.media.is_none() => {
+                path.offset = Some(number(&mut chars).ok_or("expected a character offset")?);
+            }
+            '~' | '@' if path.media.is_none() => {
+                let mut media = c.to_string();
+                while let Some(c) = chars.next_if(|&c| c != '[') {
+                    media.push(c);
+                }
+                path.media = Some(media);
+            }
+            '[' if path.offset.is_some() || path.media.is_some() => {
+                path.text_assertion = Some(assertion(&mut chars)?);
+            }
+            _ => return Err("unexpected character"),
+        }
+    }
+    if indirect {
+        return Err("'!' must be followed by a step");
+    }
+    Ok(path)
+}
+
+fn number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits.parse().ok()
+}
+
+/// Reads up to the closing `]`, leaving escapes in place.
+fn assertion(chars: &mut Peekable<Chars>) -> Result<String, &'static str> {
+    let mut raw = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            ']' => return Ok(raw),
+            '^' => {
+                raw.push(c);
+                raw.push(chars.next().ok_or("unterminated assertion")?);
+            }
+            c => raw.push(c),
+        }
+    }
+    Err("unterminated assertion")
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if SPECIAL.contains(&c) {
+            out.push('^');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '^' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A content document as the reader shows it, along with the elements the
+/// HTML parser added that aren't in the XHTML, like an implied `<head>` or
+/// `<tbody>`. Steps are counted as if those weren't there, so CFIs match
+/// the ones other readers make from the XML.
+struct Chapter {
+    doc: NodeRef,
+    implied: Vec<NodeRef>,
+}
+
+impl Chapter {
+    fn parse(raw: &str) -> Self {
+        // Start tags in the source, in document order, skipping comments,
+        // CDATA, the doctype and processing instructions.
+        static START_TAG: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"(?s)<!--.*?-->|<!\[CDATA\[.*?\]\]>|<[!?][^>]*>|<([A-Za-z][\w:.-]*)")
+                .unwrap()
+        });
+        let mut source = START_TAG
+            .captures_iter(raw)
+            .filter_map(|c| c.get(1))
+            .map(|m| m.as_str().to_ascii_lowercase())
+            .peekable();
+        let doc = parse_html(raw);
+        // The parser only adds elements, so any that doesn't match the next
+        // start tag is one of its own.
+        let implied = doc
+            .descendants()
+            .filter(|n| {
+                n.as_element().is_some_and(|el| {
+                    let name = str::to_ascii_lowercase(&el.name.local);
+                    source.next_if_eq(&name).is_none()
+                })
+            })
+            .collect();
+        Self { doc, implied }
+    }
+
+    /// Children of `node` as the XHTML has them, with added elements
+    /// replaced by their own children.
+    fn children(&self, node: &NodeRef) -> Vec<NodeRef> {
+        let mut out = Vec::new();
+        for child in node.children() {
+            if self.implied.contains(&child) {
+                out.extend(self.children(&child));
+            } else {
+                out.push(child);
+            }
+        }
+        out
+    }
+
+    /// Parent of `node` as the XHTML has it.
+    fn parent(&self, node: &NodeRef) -> Option<NodeRef> {
+        node.ancestors().find(|a| !self.implied.contains(a))
+    }
+
+    /// Siblings before `node` as the XHTML has them, nearest last.
+    fn preceding_siblings(&self, node: &NodeRef) -> Vec<NodeRef> {
+        let Some(parent) = self.parent(node) else {
+            return Vec::new();
+        };
+        let mut siblings = self.children(&parent);
+        let at = siblings.iter().position(|s| s == node).unwrap_or(0);
+        siblings.truncate(at);
+        siblings
+    }
+}
+
+/// CFI of a position in a chapter, given as an offset into its text content
+/// (as in [`CfiLocation`]), or of a range when `length` isn't zero.
+pub fn cfi_at(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    index: usize,
+    offset: usize,
+    length: usize,
+) -> Result<Cfi, BookError> {
+    let item = structure
+        .spine
+        .get(index)
+        .ok_or(BookError::SpineIndexOutOfRange(index))?;
+    let raw = archive
+        .read_text(&item.full_path)?
+        .ok_or_else(|| BookError::MissingEntry(item.full_path.clone()))?;
+    let chapter = Chapter::parse(&raw);
+
+    let path_to = |offset, at_end| {
+        let mut steps = vec![
+            Step {
+                index: structure.spine_step,
+                id: None,
+                indirect: false,
+            },
+            Step {
+                index: (index + 1) * 2,
+                id: None,
+                indirect: false,
+            },
+        ];
+        let (mut content, offset) = content_path(&chapter, offset, at_end);
+        if let Some(first) = content.first_mut() {
+            first.indirect = true;
+        }
+        steps.append(&mut content);
+        CfiPath {
+            steps,
+            offset,
+            ..CfiPath::default()
+        }
+    };
+    let start = path_to(offset, false);
+    if length == 0 {
+        return Ok(Cfi {
+            path: start,
+            range: None,
+        });
+    }
+    let end = path_to(offset + length, true);
+    Ok(range_between(start, end))
+}
+
+/// A range CFI from two whole paths. The parent path is what both ends
+/// share, leaving each at least a step, and always keeps the step into the
+/// content document: a local path can't start with `!`. Paths from
+/// [`content_path`] go below the content document's root element, so both
+/// can hold.
+fn range_between(start: CfiPath, end: CfiPath) -> Cfi {
+    let common = start
+        .steps
+        .iter()
+        .zip(&end.steps)
+        .take_while(|(a, b)| a.index == b.index && a.indirect == b.indirect)
+        .count();
+    let leaves_a_step = common.min(start.steps.len().min(end.steps.len()) - 1);
+    let shared = leaves_a_step.max(common.min(3));
+    let local = |path: &CfiPath| CfiPath {
+        steps: path.steps[shared..].to_vec(),
+        ..path.clone()
+    };
+    Cfi {
+        path: CfiPath {
+            steps: start.steps[..shared].to_vec(),
+            ..CfiPath::default()
+        },
+        range: Some((local(&start), local(&end))),
+    }
+}
+
+/// Resolves a CFI to a chapter and a span of its text. Paths that no longer
+/// match the document resolve as deep as they can.
+pub fn resolve_cfi(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    cfi: &Cfi,
+) -> Result<CfiLocation, BookError> {
+    let start = cfi.start();
+    let (index, steps) = spine_target(&start).ok_or_else(|| CfiError {
+        cfi: cfi.to_string(),
+        reason: "does not point at a spine item",
+    })?;
+    let item = structure
+        .spine
+        .get(index)
+        .ok_or(BookError::SpineIndexOutOfRange(index))?;
+    let raw = archive
+        .read_text(&item.full_path)?
+        .ok_or_else(|| BookError::MissingEntry(item.full_path.clone()))?;
+    let chapter = Chapter::parse(&raw);
+    let offset = content_offset(&chapter, steps, start.offset.unwrap_or(0));
+
+    let mut length = 0;
+    if cfi.range.is_some() {
+        let end = cfi.end();
+        // A range running into a later chapter is cut at the end of this one.
+        let end_offset = match spine_target(&end) {
+            Some((end_index, steps)) if end_index == index => {
+                content_offset(&chapter, steps, end.offset.unwrap_or(0))
+            }
+            _ => {
+                let mut texts = Vec::new();
+                rendered_text(&body_of(&chapter.doc), &mut texts);
+                texts.iter().map(text_len).sum()
+            }
+        };
+        length = end_offset.saturating_sub(offset);
+    }
+    Ok(CfiLocation {
+        spine_index: index,
+        offset,
+        length,
+    })
+}
+
+/// Spine index a path points into, and the steps within that document.
+fn spine_target(path: &CfiPath) -> Option<(usize, &[Step])> {
+    let split = path
+        .steps
+        .iter()
+        .position(|s| s.indirect)
+        .unwrap_or(path.steps.len());
+    let (package, content) = path.steps.split_at(split);
+    // The package steps select <spine>, then an <itemref>.
+    match package {
+        [_, itemref] if itemref.index % 2 == 0 => Some((itemref.index / 2 - 1, content)),
+        _ => None,
+    }
+}
+
+/// Steps from the root element to the text at `offset`, and the character
+/// offset within that text. On a boundary between two text nodes, `at_end`
+/// picks the end of the first rather than the start of the second, so that
+/// a range doesn't end in the next paragraph. A chapter without text gets
+/// the empty run at the start of its body.
+fn content_path(chapter: &Chapter, offset: usize, at_end: bool) -> (Vec<Step>, Option<usize>) {
+    let body = body_of(&chapter.doc);
+    let mut texts = Vec::new();
+    rendered_text(&body, &mut texts);
+
+    let mut remaining = offset;
+    let mut target = None;
+    for text in &texts {
+        let len = text_len(text);
+        if remaining < len || (at_end && remaining == len && len > 0) {
+            target = Some((text, remaining));
+            break;
+        }
+        remaining -= len;
+    }
+    // Past the end: clamp to the end of the last text.
+    let target = target.or_else(|| texts.last().map(|t| (t, text_len(t))));
+    let Some((text, local)) = target else {
+        let mut steps = element_steps(chapter, &body);
+        steps.push(Step {
+            index: 1,
+            id: None,
+            indirect: false,
+        });
+        return (steps, Some(0));
+    };
+
+    let parent = chapter.parent(text).expect("text nodes have a parent");
+    let mut steps = element_steps(chapter, &parent);
+    let siblings = chapter.preceding_siblings(text);
+    let elements_before = siblings.iter().filter(|s| s.as_element().is_some()).count();
+    // A text step covers every text node between two elements.
+    let run_before: usize = siblings
+        .iter()
+        .rev()
+        .take_while(|s| s.as_element().is_none())
+        .map(text_len)
+        .sum();
+    steps.push(Step {
+        index: elements_before * 2 + 1,
+        id: None,
+        indirect: false,
+    });
+    (steps, Some(run_before + local))
+}
+
+fn element_steps(chapter: &Chapter, node: &NodeRef) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let mut node = node.clone();
+    // The root element itself has no step; stop once the parent is the
+    // document node.
+    while let Some(parent) = chapter.parent(&node).filter(|p| p.as_element().is_some()) {
+        let position = chapter
+            .preceding_siblings(&node)
+            .iter()
+            .filter(|s| s.as_element().is_some())
+            .count()
+            + 1;
+        steps.push(Step {
+            index: position * 2,
+            id: element_id(&node),
+            indirect: false,
+        });
+        node = parent;
+    }
+    steps.reverse();
+    steps
+}
+
+fn content_offset(chapter: &Chapter, steps: &[Step], offset: usize) -> usize {
+    let doc = &chapter.doc;
+    let body = body_of(doc);
+    let Some(mut node) = chapter
+        .children(doc)
+        .into_iter()
+        .find(|n| n.as_element().is_some())
+    else {
+        return 0;
+    };
+    for step in steps {
+        if step.index % 2 == 1 {
+            return text_run_offset(chapter, &body, &node, step.index / 2, offset);
+        }
+        let child = chapter
+            .children(&node)
+            .into_iter()
+            .filter(|c| c.as_element().is_some())
+            .nth(step.index / 2 - 1);
+        let matches = |c: &NodeRef| step.id.is_none() || element_id(c) == step.id;
+        node = match child {
+            Some(child) if matches(&child) => child,
+            _ => match step.id.as_deref().and_then(|id| by_id(doc, id)).or(child) {
+                Some(found) => found,
+                None => break,
+            },
+        };
+    }
+    offset_before(&body, &node)
+}
+
+/// Offset of a position `offset` characters into the `run`th text run of
+/// `parent`, the one after its `run`th child element.
+fn text_run_offset(
+    chapter: &Chapter,
+    body: &NodeRef,
+    parent: &NodeRef,
+    run: usize,
+    offset: usize,
+) -> usize {
+    let mut elements = 0;
+    let mut remaining = offset;
+    for child in chapter.children(parent) {
+        if child.as_element().is_some() {
+            if elements == run {
+                // Past the end of the run.
+                return offset_before(body, &child);
+            }
+            elements += 1;
+        } else if elements == run && child.as_text().is_some() {
+            let len = text_len(&child);
+            if remaining <= len {
+                return offset_before(body, &child) + remaining;
+            }
+            remaining -= len;
+        }
+    }
+    let mut inside = Vec::new();
+    rendered_text(parent, &mut inside);
+    offset_before(body, parent) + inside.iter().map(text_len).sum::<usize>()
+}
+
+/// Length of the rendered text before `target` in document order; zero for
+/// nodes outside the body.
+pub(super) fn offset_before(body: &NodeRef, target: &NodeRef) -> usize {
+    fn walk(node: &NodeRef, target: &NodeRef, count: &mut usize) -> bool {
+        if node == target {
+            return true;
+        }
+        if node.as_text().is_some() {
+            *count += text_len(node);
+            return false;
+        }
+        if is_dropped(node) {
+            return false;
+        }
+        node.children().any(|child| walk(&child, target, count))
+    }
+    let mut count = 0;
+    if walk(body, target, &mut count) {
+        count
+    } else {
+        0
+    }
+}
+
+/// Text nodes the reader renders, in document order. Scripts, style sheets
+/// and the like are sanitized away before a chapter is shown, so their text
+/// doesn't count.
+fn rendered_text(node: &NodeRef, out: &mut Vec<NodeRef>) {
+    for child in node.children() {
+        if child.as_text().is_some() {
+            out.push(child);
+        } else if !is_dropped(&child) {
+            rendered_text(&child, out);
+        }
+    }
+}
+
+fn text_len(node: &NodeRef) -> usize {
+    node.as_text()
+        .map_or(0, |t| t.borrow().encode_utf16().count())
+}
+
+fn element_id(node: &NodeRef) -> Option<String> {
+    let el = node.as_element()?;
+    let attrs = el.attributes.borrow();
+    attrs.get("id").map(str::to_string)
+}
+
+pub(super) fn by_id(doc: &NodeRef, id: &str) -> Option<NodeRef> {
+    doc.descendants()
+        .find(|n| element_id(n).as_deref() == Some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(cfi: &str) -> Cfi {
+        let parsed: Cfi = cfi.parse().unwrap();
+        assert_eq!(parsed.to_string(), cfi);
+        parsed
+    }
+
+    fn path(s: &str) -> CfiPath {
+        parse_path(s).unwrap()
+    }
+
+    #[test]
+    fn points_round_trip() {
+        let cfi = round_trip("epubcfi(/6/4!/4/10/3:12)");
+        let steps: Vec<_> = cfi
+            .path
+            .steps
+            .iter()
+            .map(|s| (s.index, s.indirect))
+            .collect();
+        assert_eq!(
+            steps,
+            [(6, false), (4, false), (4, true), (10, false), (3, false)]
+        );
+        assert_eq!(cfi.path.offset, Some(12));
+        assert!(cfi.range.is_none());
+
+        round_trip("epubcfi(/6/4!/4)");
+        round_trip("epubcfi(/6/4!/4/2:0)");
+        round_trip("epubcfi(/6/4!/4/2/1:3~1.5@20:30)");
+    }
+
+    #[test]
+    fn ranges_round_trip() {
+        let cfi = round_trip("epubcfi(/6/4!/4/10,/1:5,/3:2)");
+        let (start, end) = cfi.range.as_ref().unwrap();
+        assert_eq!(start.offset, Some(5));
+        assert_eq!(end.offset, Some(2));
+        assert_eq!(cfi.start().steps.len(), 5);
+        assert_eq!(cfi.end().to_string(), "/6/4!/4/10/3:2");
+
+        round_trip("epubcfi(/6/4!/4,/2/1:0,:7)");
+    }
+
+    #[test]
+    fn assertions_round_trip() {
+        let cfi =
+            round_trip("epubcfi(/6/4[chap01ref]!/4[body01]/10[para^[5^]]/3:10[yyy,xx^,z;s=b])");
+        let ids: Vec<_> = cfi.path.steps.iter().map(|s| s.id.as_deref()).collect();
+        assert_eq!(
+            ids,
+            [
+                None,
+                Some("chap01ref"),
+                Some("body01"),
+                Some("para[5]"),
+                None
+            ]
+        );
+        assert_eq!(cfi.path.text_assertion.as_deref(), Some("yyy,xx^,z;s=b"));
+
+        round_trip("epubcfi(/6/4!/4/2[a^,b],/1:0[x],/1:4)");
+    }
+
+    #[test]
+    fn rejects_malformed_cfis() {
+        for bad in [
+            "/6/4!/4",
+            "epubcfi()x",
+            "epubcfi(/0)",
+            "epubcfi(!/4)",
+            "epubcfi(/6/4!)",
+            "epubcfi(/6/4!!/4)",
+            "epubcfi(/6:3/4)",
+            "epubcfi(/6/4[open)",
+            "epubcfi(/6,/2)",
+            "epubcfi(/6,/2,/4,/6)",
+        ] {
+            assert!(bad.parse::<Cfi>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn ordered_by_position() {
+        let a: Cfi = "epubcfi(/6/4!/4/2/1:3)".parse().unwrap();
+        let b: Cfi = "epubcfi(/6/4!/4/2/1:10)".parse().unwrap();
+        let c: Cfi = "epubcfi(/6/6!/4/2/1:0)".parse().unwrap();
+        let a_with_id: Cfi = "epubcfi(/6/4[x]!/4/2/1:3)".parse().unwrap();
+        assert!(a < b && b < c);
+        assert_eq!(a, a_with_id);
+    }
+
+    #[test]
+    fn ranges_keep_the_content_step_in_the_parent() {
+        // An end on an ancestor of the start still keeps a step of its own.
+        let cfi = range_between(path("/6/4!/4/2/1:5"), path("/6/4!/4/2"));
+        assert_eq!(cfi.to_string(), "epubcfi(/6/4!/4,/2/1:5,/2)");
+        let parsed: Cfi = cfi.to_string().parse().unwrap();
+        assert_eq!(parsed.start().to_string(), "/6/4!/4/2/1:5");
+        assert_eq!(parsed.end().to_string(), "/6/4!/4/2");
+
+        let cfi = range_between(path("/6/4!/4/2/1:5"), path("/6/4!/4/2/1:9"));
+        assert_eq!(cfi.to_string(), "epubcfi(/6/4!/4/2,/1:5,/1:9)");
+        let cfi = range_between(path("/6/4!/4/2/1:5"), path("/6/4!/4/6/1:2"));
+        assert_eq!(cfi.to_string(), "epubcfi(/6/4!/4,/2/1:5,/6/1:2)");
+    }
+
+    fn steps(chapter: &Chapter, offset: usize) -> String {
+        let (steps, offset) = content_path(chapter, offset, false);
+        let path = CfiPath {
+            steps,
+            offset,
+            ..CfiPath::default()
+        };
+        path.to_string()
+    }
+
+    #[test]
+    fn empty_chapters_get_a_text_step() {
+        let chapter = Chapter::parse("<html><head></head><body><img src=\"a.png\"/></body></html>");
+        assert_eq!(steps(&chapter, 0), "/4/1:0");
+        let start = path(&format!("/6/4!{}", steps(&chapter, 0)));
+        let cfi = range_between(start.clone(), start);
+        assert_eq!(cfi.to_string(), "epubcfi(/6/4!/4,/1:0,/1:0)");
+    }
+
+    #[test]
+    fn steps_skip_elements_the_parser_added() {
+        // No <head> or <tbody> in the XHTML, so none in the steps.
+        let chapter = Chapter::parse(
+            "<?xml version=\"1.0\"?><!DOCTYPE html><html><!-- <div> --><body>\
+             <table><tr><td>ab</td></tr></table><p>cd</p></body></html>",
+        );
+        assert_eq!(chapter.implied.len(), 2);
+        assert_eq!(steps(&chapter, 1), "/2/2/2/2/1:1");
+        assert_eq!(steps(&chapter, 3), "/2/4/1:1");
+
+        let chapter = Chapter::parse(
+            "<html><head><title>t</title></head><body>\
+             <table><tbody><tr><td>ab</td></tr></tbody></table><p>cd</p></body></html>",
+        );
+        assert!(chapter.implied.is_empty());
+        assert_eq!(steps(&chapter, 1), "/4/2/2/2/2/1:1");
+        assert_eq!(steps(&chapter, 3), "/4/4/1:1");
+    }
+
+    #[test]
+    fn content_paths_resolve_back_to_their_offset() {
+        let chapter = Chapter::parse(
+            "<html><body><p>one <b>two</b> three</p><table><tr><td>four</td></tr></table></body></html>",
+        );
+        for offset in 0..17 {
+            let (steps, local) = content_path(&chapter, offset, false);
+            assert_eq!(
+                content_offset(&chapter, &steps, local.unwrap()),
+                offset,
+                "{offset}"
+            );
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::LazyLock;
+
+use kuchikiki::traits::TendrilSink;
+use kuchikiki::NodeRef;
+use regex::Regex;
+use serde::Serialize;
+
+use super::css::chapter_styles;
+use super::sanitize::sanitize;
+use super::{encode_path, resolve_href, BookError, BookStructure, EpubArchive};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Chapter {
+    pub index: usize,
+    pub id: String,
+    pub full_path: String,
+    pub html: String,
+    /// The chapter's own style sheets, filtered and scoped to the reader's
+    /// chapter container. Empty if it has none.
+    pub styles: String,
+}
+
+pub fn load_chapter(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    index: usize,
+    resource_base: &str,
+) -> Result<Chapter, BookError> {
+    let item = structure
+        .spine
+        .get(index)
+        .ok_or(BookError::SpineIndexOutOfRange(index))?;
+    let raw = archive
+        .read_text(&item.full_path)?
+        .ok_or_else(|| BookError::MissingEntry(item.full_path.clone()))?;
+
+    let doc = parse_html(&raw);
+    // Sanitizing removes the `<link>` and `<style>` elements.
+    let styles = chapter_styles(archive, &doc, &item.full_path, resource_base);
+    sanitize(&doc);
+    rewrite_resources(&doc, resource_base, &item.full_path);
+
+    Ok(Chapter {
+        index,
+        id: item.id.clone(),
+        full_path: item.full_path.clone(),
+        html: inner_html(&body_of(&doc)),
+        styles,
+    })
+}
+
+/// Marks block boundaries in [`document_text`], so that words in adjacent
+/// paragraphs don't run together. The DOM has no character there, so these
+/// must be skipped when counting offsets.
+pub const BLOCK_SEPARATOR: char = '\u{1f}';
+
+/// Plain text of a content document as the reader's DOM will see it once
+/// the chapter from [`load_chapter`] is rendered, plus [`BLOCK_SEPARATOR`]s,
+/// so that character offsets into it can be mapped back onto the page.
+pub fn document_text(raw: &str) -> String {
+    let doc = parse_html(raw);
+    sanitize(&doc);
+    let mut text = String::new();
+    push_text(&body_of(&doc), &mut text);
+    text
+}
+
+fn push_text(node: &NodeRef, out: &mut String) {
+    for child in node.children() {
+        if let Some(text) = child.as_text() {
+            out.push_str(&text.borrow());
+        } else if let Some(el) = child.as_element() {
+            let block = is_block(&el.name.local);
+            if block {
+                out.push(BLOCK_SEPARATOR);
+            }
+            push_text(&child, out);
+            if block {
+                out.push(BLOCK_SEPARATOR);
+            }
+        }
+    }
+}
+
+/// Parses an XHTML content document with the HTML parser. XHTML allows
+/// `<a id="x"/>` on any element, which HTML would treat as an unclosed start
+/// tag swallowing the rest of the chapter, so those are expanded first.
+pub fn parse_html(raw: &str) -> NodeRef {
+    static SELF_CLOSING: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"<([A-Za-z][\w:.-]*)(\s[^<>]*?)?/>").unwrap());
+    let expanded = SELF_CLOSING.replace_all(raw, |caps: &regex::Captures| {
+        let tag = &caps[1];
+        let attrs = caps.get(2).map_or("", |m| m.as_str());
+        if is_void(tag) {
+            caps[0].to_string()
+        } else {
+            format!("<{tag}{attrs}></{tag}>")
+        }
+    });
+    kuchikiki::parse_html().one(&*expanded).document_node
+}
+
+pub fn body_of(doc: &NodeRef) -> NodeRef {
+    doc.select_first("body")
+        .map(|b| b.as_node().clone())
+        .unwrap_or_else(|_| doc.clone())
+}
+
+pub fn inner_html(node: &NodeRef) -> String {
+    node.children().map(|c| c.to_string()).collect()
+}
+
+fn is_void(tag: &str) -> bool {
+    matches!(
+        tag.to_ascii_lowercase().as_str(),
+        "area"
+            | "base"
+            | "br"
+            | "col"
+            | "embed"
+            | "hr"
+            | "img"
+            | "input"
+            | "link"
+            | "meta"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
+
+pub(super) fn is_block(tag: &str) -> bool {
+    matches!(
+        tag.to_ascii_lowercase().as_str(),
+        "address"
+            | "article"
+            | "aside"
+            | "blockquote"
+            | "br"
+            | "dd"
+            | "div"
+            | "dl"
+            | "dt"
+            | "figcaption"
+            | "figure"
+            | "footer"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "header"
+            | "hr"
+            | "li"
+            | "nav"
+            | "ol"
+            | "p"
+            | "pre"
+            | "section"
+            | "table"
+            | "td"
+            | "th"
+            | "tr"
+            | "ul"
+    )
+}
+
+/// Points media references at the book resource protocol so they load
+/// lazily instead of being embedded in the chapter markup.
+pub(super) fn rewrite_resources(doc: &NodeRef, resource_base: &str, base: &str) {
+    for el in doc
+        .select("img, image, audio, video, source, track")
+        .unwrap()
+    {
+        let mut attrs = el.attributes.borrow_mut();
+        // SVG <image> uses xlink:href (namespaced) or plain href.
+        for (name, attr) in attrs.map.iter_mut() {
+            if !matches!(&*name.local, "src" | "href" | "poster") {
+                continue;
+            }
+            let src = attr.value.trim();
+            if src.is_empty() || src.starts_with("data:") || src.contains("://") {
+                continue;
+            }
+            let full_path = resolve_href(base, src);
+            let full_path = full_path.split('#').next().unwrap_or_default();
+            attr.value = format!("{resource_base}{}", encode_path(full_path));
+        }
+    }
+}
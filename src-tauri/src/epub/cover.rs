@@ -0,0 +1,52 @@
+use super::content::parse_html;
+use super::opf::Package;
+use super::{resolve_href, BookError, EpubArchive};
+
+/// How many spine documents to search for an image when the package
+/// doesn't declare a cover. Covers that aren't declared are nearly always
+/// on the first page, sometimes after a title or copyright page.
+const SPINE_SEARCH_DEPTH: usize = 3;
+
+/// Archive path of the cover image: the EPUB 3 `cover-image` item, then the
+/// EPUB 2 `<meta name="cover">` item, then the first image in the spine.
+pub(super) fn find_cover(
+    archive: &mut EpubArchive,
+    package: &Package,
+) -> Result<Option<String>, BookError> {
+    let declared = package
+        .manifest
+        .iter()
+        .find(|m| m.has_property("cover-image"))
+        .or_else(|| {
+            // Some tools put the href here rather than the item id.
+            let id = package.cover_id.as_deref()?;
+            package.manifest.iter().find(|m| m.id == id || m.href == id)
+        })
+        .filter(|m| m.media_type.starts_with("image/"));
+    if let Some(item) = declared {
+        return Ok(Some(item.full_path.clone()));
+    }
+
+    for item in package.spine.iter().take(SPINE_SEARCH_DEPTH) {
+        let Some(html) = archive.read_text(&item.full_path)? else {
+            continue;
+        };
+        let doc = parse_html(&html);
+        for el in doc.select("img, image").unwrap() {
+            let attrs = el.attributes.borrow();
+            let src = attrs
+                .map
+                .iter()
+                .find(|(name, _)| matches!(&*name.local, "src" | "href"))
+                .map(|(_, attr)| attr.value.trim())
+                .filter(|src| !src.is_empty() && !src.starts_with("data:"));
+            let Some(src) = src else { continue };
+            let full_path = resolve_href(&item.full_path, src);
+            let full_path = full_path.split('#').next().unwrap_or_default();
+            if archive.crc32(full_path).is_some() {
+                return Ok(Some(full_path.to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,719 @@
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use kuchikiki::NodeRef;
+use regex::Regex;
+
+use super::sanitize::ID_PREFIX;
+use super::{encode_path, resolve_href, EpubArchive};
+
+/// The element the reader renders chapters into. Publisher rules only apply
+/// inside it, and rules for `html` or `body` apply to it.
+const SCOPE: &str = ".epub-body";
+
+/// Properties dropped wherever they appear: colours and backgrounds that
+/// would fight the reader's theme, multi-column layout, and the old
+/// script-running extensions.
+const DROPPED_PROPERTIES: &[&str] = &[
+    "-moz-binding",
+    "-webkit-text-fill-color",
+    "behavior",
+    "color",
+    "column-count",
+    "column-width",
+    "columns",
+];
+
+/// Properties dropped from rules for `html` and `body`, along with their
+/// longhands: the reader sizes and pads the chapter container itself and
+/// sets its font and line height from the reading settings.
+const ROOT_PROPERTIES: &[&str] = &[
+    "font",
+    "font-family",
+    "font-size",
+    "height",
+    "line-height",
+    "margin",
+    "max-height",
+    "max-width",
+    "min-height",
+    "min-width",
+    "padding",
+    "width",
+];
+
+const FONT_FACE_PROPERTIES: &[&str] = &[
+    "font-display",
+    "font-family",
+    "font-feature-settings",
+    "font-stretch",
+    "font-style",
+    "font-variant",
+    "font-weight",
+    "src",
+    "unicode-range",
+];
+
+const ABSOLUTE_SIZE_KEYWORDS: &[&str] = &[
+    "xx-small",
+    "x-small",
+    "small",
+    "medium",
+    "large",
+    "x-large",
+    "xx-large",
+    "xxx-large",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Context {
+    Rule,
+    Root,
+    FontFace,
+}
+
+/// The publisher CSS a chapter links or embeds, in document order, made
+/// safe to add to the reader's page: rules are scoped to the chapter
+/// container, declarations that would break the layout or override the
+/// reader's theme and font size are dropped, and URLs point into the book
+/// through the resource protocol or are dropped. Sheets that can't be read
+/// are skipped.
+pub fn chapter_styles(
+    archive: &mut EpubArchive,
+    doc: &NodeRef,
+    chapter_path: &str,
+    resource_base: &str,
+) -> String {
+    let mut filter = Filter {
+        archive,
+        resource_base,
+        included: HashSet::new(),
+    };
+    let mut out = String::new();
+    for node in doc.descendants() {
+        let Some(el) = node.as_element() else {
+            continue;
+        };
+        match &*el.name.local {
+            "link" => {
+                let href = {
+                    let attrs = el.attributes.borrow();
+                    let rel = attrs.get("rel").unwrap_or_default().to_ascii_lowercase();
+                    let rels: Vec<&str> = rel.split_ascii_whitespace().collect();
+                    let print = attrs
+                        .get("media")
+                        .is_some_and(|m| m.trim().eq_ignore_ascii_case("print"));
+                    if !rels.contains(&"stylesheet") || rels.contains(&"alternate") || print {
+                        continue;
+                    }
+                    attrs.get("href").map(str::to_string)
+                };
+                if let Some(path) = href.and_then(|href| book_path(chapter_path, &href)) {
+                    out.push_str(&filter.sheet(&path));
+                }
+            }
+            "style" => out.push_str(&filter.rules(&node.text_contents(), chapter_path)),
+            _ => {}
+        }
+    }
+    // The result goes into a `<style>` element, which `</style>` in a string
+    // would close. `\/` is still `/` to CSS.
+    out.replace("</", "<\\/")
+}
+
+struct Filter<'a> {
+    archive: &'a mut EpubArchive,
+    resource_base: &'a str,
+    /// Sheets already added, so each is included once and `@import` loops
+    /// end.
+    included: HashSet<String>,
+}
+
+impl Filter<'_> {
+    fn sheet(&mut self, path: &str) -> String {
+        if !self.included.insert(path.to_string()) {
+            return String::new();
+        }
+        match self.archive.read_text(path) {
+            Ok(Some(css)) => self.rules(&css, path),
+            _ => String::new(),
+        }
+    }
+
+    /// Filters a list of rules. `base` is the path relative URLs in them
+    /// are resolved against.
+    fn rules(&mut self, css: &str, base: &str) -> String {
+        let css = strip_comments(css);
+        let mut out = String::new();
+        let mut rest = css.as_str();
+        loop {
+            rest = rest.trim_start();
+            if let Some(r) = rest
+                .strip_prefix("<!--")
+                .or_else(|| rest.strip_prefix("-->"))
+            {
+                rest = r;
+                continue;
+            }
+            let Some(end) = find_top_level(rest, b"{;") else {
+                break;
+            };
+            let prelude = rest[..end].trim();
+            if rest.as_bytes()[end] == b';' {
+                if let Some(import) = at_rule(prelude, "import") {
+                    out.push_str(&self.import(import, base));
+                }
+                rest = &rest[end + 1..];
+                continue;
+            }
+            let close = matching_brace(rest, end);
+            let block = &rest[end + 1..close];
+            rest = rest.get(close + 1..).unwrap_or_default();
+
+            if let Some(at) = prelude.strip_prefix('@') {
+                let (name, params) = at.split_once(char::is_whitespace).unwrap_or((at, ""));
+                let (name, params) = (name.to_ascii_lowercase(), params.trim());
+                match name.as_str() {
+                    "media" | "supports" if safe_condition(params) => {
+                        let inner = self.rules(block, base);
+                        if !inner.is_empty() {
+                            out.push_str(&format!("@{name} {params} {{\n{inner}}}\n"));
+                        }
+                    }
+                    "font-face" => {
+                        let decls = self.declarations(block, base, Context::FontFace);
+                        if !decls.is_empty() {
+                            out.push_str(&format!("@font-face {{ {decls} }}\n"));
+                        }
+                    }
+                    // @page, @keyframes, @namespace blocks and the like.
+                    _ => {}
+                }
+                continue;
+            }
+
+            let (selectors, root) = scope_selectors(prelude);
+            if !selectors.is_empty() {
+                let decls = self.declarations(block, base, Context::Rule);
+                if !decls.is_empty() {
+                    out.push_str(&format!("{} {{ {decls} }}\n", selectors.join(", ")));
+                }
+            }
+            if root {
+                let decls = self.declarations(block, base, Context::Root);
+                if !decls.is_empty() {
+                    out.push_str(&format!("{SCOPE} {{ {decls} }}\n"));
+                }
+            }
+        }
+        out
+    }
+
+    /// Inlines an `@import`ed sheet from inside the book, keeping its media
+    /// query.
+    fn import(&mut self, params: &str, base: &str) -> String {
+        // `url(...)`, a string, or (leniently) a bare path, then media.
+        let end = if params
+            .get(..4)
+            .is_some_and(|p| p.eq_ignore_ascii_case("url("))
+        {
+            find_top_level(&params[4..], b")").map_or(params.len(), |i| i + 5)
+        } else {
+            find_top_level(params, b" \t\r\n").unwrap_or(params.len())
+        };
+        let (target, media) = (&params[..end], params[end..].trim());
+        let href = match target.get(..4) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("url(") => {
+                unquote(target[4..].trim_end_matches(')'))
+            }
+            _ => unquote(target),
+        };
+        let Some(path) = book_path(base, href) else {
+            return String::new();
+        };
+        let inner = self.sheet(&path);
+        if media.is_empty() || inner.is_empty() {
+            inner
+        } else if safe_condition(media) {
+            format!("@media {media} {{\n{inner}}}\n")
+        } else {
+            String::new()
+        }
+    }
+
+    fn declarations(&self, block: &str, base: &str, context: Context) -> String {
+        let mut out = Vec::new();
+        for decl in split_top_level(block, b';') {
+            let Some(colon) = find_top_level(decl, b":") else {
+                continue;
+            };
+            let name = decl[..colon].trim().to_ascii_lowercase();
+            let value = decl[colon + 1..].trim();
+            if let Some(value) = self.declaration(&name, value, base, context) {
+                out.push(format!("{name}: {value};"));
+            }
+        }
+        out.join(" ")
+    }
+
+    /// The value to keep for a declaration, with URLs rewritten, or `None`
+    /// to drop it.
+    fn declaration(&self, name: &str, value: &str, base: &str, context: Context) -> Option<String> {
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '-')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid_name || value.is_empty() {
+            return None;
+        }
+        match context {
+            Context::FontFace if !FONT_FACE_PROPERTIES.contains(&name) => return None,
+            Context::Root if is_listed(ROOT_PROPERTIES, name) => return None,
+            Context::Rule | Context::Root
+                if DROPPED_PROPERTIES.contains(&name) || name.starts_with("background") =>
+            {
+                return None
+            }
+            _ => {}
+        }
+
+        let bare = outside_strings(value).to_ascii_lowercase();
+        // Escapes could spell out anything below in a way it isn't matched.
+        if bare.contains('\\')
+            || ["expression(", "javascript:", "image-set("]
+                .iter()
+                .any(|s| bare.contains(s))
+        {
+            return None;
+        }
+        if escapes_container(name, &bare) {
+            return None;
+        }
+        if matches!(name, "font" | "font-size") && has_absolute_size(&bare) {
+            return None;
+        }
+        self.rewrite_urls(value, base)
+    }
+
+    /// Points `url()`s at the resource protocol. `None` if one leads out of
+    /// the book.
+    fn rewrite_urls(&self, value: &str, base: &str) -> Option<String> {
+        let mut out = String::new();
+        let mut rest = value;
+        while let Some(start) = find_url(rest) {
+            out.push_str(&rest[..start]);
+            let args = &rest[start + 4..];
+            let close = find_top_level(args, b")")?;
+            let url = unquote(args[..close].trim());
+            let lower = url.to_ascii_lowercase();
+            if let Some(id) = url.strip_prefix('#') {
+                // A reference to an SVG element, such as a filter.
+                out.push_str(&format!("url(\"#{ID_PREFIX}{id}\")"));
+            } else if lower.starts_with("data:image/")
+                || lower.starts_with("data:font/")
+                || lower.starts_with("data:application/")
+            {
+                out.push_str(&format!("url(\"{}\")", url.replace('"', "%22")));
+            } else {
+                let path = book_path(base, url)?;
+                // A backslash would start an escape in the CSS string.
+                let url = encode_path(&path).replace('\\', "%5C");
+                out.push_str(&format!("url(\"{}{url}\")", self.resource_base));
+            }
+            rest = &args[close + 1..];
+        }
+        out.push_str(rest);
+        Some(out)
+    }
+}
+
+/// Scopes each selector in a list to the chapter container. Selectors for
+/// the document itself (`html`, `body`, `:root`) are reported separately,
+/// as they now mean the container; ones qualifying those elements, like
+/// `body.titlepage`, can't match anything and are dropped. So are ones for
+/// their siblings, like `body ~ nav`, which would reach the app's own
+/// elements next to the container. Id selectors get
+/// the prefix [`sanitize`](super::sanitize::sanitize) gives ids.
+fn scope_selectors(list: &str) -> (Vec<String>, bool) {
+    let mut scoped = Vec::new();
+    let mut root = false;
+    for selector in split_top_level(list, b',') {
+        let selector = selector.trim();
+        if selector.is_empty() {
+            continue;
+        }
+        // What follows the leading root compounds, combinator included.
+        let mut rest = selector;
+        let mut qualified_root = false;
+        while !rest.is_empty() {
+            let compound = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '>');
+            let end = compound
+                .find(|c: char| c.is_whitespace() || c == '>')
+                .unwrap_or(compound.len());
+            let lower = compound[..end].to_ascii_lowercase();
+            if !matches!(lower.as_str(), "html" | "body" | ":root") {
+                qualified_root = ["html", "body", ":root"].iter().any(|r| {
+                    lower
+                        .strip_prefix(r)
+                        .is_some_and(|q| q.starts_with(['.', '#', '[', ':', '+', '~']))
+                });
+                break;
+            }
+            rest = &compound[end..];
+        }
+        let rest = rest.trim_start();
+        if qualified_root || rest.starts_with(['+', '~']) {
+            continue;
+        }
+        if rest.is_empty() {
+            root = true;
+        } else {
+            scoped.push(format!("{SCOPE} {}", prefix_id_selectors(rest)));
+        }
+    }
+    (scoped, root)
+}
+
+/// `selector` with [`ID_PREFIX`] after each `#` that starts an id selector,
+/// leaving strings, attribute selectors and escaped `#`s alone.
+fn prefix_id_selectors(selector: &str) -> String {
+    let bytes = selector.as_bytes();
+    let mut out = String::new();
+    let mut copied = 0;
+    let mut depth = 0usize;
+    let mut scanner = Scanner::new(selector);
+    while let Some((i, in_string)) = scanner.next() {
+        if in_string {
+            continue;
+        }
+        match bytes[i] {
+            b'[' => depth += 1,
+            b']' => depth = depth.saturating_sub(1),
+            b'#' if depth == 0 && (i == 0 || bytes[i - 1] != b'\\') => {
+                out.push_str(&selector[copied..=i]);
+                out.push_str(ID_PREFIX);
+                copied = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push_str(&selector[copied..]);
+    out
+}
+
+/// Whether a declaration could lift content out of the chapter container
+/// and over the reader's toolbar and menus. `bare` is the lowercased value
+/// with its strings removed.
+fn escapes_container(name: &str, bare: &str) -> bool {
+    match name {
+        "position" => ["absolute", "fixed", "sticky"]
+            .iter()
+            .any(|p| bare.contains(p)),
+        "z-index" => true,
+        _ => false,
+    }
+}
+
+/// The parameters of `prelude` if it is the at-rule `@name`.
+fn at_rule<'a>(prelude: &'a str, name: &str) -> Option<&'a str> {
+    let rest = prelude.strip_prefix('@')?;
+    let (at, params) = rest.split_at_checked(name.len())?;
+    let separated = params.starts_with(|c: char| c.is_whitespace() || c == '"' || c == '\'');
+    (at.eq_ignore_ascii_case(name) && separated).then(|| params.trim())
+}
+
+/// Whether a `@media` or `@supports` condition is plain enough to copy.
+fn safe_condition(condition: &str) -> bool {
+    condition
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c.is_whitespace() || "(),:.-/%".contains(c))
+}
+
+/// Whether `name` is one of `properties` or a longhand of one, such as
+/// `margin-top` for `margin`.
+fn is_listed(properties: &[&str], name: &str) -> bool {
+    properties.iter().any(|p| {
+        name.strip_prefix(p)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+    })
+}
+
+fn has_absolute_size(value: &str) -> bool {
+    static ABSOLUTE_LENGTH: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?:^|[\s(/,*+-])[+-]?(?:\d+\.?\d*|\.\d+)(?:px|pt|pc|cm|mm|in|q)\b").unwrap()
+    });
+    ABSOLUTE_LENGTH.is_match(value)
+        || value
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .any(|token| ABSOLUTE_SIZE_KEYWORDS.contains(&token))
+}
+
+/// Path inside the book of a relative URL in `base`; `None` for anything
+/// with a scheme or host.
+fn book_path(base: &str, href: &str) -> Option<String> {
+    let href = href.trim();
+    let scheme = href
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.contains(['/', '?', '#']));
+    if href.is_empty() || scheme || href.starts_with("//") {
+        return None;
+    }
+    let path = resolve_href(base, href);
+    Some(path.split('#').next().unwrap_or_default().to_string())
+}
+
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = s.strip_prefix(quote).and_then(|s| s.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    s
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    loop {
+        let Some(start) = find_unquoted(rest, "/*") else {
+            out.push_str(rest);
+            return out;
+        };
+        out.push_str(&rest[..start]);
+        out.push(' ');
+        match rest[start + 2..].find("*/") {
+            Some(end) => rest = &rest[start + 2 + end + 2..],
+            None => return out,
+        }
+    }
+}
+
+/// `value` with the contents of its strings removed.
+fn outside_strings(value: &str) -> String {
+    let mut out = String::new();
+    let mut scanner = Scanner::new(value);
+    while let Some((i, in_string)) = scanner.next() {
+        if !in_string {
+            out.push(value.as_bytes()[i] as char);
+        }
+    }
+    out
+}
+
+/// Start of the next `url(` outside strings, in any case.
+fn find_url(value: &str) -> Option<usize> {
+    let lower = value.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(i) = find_unquoted(&lower[from..], "url(") {
+        let at = from + i;
+        // Not the tail of another function name.
+        let preceded = lower[..at]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !preceded {
+            return Some(at);
+        }
+        from = at + 4;
+    }
+    None
+}
+
+fn find_unquoted(s: &str, needle: &str) -> Option<usize> {
+    let mut scanner = Scanner::new(s);
+    while let Some((i, in_string)) = scanner.next() {
+        if !in_string && s.as_bytes()[i..].starts_with(needle.as_bytes()) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// First of `bytes` outside strings, parentheses and brackets.
+fn find_top_level(s: &str, bytes: &[u8]) -> Option<usize> {
+    let mut scanner = Scanner::new(s);
+    let mut depth = 0usize;
+    while let Some((i, in_string)) = scanner.next() {
+        if in_string {
+            continue;
+        }
+        let b = s.as_bytes()[i];
+        if depth == 0 && bytes.contains(&b) {
+            return Some(i);
+        }
+        match b {
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_top_level(s: &str, separator: u8) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while let Some(i) = find_top_level(rest, &[separator]) {
+        parts.push(&rest[..i]);
+        rest = &rest[i + 1..];
+    }
+    parts.push(rest);
+    parts
+}
+
+/// Index of the `}` closing the block opened at `open`, or the end of `s`
+/// for a block left open.
+fn matching_brace(s: &str, open: usize) -> usize {
+    let mut scanner = Scanner::new(&s[open + 1..]);
+    let mut depth = 1;
+    while let Some((i, in_string)) = scanner.next() {
+        if in_string {
+            continue;
+        }
+        match s.as_bytes()[open + 1 + i] {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return open + 1 + i;
+                }
+            }
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+/// Walks the bytes of some CSS, saying for each whether it is inside a
+/// string (quotes included). Only ASCII is ever looked for, so byte
+/// positions inside multi-byte characters don't matter.
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    quote: Option<u8>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(s: &'a str) -> Self {
+        Self {
+            bytes: s.as_bytes(),
+            pos: 0,
+            quote: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<(usize, bool)> {
+        let i = self.pos;
+        let b = *self.bytes.get(i)?;
+        self.pos += 1;
+        match self.quote {
+            Some(q) => {
+                if b == b'\\' {
+                    self.pos += 1;
+                } else if b == q || b == b'\n' {
+                    self.quote = None;
+                }
+                Some((i, true))
+            }
+            None if b == b'"' || b == b'\'' => {
+                self.quote = Some(b);
+                Some((i, true))
+            }
+            None => Some((i, false)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_positioning_out_of_the_container() {
+        for (name, value) in [
+            ("position", "absolute"),
+            ("position", "fixed"),
+            ("position", "sticky"),
+            ("position", "-webkit-sticky"),
+            ("z-index", "10"),
+            ("z-index", "auto"),
+        ] {
+            assert!(escapes_container(name, value), "{name}: {value}");
+        }
+        for (name, value) in [
+            ("position", "relative"),
+            ("position", "static"),
+            ("top", "1em"),
+        ] {
+            assert!(!escapes_container(name, value), "{name}: {value}");
+        }
+    }
+
+    #[test]
+    fn scopes_selectors() {
+        assert_eq!(
+            scope_selectors("p, h1 > em,div.box"),
+            (
+                vec![
+                    ".epub-body p".to_string(),
+                    ".epub-body h1 > em".to_string(),
+                    ".epub-body div.box".to_string(),
+                ],
+                false
+            )
+        );
+        assert_eq!(
+            scope_selectors("#title, a[href='#x'] #y"),
+            (
+                vec![
+                    ".epub-body #epub-title".to_string(),
+                    ".epub-body a[href='#x'] #epub-y".to_string(),
+                ],
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn reports_root_selectors() {
+        assert_eq!(scope_selectors("html, BODY, :root"), (vec![], true));
+        assert_eq!(scope_selectors("html body"), (vec![], true));
+        assert_eq!(
+            scope_selectors("body p, html > body > div"),
+            (
+                vec![".epub-body p".to_string(), ".epub-body > div".to_string()],
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn drops_qualified_roots() {
+        for selector in [
+            "body.titlepage",
+            "html[lang] p",
+            "body#x",
+            ":root:hover",
+            "body:first-child",
+        ] {
+            assert_eq!(scope_selectors(selector), (vec![], false), "{selector}");
+        }
+    }
+
+    #[test]
+    fn drops_root_siblings() {
+        for selector in [
+            "~ div",
+            "+ p",
+            "body ~ nav",
+            "html > body + div",
+            "body~nav",
+            "body+p",
+        ] {
+            assert_eq!(scope_selectors(selector), (vec![], false), "{selector}");
+        }
+        assert_eq!(
+            scope_selectors("body ~ nav, h1 + p"),
+            (vec![".epub-body h1 + p".to_string()], false)
+        );
+    }
+}
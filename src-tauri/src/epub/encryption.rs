@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+
+use super::opf::LocalName;
+use super::{parse_xml, resolve_href, BookError, BookStructure, EpubArchive, Metadata};
+
+const ENCRYPTION_PATH: &str = "META-INF/encryption.xml";
+
+/// Files that DRM schemes add to `META-INF`, holding the licence a book's
+/// content key is tied to.
+const DRM_FILES: &[(&str, DrmScheme)] = &[
+    ("META-INF/rights.xml", DrmScheme::AdobeAdept),
+    ("META-INF/license.lcpl", DrmScheme::ReadiumLcp),
+    ("META-INF/sinf.xml", DrmScheme::AppleFairPlay),
+];
+
+const IDPF_ALGORITHM: &str = "http://www.idpf.org/2008/embedding";
+const ADOBE_ALGORITHM: &str = "http://ns.adobe.com/pdf/enc#RC";
+
+/// Font obfuscation schemes. Both XOR the start of the file with a key
+/// derived from the book's identifier, to tie a font to the book it was
+/// licensed for; neither is encryption in any real sense.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Obfuscation {
+    /// The EPUB specification's algorithm: the first 1040 bytes, XORed
+    /// with the SHA-1 of the unique identifier.
+    Idpf,
+    /// Adobe's older algorithm: the first 1024 bytes, XORed with the
+    /// 16 bytes of the book's UUID.
+    Adobe,
+}
+
+impl Obfuscation {
+    fn from_algorithm(algorithm: &str) -> Option<Self> {
+        match algorithm.trim() {
+            IDPF_ALGORITHM => Some(Self::Idpf),
+            ADOBE_ALGORITHM => Some(Self::Adobe),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DrmScheme {
+    /// Adobe Digital Editions, also used by Barnes & Noble.
+    AdobeAdept,
+    ReadiumLcp,
+    AppleFairPlay,
+    /// Resources are encrypted, but nothing says by whom.
+    Unknown,
+}
+
+impl fmt::Display for DrmScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::AdobeAdept => "Adobe DRM",
+            Self::ReadiumLcp => "Readium LCP DRM",
+            Self::AppleFairPlay => "Apple FairPlay DRM",
+            Self::Unknown => "DRM",
+        })
+    }
+}
+
+/// What `encryption.xml` declares.
+#[derive(Default)]
+struct Encryption {
+    obfuscated: HashMap<String, Obfuscation>,
+    /// Whether anything is encrypted with something other than font
+    /// obfuscation, which takes a key from outside the book to read.
+    encrypted: bool,
+}
+
+/// The book's obfuscated fonts, by archive path, or
+/// [`BookError::DrmProtected`] if its content can't be read without a
+/// licence.
+pub fn read_encryption(
+    archive: &mut EpubArchive,
+) -> Result<HashMap<String, Obfuscation>, BookError> {
+    // A broken encryption.xml shouldn't make the whole book unreadable;
+    // obfuscated fonts just won't load.
+    let encryption = match archive.read_text(ENCRYPTION_PATH) {
+        Ok(Some(xml)) => parse_encryption(&xml).unwrap_or_default(),
+        _ => Encryption::default(),
+    };
+    let scheme = DRM_FILES
+        .iter()
+        .find(|(path, _)| archive.contains(path))
+        .map(|&(_, scheme)| scheme);
+    match scheme {
+        Some(scheme) => Err(BookError::DrmProtected { scheme }),
+        None if encryption.encrypted => Err(BookError::DrmProtected {
+            scheme: DrmScheme::Unknown,
+        }),
+        None => Ok(encryption.obfuscated),
+    }
+}
+
+fn parse_encryption(xml: &str) -> Result<Encryption, BookError> {
+    let doc = parse_xml(ENCRYPTION_PATH, xml)?;
+    let mut encryption = Encryption::default();
+    for data in doc
+        .descendants()
+        .filter(|n| n.has_tag_name_local("EncryptedData"))
+    {
+        let method = data
+            .descendants()
+            .find(|n| n.has_tag_name_local("EncryptionMethod"))
+            .and_then(|n| n.attribute("Algorithm"));
+        let uri = data
+            .descendants()
+            .find(|n| n.has_tag_name_local("CipherReference"))
+            .and_then(|n| n.attribute("URI"));
+        let Some(uri) = uri else {
+            continue;
+        };
+        match method.and_then(Obfuscation::from_algorithm) {
+            // URIs are relative to the root of the container.
+            Some(method) => {
+                encryption.obfuscated.insert(resolve_href("", uri), method);
+            }
+            None => encryption.encrypted = true,
+        }
+    }
+    Ok(encryption)
+}
+
+/// Reads a resource from the archive as the book's author made it, with any
+/// obfuscation undone.
+pub fn read_resource(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    path: &str,
+) -> Result<Option<Vec<u8>>, BookError> {
+    let mut bytes = archive.read_bytes(path)?;
+    if let (Some(bytes), Some(&method)) = (&mut bytes, structure.obfuscated.get(path)) {
+        deobfuscate(method, &structure.metadata, bytes);
+    }
+    Ok(bytes)
+}
+
+/// Reverses the obfuscation of a resource in place. Without an identifier
+/// to derive the key from, the data is left as it is.
+fn deobfuscate(method: Obfuscation, metadata: &Metadata, data: &mut [u8]) {
+    let (key, length) = match method {
+        Obfuscation::Idpf => match idpf_key(metadata) {
+            Some(key) => (key, 1040),
+            None => return,
+        },
+        Obfuscation::Adobe => match adobe_key(metadata) {
+            Some(key) => (key, 1024),
+            None => return,
+        },
+    };
+    for (i, byte) in data.iter_mut().take(length).enumerate() {
+        *byte ^= key[i % key.len()];
+    }
+}
+
+/// SHA-1 of the unique identifier with all whitespace removed.
+fn idpf_key(metadata: &Metadata) -> Option<Vec<u8>> {
+    let id: String = metadata
+        .identifier
+        .as_deref()?
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n'))
+        .collect();
+    Some(Sha1::digest(id.as_bytes()).to_vec())
+}
+
+/// The bytes of the book's UUID: the unique identifier if it is one, as
+/// Adobe's tools always make it, or else the first identifier that is.
+fn adobe_key(metadata: &Metadata) -> Option<Vec<u8>> {
+    metadata
+        .identifier
+        .iter()
+        .chain(metadata.identifiers.iter().map(|id| &id.value))
+        .find_map(|id| uuid_bytes(id))
+}
+
+fn uuid_bytes(id: &str) -> Option<Vec<u8>> {
+    // Accepts `urn:uuid:` and braced forms as well as the bare UUID.
+    let uuid = uuid::Uuid::parse_str(id.trim()).ok()?;
+    Some(uuid.as_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::Path;
+
+    use super::*;
+    use crate::epub::metadata::Identifier;
+    use crate::test_support::{write_epub, ScratchDir};
+
+    const UUID: &str = "urn:uuid:0c2fd2a4-9e37-4b52-8c8e-3a1f4a8d2b61";
+
+    /// A stand-in font, longer than either obfuscated prefix.
+    fn font() -> Vec<u8> {
+        (0..2000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Obfuscates `data` as a publisher's tools would.
+    fn obfuscate(data: &[u8], key: &[u8], length: usize) -> Vec<u8> {
+        data.iter()
+            .enumerate()
+            .map(|(i, b)| {
+                if i < length {
+                    b ^ key[i % key.len()]
+                } else {
+                    *b
+                }
+            })
+            .collect()
+    }
+
+    fn metadata(identifier: Option<&str>, identifiers: &[&str]) -> Metadata {
+        Metadata {
+            identifier: identifier.map(Into::into),
+            identifiers: identifiers
+                .iter()
+                .map(|&value| Identifier {
+                    value: value.into(),
+                    scheme: None,
+                })
+                .collect(),
+            ..Metadata::default()
+        }
+    }
+
+    #[test]
+    fn undoes_idpf_obfuscation() {
+        let key = Sha1::digest(UUID.as_bytes());
+        let mut data = obfuscate(&font(), &key, 1040);
+        assert_ne!(data, font());
+        // The key ignores whitespace around and inside the identifier.
+        let spaced = format!(" {}\n\t{} ", &UUID[..20], &UUID[20..]);
+        deobfuscate(Obfuscation::Idpf, &metadata(Some(&spaced), &[]), &mut data);
+        assert_eq!(data, font());
+    }
+
+    #[test]
+    fn undoes_adobe_obfuscation() {
+        let key = uuid::Uuid::parse_str(UUID).unwrap();
+        let mut data = obfuscate(&font(), key.as_bytes(), 1024);
+        assert_ne!(data, font());
+        deobfuscate(Obfuscation::Adobe, &metadata(Some(UUID), &[]), &mut data);
+        assert_eq!(data, font());
+
+        // A unique identifier that isn't a UUID falls back to one that is.
+        let mut data = obfuscate(&font(), key.as_bytes(), 1024);
+        let metadata = metadata(Some("isbn:9780000000000"), &["9780000000000", UUID]);
+        deobfuscate(Obfuscation::Adobe, &metadata, &mut data);
+        assert_eq!(data, font());
+    }
+
+    #[test]
+    fn leaves_data_without_a_key() {
+        for method in [Obfuscation::Idpf, Obfuscation::Adobe] {
+            let mut data = font();
+            deobfuscate(method, &metadata(None, &[]), &mut data);
+            assert_eq!(data, font());
+        }
+        let mut data = font();
+        deobfuscate(
+            Obfuscation::Adobe,
+            &metadata(Some("not a uuid"), &[]),
+            &mut data,
+        );
+        assert_eq!(data, font());
+    }
+
+    #[test]
+    fn reads_obfuscated_fonts() {
+        let encryption = parse_encryption(&format!(
+            r#"<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container"
+                xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+              <enc:EncryptedData>
+                <enc:EncryptionMethod Algorithm="{IDPF_ALGORITHM}"/>
+                <enc:CipherData><enc:CipherReference URI="OEBPS/fonts/a.otf"/></enc:CipherData>
+              </enc:EncryptedData>
+              <enc:EncryptedData>
+                <enc:EncryptionMethod Algorithm="{ADOBE_ALGORITHM}"/>
+                <enc:CipherData><enc:CipherReference URI="OEBPS/fonts/b.otf"/></enc:CipherData>
+              </enc:EncryptedData>
+            </encryption>"#
+        ))
+        .unwrap();
+        assert!(!encryption.encrypted);
+        assert_eq!(
+            encryption.obfuscated["OEBPS/fonts/a.otf"],
+            Obfuscation::Idpf
+        );
+        assert_eq!(
+            encryption.obfuscated["OEBPS/fonts/b.otf"],
+            Obfuscation::Adobe
+        );
+    }
+
+    /// A book with `extra` files added to the archive.
+    fn write_book(path: &Path, extra: &[(&str, &str)]) {
+        write_epub(path, "Locked", &["<p>Text</p>"]);
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let mut zip = zip::ZipWriter::new_append(file).unwrap();
+        for (name, contents) in extra {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn encryption_xml(algorithm: &str, uri: &str) -> String {
+        format!(
+            r#"<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container"
+                xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+              <enc:EncryptedData>
+                <enc:EncryptionMethod Algorithm="{algorithm}"/>
+                <enc:CipherData><enc:CipherReference URI="{uri}"/></enc:CipherData>
+              </enc:EncryptedData>
+            </encryption>"#
+        )
+    }
+
+    fn drm_of(dir: &ScratchDir, extra: &[(&str, &str)]) -> Option<DrmScheme> {
+        let path = dir.join("book.epub");
+        write_book(&path, extra);
+        let mut archive = EpubArchive::open(&path).unwrap();
+        match read_encryption(&mut archive) {
+            Ok(_) => None,
+            Err(BookError::DrmProtected { scheme }) => Some(scheme),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[test]
+    fn detects_drm_licences() {
+        let dir = ScratchDir::new("encryption-licences");
+        assert_eq!(drm_of(&dir, &[]), None);
+        assert_eq!(
+            drm_of(&dir, &[("META-INF/rights.xml", "<rights/>")]),
+            Some(DrmScheme::AdobeAdept)
+        );
+        assert_eq!(
+            drm_of(&dir, &[("META-INF/license.lcpl", "{}")]),
+            Some(DrmScheme::ReadiumLcp)
+        );
+        assert_eq!(
+            drm_of(&dir, &[("META-INF/sinf.xml", "<fairplay:sinf/>")]),
+            Some(DrmScheme::AppleFairPlay)
+        );
+    }
+
+    #[test]
+    fn detects_encrypted_content() {
+        let dir = ScratchDir::new("encryption-content");
+        let aes = encryption_xml(
+            "http://www.w3.org/2001/04/xmlenc#aes128-cbc",
+            "OEBPS/ch1.xhtml",
+        );
+        assert_eq!(
+            drm_of(&dir, &[(ENCRYPTION_PATH, &aes)]),
+            Some(DrmScheme::Unknown)
+        );
+        // A licence file names the scheme even when encryption.xml is there.
+        assert_eq!(
+            drm_of(
+                &dir,
+                &[
+                    (ENCRYPTION_PATH, &aes),
+                    ("META-INF/rights.xml", "<rights/>")
+                ]
+            ),
+            Some(DrmScheme::AdobeAdept)
+        );
+        // Obfuscated fonts alone aren't DRM.
+        let font = encryption_xml(IDPF_ALGORITHM, "OEBPS/fonts/a.otf");
+        assert_eq!(drm_of(&dir, &[(ENCRYPTION_PATH, &font)]), None);
+    }
+
+    #[test]
+    fn refuses_text_of_drm_books() {
+        let dir = ScratchDir::new("encryption-text");
+        let path = dir.join("book.epub");
+        write_book(&path, &[("META-INF/license.lcpl", "{}")]);
+        assert!(matches!(
+            crate::epub::read_text(&path),
+            Err(BookError::DrmProtected {
+                scheme: DrmScheme::ReadiumLcp
+            })
+        ));
+    }
+}
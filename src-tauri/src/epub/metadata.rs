@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use roxmltree::Node;
+use serde::Serialize;
+
+use super::opf::text_of;
+
+/// Collections nested deeper than this are almost certainly a `refines`
+/// cycle rather than a real hierarchy.
+const MAX_COLLECTION_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    /// Sort form of the title, e.g. "Hobbit, The".
+    pub title_sort: Option<String>,
+    /// `dc:creator` entries, in display order.
+    pub creators: Vec<Contributor>,
+    /// `dc:contributor` entries: editors, translators, illustrators...
+    pub contributors: Vec<Contributor>,
+    pub languages: Vec<String>,
+    /// The identifier referenced by the package `unique-identifier`.
+    pub identifier: Option<String>,
+    pub identifiers: Vec<Identifier>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    pub dates: Vec<DateEntry>,
+    /// The EPUB 3 `dcterms:modified` timestamp.
+    pub modified: Option<String>,
+    pub subjects: Vec<String>,
+    pub series: Option<Series>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Contributor {
+    pub name: String,
+    /// Sort form of the name, e.g. "Tolkien, J. R. R.".
+    pub file_as: Option<String>,
+    /// MARC relator codes such as `aut`, `edt` or `trl`.
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identifier {
+    pub value: String,
+    /// Lowercase scheme (`isbn`, `uuid`, `doi`...), when declared or
+    /// recognisable from the value.
+    pub scheme: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateEntry {
+    pub value: String,
+    /// EPUB 2 `opf:event`, e.g. `publication` or `modification`.
+    pub event: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Series {
+    pub name: String,
+    pub position: Option<f64>,
+    /// The collection this one is itself part of, for nested sets.
+    pub part_of: Option<Box<Series>>,
+}
+
+/// A `<meta refines="#id">` entry, attaching a property to another element.
+struct Refinement<'a, 'i> {
+    node: Node<'a, 'i>,
+    property: &'a str,
+    scheme: Option<&'a str>,
+    value: String,
+}
+
+type Refinements<'a, 'i> = HashMap<&'a str, Vec<Refinement<'a, 'i>>>;
+
+pub(super) fn parse(metadata: Node, unique_id: Option<&str>) -> Metadata {
+    let elements: Vec<Node> = metadata.children().filter(Node::is_element).collect();
+
+    let mut refinements = Refinements::new();
+    for el in &elements {
+        let (Some(target), Some(property)) = (el.attribute("refines"), el.attribute("property"))
+        else {
+            continue;
+        };
+        let Some(value) = text_of(*el) else { continue };
+        refinements
+            .entry(target.trim_start_matches('#'))
+            .or_default()
+            .push(Refinement {
+                node: *el,
+                property,
+                scheme: el.attribute("scheme"),
+                value,
+            });
+    }
+
+    let mut out = Metadata::default();
+    let mut titles = Vec::new();
+    let mut creators = Vec::new();
+    let mut contributors = Vec::new();
+    let mut identifiers = Vec::new();
+    let mut calibre_series = None;
+    let mut calibre_index = None;
+    for &el in &elements {
+        match el.tag_name().name() {
+            "meta" => {
+                if let (Some(name), Some(content)) = (el.attribute("name"), el.attribute("content"))
+                {
+                    match name {
+                        "calibre:series" => calibre_series = Some(content.trim().to_string()),
+                        "calibre:series_index" => calibre_index = content.trim().parse().ok(),
+                        "calibre:title_sort" => out.title_sort = Some(content.trim().to_string()),
+                        _ => {}
+                    }
+                    continue;
+                }
+                if el.attribute("refines").is_some() {
+                    continue;
+                }
+                match el.attribute("property") {
+                    Some("dcterms:modified") => out.modified = text_of(el),
+                    Some("belongs-to-collection") if out.series.is_none() => {
+                        out.series =
+                            collection(el, &refinements, 0).filter(|_| is_series(el, &refinements));
+                    }
+                    _ => {}
+                }
+            }
+            name => {
+                let Some(value) = text_of(el) else { continue };
+                match name {
+                    "title" => titles.push((el, value)),
+                    "creator" => creators.push(contributor(el, value, &refinements)),
+                    "contributor" => contributors.push(contributor(el, value, &refinements)),
+                    "language" => out.languages.push(value),
+                    "identifier" => identifiers.push((el, value)),
+                    "publisher" if out.publisher.is_none() => out.publisher = Some(value),
+                    "description" if out.description.is_none() => out.description = Some(value),
+                    "subject" => out.subjects.push(value),
+                    "date" => out.dates.push(DateEntry {
+                        value,
+                        event: attribute_local(el, "event").map(str::to_string),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // EPUB 3 marks the main title and subtitle with `title-type`; EPUB 2
+    // only has the document order.
+    let title_type = |el: Node| refined(el, &refinements, "title-type").next();
+    let main = titles
+        .iter()
+        .find(|(el, _)| title_type(*el).is_some_and(|r| r.value == "main"))
+        .or(titles.first());
+    if let Some((el, value)) = main {
+        out.title = Some(value.clone());
+        if let Some(file_as) = refined(*el, &refinements, "file-as").next() {
+            out.title_sort = Some(file_as.value.clone());
+        }
+    }
+    out.subtitle = titles
+        .iter()
+        .find(|(el, _)| title_type(*el).is_some_and(|r| r.value == "subtitle"))
+        .map(|(_, value)| value.clone());
+
+    out.creators = in_display_order(creators);
+    out.contributors = in_display_order(contributors);
+
+    out.identifier = identifiers
+        .iter()
+        .find(|(el, _)| el.attribute("id").is_some() && el.attribute("id") == unique_id)
+        .or(identifiers.first())
+        .map(|(_, value)| value.clone());
+    out.identifiers = identifiers
+        .into_iter()
+        .map(|(el, value)| Identifier {
+            scheme: identifier_scheme(el, &value, &refinements),
+            value,
+        })
+        .collect();
+
+    if out.series.is_none() {
+        out.series = calibre_series.map(|name| Series {
+            name,
+            position: calibre_index,
+            part_of: None,
+        });
+    }
+    out
+}
+
+/// Refinements of `el` with the given property, following its `id`.
+fn refined<'r, 'a, 'i>(
+    el: Node,
+    refinements: &'r Refinements<'a, 'i>,
+    property: &'r str,
+) -> impl Iterator<Item = &'r Refinement<'a, 'i>> {
+    el.attribute("id")
+        .and_then(|id| refinements.get(id))
+        .into_iter()
+        .flatten()
+        .filter(move |r| r.property == property)
+}
+
+fn contributor(el: Node, name: String, refinements: &Refinements) -> (Contributor, usize) {
+    let mut roles: Vec<String> = refined(el, refinements, "role")
+        .map(|r| r.value.clone())
+        .collect();
+    if let Some(role) = attribute_local(el, "role") {
+        roles.push(role.to_string());
+    }
+    let file_as = refined(el, refinements, "file-as")
+        .next()
+        .map(|r| r.value.clone())
+        .or_else(|| attribute_local(el, "file-as").map(str::to_string));
+    // Entries without a display-seq keep their document order, after the
+    // numbered ones.
+    let seq = refined(el, refinements, "display-seq")
+        .next()
+        .and_then(|r| r.value.parse().ok())
+        .unwrap_or(usize::MAX);
+    (
+        Contributor {
+            name,
+            file_as,
+            roles,
+        },
+        seq,
+    )
+}
+
+fn in_display_order(mut entries: Vec<(Contributor, usize)>) -> Vec<Contributor> {
+    entries.sort_by_key(|(_, seq)| *seq);
+    entries.into_iter().map(|(c, _)| c).collect()
+}
+
+fn is_series(el: Node, refinements: &Refinements) -> bool {
+    refined(el, refinements, "collection-type")
+        .next()
+        .is_none_or(|r| r.value == "series")
+}
+
+fn collection(el: Node, refinements: &Refinements, depth: usize) -> Option<Series> {
+    let name = text_of(el)?;
+    let position = refined(el, refinements, "group-position")
+        .next()
+        .and_then(|r| r.value.parse().ok());
+    // A collection that refines another one holds it: follow the chain up.
+    let part_of = if depth < MAX_COLLECTION_DEPTH {
+        refined(el, refinements, "belongs-to-collection")
+            .find_map(|r| collection(r.node, refinements, depth + 1))
+            .map(Box::new)
+    } else {
+        None
+    };
+    Some(Series {
+        name,
+        position,
+        part_of,
+    })
+}
+
+fn identifier_scheme(el: Node, value: &str, refinements: &Refinements) -> Option<String> {
+    if let Some(scheme) = attribute_local(el, "scheme") {
+        return Some(scheme.to_ascii_lowercase());
+    }
+    // EPUB 3 declares the type with ONIX code list 5.
+    let onix = refined(el, refinements, "identifier-type")
+        .find(|r| r.scheme == Some("onix:codelist5"))
+        .and_then(|r| match r.value.as_str() {
+            "02" | "15" => Some("isbn"),
+            "06" => Some("doi"),
+            "07" => Some("issn"),
+            _ => None,
+        });
+    if let Some(scheme) = onix {
+        return Some(scheme.to_string());
+    }
+
+    let lower = value.to_ascii_lowercase();
+    let unprefixed = lower.strip_prefix("urn:").unwrap_or(&lower);
+    if let Some((scheme, _)) = unprefixed.split_once(':') {
+        if matches!(scheme, "isbn" | "uuid" | "doi" | "issn" | "asin") {
+            return Some(scheme.to_string());
+        }
+    }
+    let digits: String = lower.chars().filter(|c| !matches!(c, '-' | ' ')).collect();
+    if !digits.is_ascii() {
+        return None;
+    }
+    let looks_like_isbn = match digits.len() {
+        10 => {
+            digits[..9].bytes().all(|b| b.is_ascii_digit())
+                && digits.ends_with(|c: char| c == 'x' || c.is_ascii_digit())
+        }
+        13 => digits.bytes().all(|b| b.is_ascii_digit()) && digits.starts_with("97"),
+        _ => false,
+    };
+    looks_like_isbn.then(|| "isbn".to_string())
+}
+
+/// Looks an attribute up by local name, ignoring its namespace. EPUB 2
+/// files bind the `opf:` prefix inconsistently.
+fn attribute_local<'a>(el: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    el.attributes()
+        .find(|a| a.name() == name)
+        .map(|a| a.value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identifiers(xml: &str) -> Vec<Identifier> {
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        parse(doc.root_element(), None).identifiers
+    }
+
+    #[test]
+    fn recognises_isbns_from_the_value() {
+        let ids = identifiers(
+            r#"<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+                <dc:identifier>978-0-14-044913-6</dc:identifier>
+                <dc:identifier>0-14-044913-X</dc:identifier>
+                <dc:identifier>123456789</dc:identifier>
+            </metadata>"#,
+        );
+        let schemes: Vec<_> = ids.iter().map(|i| i.scheme.as_deref()).collect();
+        assert_eq!(schemes, [Some("isbn"), Some("isbn"), None]);
+    }
+
+    #[test]
+    fn non_ascii_identifiers_have_no_scheme() {
+        let ids = identifiers(
+            r#"<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+                <dc:identifier>abcdefg€</dc:identifier>
+                <dc:identifier>12345678€</dc:identifier>
+            </metadata>"#,
+        );
+        assert_eq!(ids.len(), 2);
+        assert!(ids.iter().all(|i| i.scheme.is_none()));
+    }
+}
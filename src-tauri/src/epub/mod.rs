@@ -0,0 +1,326 @@
+mod archive;
+mod cfi;
+mod content;
+mod cover;
+mod css;
+mod encryption;
+mod metadata;
+mod notes;
+mod opf;
+mod pages;
+mod passage;
+mod sanitize;
+mod toc;
+mod words;
+mod xpointer;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Serialize;
+
+pub use archive::EpubArchive;
+pub use cfi::{cfi_at, resolve_cfi, Cfi, CfiError, CfiLocation};
+pub use content::{load_chapter, Chapter, BLOCK_SEPARATOR};
+pub use encryption::{read_resource, DrmScheme, Obfuscation};
+pub use metadata::Metadata;
+pub use notes::{resolve_link, LinkTarget};
+pub use pages::{page_map, PageMark};
+pub use passage::{find_passage, passage_at, Passage};
+pub use words::{read_lengths, SpineLength, TextPosition, WordIndex};
+pub use xpointer::resolve_xpointer;
+
+/// Spine media types the reader renders as chapters.
+const CONTENT_TYPES: &[&str] = &["application/xhtml+xml", "text/html"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum BookError {
+    #[error("Failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid epub: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Invalid epub: missing container.xml")]
+    MissingContainer,
+    #[error("Invalid epub: no rootfile")]
+    MissingRootfile,
+    #[error("Invalid epub: missing OPF at {0}")]
+    MissingOpf(String),
+    #[error("Invalid epub: malformed {path}: {reason}")]
+    Malformed { path: String, reason: String },
+    #[error("Invalid epub: missing {0}")]
+    MissingEntry(String),
+    #[error("Invalid epub: {0} is too large")]
+    EntryTooLarge(String),
+    #[error("Spine index {0} is out of range")]
+    SpineIndexOutOfRange(usize),
+    #[error("This book is protected by {scheme}, so it can't be opened here")]
+    DrmProtected { scheme: DrmScheme },
+    #[error(transparent)]
+    Cfi(#[from] CfiError),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestItem {
+    pub id: String,
+    pub href: String,
+    pub media_type: String,
+    pub properties: Vec<String>,
+    /// Path of the resource inside the archive, resolved against the OPF.
+    pub full_path: String,
+}
+
+impl ManifestItem {
+    pub fn has_property(&self, name: &str) -> bool {
+        self.properties.iter().any(|p| p == name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpineItem {
+    pub id: String,
+    pub media_type: String,
+    pub full_path: String,
+    pub linear: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TocEntry {
+    pub label: String,
+    /// Archive path of the target, including any `#fragment`.
+    pub href: String,
+    pub subitems: Vec<TocEntry>,
+}
+
+/// A major part of the book, such as where the main text starts.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Landmark {
+    /// An EPUB 3 structural semantic: "cover", "toc", "bodymatter",
+    /// "bibliography", "index" and so on. EPUB 2 guide types are translated.
+    pub kind: String,
+    pub label: String,
+    /// Archive path of the target, including any `#fragment`.
+    pub href: String,
+}
+
+/// An entry in the book's list of print page numbers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageTarget {
+    /// The page number as printed, which needn't be a number: "xiv".
+    pub label: String,
+    /// Archive path of the page break, including any `#fragment`.
+    pub href: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookStructure {
+    pub opf_path: String,
+    pub version: String,
+    pub manifest: Vec<ManifestItem>,
+    pub spine: Vec<SpineItem>,
+    pub toc: Vec<TocEntry>,
+    /// Print page numbers, in reading order. Empty unless the book maps its
+    /// text to a print edition.
+    pub page_list: Vec<PageTarget>,
+    /// From the navigation document, or failing that the EPUB 2 guide.
+    pub landmarks: Vec<Landmark>,
+    pub metadata: Metadata,
+    /// CFI step that selects `<spine>` in the package document.
+    #[serde(skip)]
+    pub spine_step: usize,
+    /// Fonts obfuscated as declared in `META-INF/encryption.xml`, by
+    /// archive path.
+    #[serde(skip)]
+    pub obfuscated: HashMap<String, Obfuscation>,
+}
+
+pub fn parse(path: &Path) -> Result<BookStructure, BookError> {
+    let mut archive = EpubArchive::open(path)?;
+    read_structure(&mut archive)
+}
+
+/// Reads only the package metadata, skipping the navigation documents.
+pub fn read_metadata(path: &Path) -> Result<Metadata, BookError> {
+    let mut archive = EpubArchive::open(path)?;
+    let (_, package) = read_package(&mut archive)?;
+    Ok(package.metadata)
+}
+
+/// Reads the cover image, if the book has one.
+pub fn read_cover(path: &Path) -> Result<Option<Vec<u8>>, BookError> {
+    let mut archive = EpubArchive::open(path)?;
+    let (_, package) = read_package(&mut archive)?;
+    match cover::find_cover(&mut archive, &package)? {
+        Some(cover_path) => archive.read_bytes(&cover_path),
+        None => Ok(None),
+    }
+}
+
+/// The chapter title and text at a CFI.
+pub fn read_passage(path: &Path, cfi: &Cfi) -> Result<Passage, BookError> {
+    let mut archive = EpubArchive::open(path)?;
+    let structure = read_structure(&mut archive)?;
+    passage_at(&mut archive, &structure, cfi)
+}
+
+/// Text of every XHTML spine item, paired with its spine index. Block
+/// boundaries are marked with [`BLOCK_SEPARATOR`].
+pub fn read_text(path: &Path) -> Result<Vec<(usize, String)>, BookError> {
+    let mut archive = EpubArchive::open(path)?;
+    let (_, package) = read_package(&mut archive)?;
+    // Only to refuse books with DRM, whose chapters would be ciphertext;
+    // obfuscated fonts make no difference to the text.
+    encryption::read_encryption(&mut archive)?;
+    let mut chapters = Vec::new();
+    for (index, item) in package.spine.iter().enumerate() {
+        if !CONTENT_TYPES.contains(&item.media_type.as_str()) {
+            continue;
+        }
+        if let Some(raw) = archive.read_text(&item.full_path)? {
+            chapters.push((index, content::document_text(&raw)));
+        }
+    }
+    Ok(chapters)
+}
+
+pub fn read_structure(archive: &mut EpubArchive) -> Result<BookStructure, BookError> {
+    let (opf_path, package) = read_package(archive)?;
+
+    // A broken navigation document shouldn't make the whole book unreadable,
+    // so TOC and page list failures fall back to an empty list.
+    let mut toc = Vec::new();
+    let mut page_list = Vec::new();
+    let mut landmarks = Vec::new();
+    let ncx = package
+        .toc_id
+        .as_deref()
+        .and_then(|id| package.manifest.iter().find(|m| m.id == id))
+        .or_else(|| {
+            package
+                .manifest
+                .iter()
+                .find(|m| m.media_type == "application/x-dtbncx+xml")
+        });
+    let ncx_xml = ncx.and_then(|ncx| archive.read_text(&ncx.full_path).ok().flatten());
+    let nav = package.manifest.iter().find(|m| m.has_property("nav"));
+    let nav_xml = nav.and_then(|nav| archive.read_text(&nav.full_path).ok().flatten());
+    if let (Some(ncx), Some(xml)) = (ncx, &ncx_xml) {
+        toc = toc::parse_ncx(&ncx.full_path, xml).unwrap_or_default();
+    }
+    if let (Some(nav), Some(xml)) = (nav, &nav_xml) {
+        if toc.is_empty() {
+            toc = toc::parse_nav(&nav.full_path, xml).unwrap_or_default();
+        }
+        page_list = toc::parse_nav_pages(&nav.full_path, xml).unwrap_or_default();
+        landmarks = toc::parse_nav_landmarks(&nav.full_path, xml).unwrap_or_default();
+    }
+    if let (Some(ncx), Some(xml), true) = (ncx, &ncx_xml, page_list.is_empty()) {
+        page_list = toc::parse_ncx_pages(&ncx.full_path, xml).unwrap_or_default();
+    }
+
+    let obfuscated = encryption::read_encryption(archive)?;
+
+    Ok(BookStructure {
+        opf_path,
+        version: package.version,
+        manifest: package.manifest,
+        spine: package.spine,
+        toc,
+        page_list,
+        landmarks: if landmarks.is_empty() {
+            package.guide
+        } else {
+            landmarks
+        },
+        metadata: package.metadata,
+        spine_step: package.spine_step,
+        obfuscated,
+    })
+}
+
+fn read_package(archive: &mut EpubArchive) -> Result<(String, opf::Package), BookError> {
+    let container = archive
+        .read_text("META-INF/container.xml")?
+        .ok_or(BookError::MissingContainer)?;
+    let opf_path = opf::rootfile_path(&container)?;
+    let opf_xml = archive
+        .read_text(&opf_path)?
+        .ok_or_else(|| BookError::MissingOpf(opf_path.clone()))?;
+    let package = opf::parse_package(&opf_path, &opf_xml)?;
+    Ok((opf_path, package))
+}
+
+/// Resolves `href` relative to the archive path `base`, the way a browser
+/// would resolve a relative URL. Percent-escapes in the path are decoded so
+/// the result can be looked up in the zip directly; a `#fragment` is kept.
+pub fn resolve_href(base: &str, href: &str) -> String {
+    let (path, fragment) = match href.split_once('#') {
+        Some((p, f)) => (p, Some(f)),
+        None => (href, None),
+    };
+    let path = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+
+    let mut resolved = if path.is_empty() {
+        base.to_string()
+    } else if let Some(absolute) = path.strip_prefix('/') {
+        normalize_path(absolute)
+    } else {
+        let dir = base.rfind('/').map_or("", |i| &base[..=i]);
+        normalize_path(&format!("{dir}{path}"))
+    };
+    if let Some(fragment) = fragment {
+        resolved.push('#');
+        resolved.push_str(fragment);
+    }
+    resolved
+}
+
+/// Percent-encodes an archive path for use in a URL, keeping `/` separators.
+pub fn encode_path(path: &str) -> String {
+    const SEGMENT: &AsciiSet = &CONTROLS
+        .add(b' ')
+        .add(b'"')
+        .add(b'#')
+        .add(b'%')
+        .add(b'<')
+        .add(b'>')
+        .add(b'?')
+        .add(b'`')
+        .add(b'{')
+        .add(b'}');
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn parse_xml<'a>(path: &str, text: &'a str) -> Result<roxmltree::Document<'a>, BookError> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    roxmltree::Document::parse_with_options(text, options).map_err(|e| BookError::Malformed {
+        path: path.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+fn normalize_path(path: &str) -> String {
+    let mut stack: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                stack.pop();
+            }
+            p => stack.push(p),
+        }
+    }
+    stack.join("/")
+}
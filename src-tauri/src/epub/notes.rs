@@ -0,0 +1,199 @@
+use kuchikiki::NodeRef;
+use serde::Serialize;
+
+use super::cfi::by_id;
+use super::content::{inner_html, is_block, parse_html, rewrite_resources};
+use super::sanitize::{sanitize, ID_PREFIX};
+use super::{resolve_href, BookError, BookStructure, EpubArchive};
+
+/// Where a link in a chapter leads.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkTarget {
+    /// Archive path of the target, with its `#fragment` if it has one.
+    pub href: String,
+    /// The spine item the target is in, if it's in the spine at all.
+    pub spine_index: Option<usize>,
+    /// Set when the target is a footnote or endnote.
+    pub note: Option<Note>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+    /// Sanitized contents of the note. Links in it are rewritten to archive
+    /// paths like [`LinkTarget::href`], and ids are removed so they don't
+    /// clash with the chapter's.
+    pub html: String,
+    /// Archive path and fragment of the note's link back into the text.
+    pub backlink: Option<String>,
+}
+
+const NOTE_TYPES: &[&str] = &["footnote", "endnote", "note", "rearnote"];
+const NOTE_ROLES: &[&str] = &["doc-footnote", "doc-endnote"];
+
+/// Resolves a link's `href` in spine item `index`, as it appears in the
+/// sanitized chapter. Returns `None` for links out of the book.
+///
+/// The target counts as a note if the link is marked as a note reference,
+/// the target is marked as a note, or the target links back to the chapter,
+/// which is how notes without EPUB 3 semantics are usually written.
+pub fn resolve_link(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    index: usize,
+    href: &str,
+    resource_base: &str,
+) -> Result<Option<LinkTarget>, BookError> {
+    let item = structure
+        .spine
+        .get(index)
+        .ok_or(BookError::SpineIndexOutOfRange(index))?;
+    let href = href.trim();
+    if is_external(href) {
+        return Ok(None);
+    }
+    let resolved = resolve_href(&item.full_path, href);
+    let (path, fragment) = match resolved.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (resolved.as_str(), None),
+    };
+    let mut target = LinkTarget {
+        href: book_href(&resolved),
+        spine_index: structure.spine.iter().position(|s| s.full_path == path),
+        note: None,
+    };
+    let Some(fragment) = fragment.filter(|f| !f.is_empty()) else {
+        return Ok(Some(target));
+    };
+
+    let Some(raw) = archive.read_text(&item.full_path)? else {
+        return Ok(Some(target));
+    };
+    // Both are sanitized, so their ids and links match the chapter's.
+    let source = parse_html(&raw);
+    sanitize(&source);
+    let doc = if path == item.full_path {
+        source.clone()
+    } else {
+        match archive.read_text(path)? {
+            Some(raw) => {
+                let doc = parse_html(&raw);
+                sanitize(&doc);
+                doc
+            }
+            None => return Ok(Some(target)),
+        }
+    };
+    let id = percent_encoding::percent_decode_str(fragment).decode_utf8_lossy();
+    let Some(element) = by_id(&doc, &id) else {
+        return Ok(Some(target));
+    };
+    // Anchors like `<a id="fn1"/>` mark the start of a note rather than
+    // holding it.
+    let Some(note) = element
+        .inclusive_ancestors()
+        .find(|n| n.as_element().is_some_and(|el| is_block(&el.name.local)))
+    else {
+        return Ok(Some(target));
+    };
+
+    let backlink = backlink(&note, path, &item.full_path);
+    let is_note = noteref(&source, href)
+        || is_marked_note(&element)
+        || is_marked_note(&note)
+        || backlink.is_some();
+    if !is_note {
+        return Ok(Some(target));
+    }
+
+    rewrite_resources(&note, resource_base, path);
+    for node in note.inclusive_descendants() {
+        let Some(el) = node.as_element() else {
+            continue;
+        };
+        let mut attrs = el.attributes.borrow_mut();
+        attrs.remove("id");
+        if let Some(link) = attrs.get_mut("href") {
+            if !is_external(link.trim()) {
+                *link = book_href(&resolve_href(path, link.trim()));
+            }
+        }
+    }
+    target.note = Some(Note {
+        html: inner_html(&note),
+        backlink,
+    });
+    Ok(Some(target))
+}
+
+/// An archive path and fragment with [`ID_PREFIX`] taken off the fragment,
+/// so it names the id as the book does.
+fn book_href(href: &str) -> String {
+    match href.split_once('#') {
+        Some((path, fragment)) => {
+            let fragment = fragment.strip_prefix(ID_PREFIX).unwrap_or(fragment);
+            format!("{path}#{fragment}")
+        }
+        None => href.to_string(),
+    }
+}
+
+/// Whether an href has a URL scheme, as in `https:` or `mailto:`.
+fn is_external(href: &str) -> bool {
+    let end = href.find(['/', '#', '?']).unwrap_or(href.len());
+    href[..end].contains(':')
+}
+
+fn has_token(node: &NodeRef, attr: &str, values: &[&str]) -> bool {
+    let Some(el) = node.as_element() else {
+        return false;
+    };
+    let attrs = el.attributes.borrow();
+    attrs
+        .get(attr)
+        .is_some_and(|v| v.split_whitespace().any(|t| values.contains(&t)))
+}
+
+fn is_marked_note(node: &NodeRef) -> bool {
+    has_token(node, "epub:type", NOTE_TYPES) || has_token(node, "role", NOTE_ROLES)
+}
+
+/// Whether a link with exactly this `href` in the chapter is marked as a
+/// note reference.
+fn noteref(source: &NodeRef, href: &str) -> bool {
+    source.descendants().any(|n| {
+        let is_link = n.as_element().is_some_and(|el| {
+            &*el.name.local == "a"
+                && el.attributes.borrow().get("href").map(str::trim) == Some(href)
+        });
+        is_link
+            && (has_token(&n, "epub:type", &["noteref"]) || has_token(&n, "role", &["doc-noteref"]))
+    })
+}
+
+/// The link in a note back into `chapter_path`, resolved to an archive path.
+/// One marked as a back-reference wins over any other link to the chapter.
+fn backlink(note: &NodeRef, note_path: &str, chapter_path: &str) -> Option<String> {
+    let links: Vec<(NodeRef, String)> = note
+        .descendants()
+        .filter_map(|n| {
+            let el = n.as_element()?;
+            if &*el.name.local != "a" {
+                return None;
+            }
+            let href = el.attributes.borrow().get("href")?.trim().to_string();
+            if is_external(&href) {
+                return None;
+            }
+            let resolved = resolve_href(note_path, &href);
+            let (path, fragment) = resolved.split_once('#')?;
+            (path == chapter_path && !fragment.is_empty())
+                .then(|| (n.clone(), book_href(&resolved)))
+        })
+        .collect();
+    let marked = links.iter().find(|(n, _)| {
+        has_token(n, "epub:type", &["backlink"]) || has_token(n, "role", &["doc-backlink"])
+    });
+    marked.or(links.first()).map(|(_, href)| href.clone())
+}
@@ -0,0 +1,174 @@
+use roxmltree::Node;
+
+use super::{
+    metadata, parse_xml, resolve_href, BookError, Landmark, ManifestItem, Metadata, SpineItem,
+};
+
+pub struct Package {
+    pub version: String,
+    pub manifest: Vec<ManifestItem>,
+    pub spine: Vec<SpineItem>,
+    /// The `toc` attribute of `<spine>`, pointing at the NCX in EPUB 2.
+    pub toc_id: Option<String>,
+    /// The EPUB 2 `<meta name="cover">` item id.
+    pub cover_id: Option<String>,
+    pub metadata: Metadata,
+    /// The EPUB 2 `<guide>`, with types translated to EPUB 3 landmarks.
+    pub guide: Vec<Landmark>,
+    /// CFI step of `<spine>`: twice its position among the package's
+    /// child elements.
+    pub spine_step: usize,
+}
+
+pub fn rootfile_path(container_xml: &str) -> Result<String, BookError> {
+    let doc = parse_xml("META-INF/container.xml", container_xml)?;
+    doc.descendants()
+        .filter(|n| n.has_tag_name_local("rootfile"))
+        .find(|n| {
+            n.attribute("media-type")
+                .is_none_or(|t| t == "application/oebps-package+xml")
+        })
+        .and_then(|n| n.attribute("full-path"))
+        .filter(|p| !p.is_empty())
+        .map(|p| resolve_href("", p))
+        .ok_or(BookError::MissingRootfile)
+}
+
+pub fn parse_package(opf_path: &str, xml: &str) -> Result<Package, BookError> {
+    let doc = parse_xml(opf_path, xml)?;
+    let root = doc.root_element();
+    if !root.has_tag_name_local("package") {
+        return Err(BookError::Malformed {
+            path: opf_path.to_string(),
+            reason: format!("expected <package>, found <{}>", root.tag_name().name()),
+        });
+    }
+
+    let manifest_el = child(root, "manifest").ok_or_else(|| BookError::Malformed {
+        path: opf_path.to_string(),
+        reason: "missing <manifest>".into(),
+    })?;
+    let manifest: Vec<ManifestItem> = manifest_el
+        .children()
+        .filter(|n| n.has_tag_name_local("item"))
+        .filter_map(|item| {
+            let id = item.attribute("id")?;
+            let href = item.attribute("href")?;
+            Some(ManifestItem {
+                id: id.to_string(),
+                href: href.to_string(),
+                media_type: item.attribute("media-type").unwrap_or_default().to_string(),
+                properties: item
+                    .attribute("properties")
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+                full_path: resolve_href(opf_path, href),
+            })
+        })
+        .collect();
+
+    let spine_el = child(root, "spine");
+    let spine = spine_el
+        .into_iter()
+        .flat_map(|s| s.children())
+        .filter(|n| n.has_tag_name_local("itemref"))
+        .filter_map(|itemref| {
+            let idref = itemref.attribute("idref")?;
+            let item = manifest.iter().find(|m| m.id == idref)?;
+            Some(SpineItem {
+                id: item.id.clone(),
+                media_type: item.media_type.clone(),
+                full_path: item.full_path.clone(),
+                linear: itemref.attribute("linear") != Some("no"),
+            })
+        })
+        .collect();
+
+    let spine_step = root
+        .children()
+        .filter(Node::is_element)
+        .position(|n| n.has_tag_name_local("spine"))
+        .map_or(6, |i| (i + 1) * 2);
+
+    let unique_id = root.attribute("unique-identifier");
+    let metadata_el = child(root, "metadata");
+    let cover_id = metadata_el
+        .into_iter()
+        .flat_map(|m| m.children())
+        .find(|n| n.has_tag_name_local("meta") && n.attribute("name") == Some("cover"))
+        .and_then(|n| n.attribute("content"))
+        .map(str::to_string);
+    let metadata = metadata_el
+        .map(|m| metadata::parse(m, unique_id))
+        .unwrap_or_default();
+
+    let guide = child(root, "guide")
+        .into_iter()
+        .flat_map(|g| g.children())
+        .filter(|n| n.has_tag_name_local("reference"))
+        .filter_map(|reference| {
+            let kind = guide_kind(reference.attribute("type")?);
+            Some(Landmark {
+                label: reference
+                    .attribute("title")
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .unwrap_or(&kind)
+                    .to_string(),
+                href: resolve_href(opf_path, reference.attribute("href")?),
+                kind,
+            })
+        })
+        .collect();
+
+    Ok(Package {
+        version: root.attribute("version").unwrap_or("2.0").to_string(),
+        manifest,
+        spine,
+        toc_id: spine_el
+            .and_then(|s| s.attribute("toc"))
+            .map(str::to_string),
+        cover_id,
+        metadata,
+        guide,
+        spine_step,
+    })
+}
+
+/// The EPUB 3 semantic for an EPUB 2 guide type. Most are spelled the same;
+/// types are case-insensitive.
+fn guide_kind(kind: &str) -> String {
+    let kind = kind.trim().to_ascii_lowercase();
+    match kind.as_str() {
+        "text" => "bodymatter".into(),
+        "title-page" => "titlepage".into(),
+        _ => kind,
+    }
+}
+
+pub(super) fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|n| n.has_tag_name_local(name))
+}
+
+/// Concatenated, whitespace-collapsed text content, or `None` if empty.
+pub(super) fn text_of(node: Node) -> Option<String> {
+    let text: String = node
+        .descendants()
+        .filter(Node::is_text)
+        .filter_map(|n| n.text())
+        .collect();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+pub(super) trait LocalName {
+    fn has_tag_name_local(&self, name: &str) -> bool;
+}
+
+impl LocalName for Node<'_, '_> {
+    fn has_tag_name_local(&self, name: &str) -> bool {
+        self.is_element() && self.tag_name().name() == name
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use kuchikiki::NodeRef;
+use serde::Serialize;
+
+use super::cfi::{by_id, offset_before};
+use super::content::{body_of, parse_html};
+use super::{BookError, BookStructure, EpubArchive};
+
+/// Where a print page starts in the text the reader shows.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageMark {
+    pub label: String,
+    pub spine_index: usize,
+    /// Offset into the chapter's text content, in UTF-16 code units as the
+    /// DOM counts them.
+    pub offset: usize,
+}
+
+/// Places each entry of the page list in the text, in reading order.
+/// Entries pointing outside the spine, or at an id that isn't there, are
+/// left out.
+pub fn page_map(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+) -> Result<Vec<PageMark>, BookError> {
+    // Page lists run into the hundreds; parse each chapter once.
+    let mut docs: HashMap<usize, Option<NodeRef>> = HashMap::new();
+    let mut marks = Vec::new();
+    for target in &structure.page_list {
+        let (path, fragment) = match target.href.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (target.href.as_str(), None),
+        };
+        let Some(index) = structure.spine.iter().position(|s| s.full_path == path) else {
+            continue;
+        };
+        let doc = match docs.entry(index) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let raw = archive.read_text(path)?;
+                entry.insert(raw.map(|raw| parse_html(&raw)))
+            }
+        };
+        let Some(doc) = doc else {
+            continue;
+        };
+        let offset = match fragment {
+            Some(id) => match by_id(doc, id) {
+                Some(target) => offset_before(&body_of(doc), &target),
+                None => continue,
+            },
+            None => 0,
+        };
+        marks.push(PageMark {
+            label: target.label.clone(),
+            spine_index: index,
+            offset,
+        });
+    }
+    // Lists are meant to be in reading order, but that's not enforced.
+    marks.sort_by_key(|m| (m.spine_index, m.offset));
+    Ok(marks)
+}
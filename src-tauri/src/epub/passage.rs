@@ -0,0 +1,202 @@
+use serde::Serialize;
+
+use super::cfi::{by_id, offset_before, resolve_cfi};
+use super::content::{body_of, document_text, parse_html, BLOCK_SEPARATOR};
+use super::{BookError, BookStructure, Cfi, CfiLocation, EpubArchive, TocEntry, CONTENT_TYPES};
+
+/// Characters of text kept in an excerpt.
+const EXCERPT_LENGTH: usize = 160;
+
+/// A position in a book as a person would describe it: the chapter it's in
+/// and the text found there.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Passage {
+    pub location: CfiLocation,
+    /// Label of the table of contents entry the position falls under.
+    pub chapter_title: Option<String>,
+    /// The text of a range, or the text following a point, with whitespace
+    /// collapsed and long passages cut short.
+    pub excerpt: String,
+}
+
+pub fn passage_at(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    cfi: &Cfi,
+) -> Result<Passage, BookError> {
+    let location = resolve_cfi(archive, structure, cfi)?;
+    let item = &structure.spine[location.spine_index];
+    let raw = archive
+        .read_text(&item.full_path)?
+        .ok_or_else(|| BookError::MissingEntry(item.full_path.clone()))?;
+    Ok(Passage {
+        location,
+        chapter_title: chapter_title(structure, &raw, location),
+        excerpt: excerpt(&document_text(&raw), location),
+    })
+}
+
+/// Finds `text` in the book, ignoring differences in whitespace, for
+/// positions recorded by other readers that don't line up exactly with this
+/// one's. Of several occurrences, the one nearest `near` wins; without a
+/// hint, the first in the book.
+pub fn find_passage(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    text: &str,
+    near: Option<CfiLocation>,
+) -> Result<Option<CfiLocation>, BookError> {
+    let (needle, _) = squash(text);
+    let needle = needle.trim();
+    if needle.is_empty() {
+        return Ok(None);
+    }
+    // The hinted chapter first, then the rest in reading order.
+    let hinted = near.map(|n| n.spine_index);
+    let order = hinted
+        .into_iter()
+        .chain((0..structure.spine.len()).filter(|&i| Some(i) != hinted));
+    for index in order {
+        let Some(item) = structure.spine.get(index) else {
+            continue;
+        };
+        if !CONTENT_TYPES.contains(&item.media_type.as_str()) {
+            continue;
+        }
+        let Some(raw) = archive.read_text(&item.full_path)? else {
+            continue;
+        };
+        let (haystack, offsets) = squash(&document_text(&raw));
+        let target = near
+            .filter(|n| n.spine_index == index)
+            .map_or(0, |n| n.offset);
+        let found = haystack
+            .match_indices(needle)
+            .map(|(i, m)| (offsets[i], offsets[i + m.len()] - offsets[i]))
+            .min_by_key(|&(offset, _)| offset.abs_diff(target));
+        if let Some((offset, length)) = found {
+            return Ok(Some(CfiLocation {
+                spine_index: index,
+                offset,
+                length,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// The last TOC entry, in reading order, that starts at or before
+/// `location`. Entries with a fragment in the same chapter are placed by the
+/// element it names; chapters can span several files, so entries in earlier
+/// files count too.
+fn chapter_title(structure: &BookStructure, raw: &str, location: CfiLocation) -> Option<String> {
+    fn flatten<'a>(entries: &'a [TocEntry], out: &mut Vec<&'a TocEntry>) {
+        for entry in entries {
+            out.push(entry);
+            flatten(&entry.subitems, out);
+        }
+    }
+    let mut entries = Vec::new();
+    flatten(&structure.toc, &mut entries);
+
+    let doc = parse_html(raw);
+    let body = body_of(&doc);
+    let mut title = None;
+    for entry in entries {
+        let (path, fragment) = match entry.href.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (entry.href.as_str(), None),
+        };
+        let Some(index) = structure.spine.iter().position(|s| s.full_path == path) else {
+            continue;
+        };
+        let starts_before = match index.cmp(&location.spine_index) {
+            std::cmp::Ordering::Less => true,
+            std::cmp::Ordering::Greater => false,
+            std::cmp::Ordering::Equal => fragment
+                .and_then(|id| by_id(&doc, id))
+                .is_none_or(|target| offset_before(&body, &target) <= location.offset),
+        };
+        if starts_before && !entry.label.is_empty() {
+            title = Some(entry.label.clone());
+        }
+    }
+    title
+}
+
+fn excerpt(text: &str, location: CfiLocation) -> String {
+    // Offsets count UTF-16 code units and skip the block separators. Points
+    // take more text than an excerpt needs, since whitespace collapses.
+    let end = location.offset + location.length;
+    let mut units = 0;
+    let mut passage = String::new();
+    let mut taken = 0;
+    for c in text.chars() {
+        if c == BLOCK_SEPARATOR {
+            if taken > 0 {
+                passage.push(' ');
+            } else {
+                passage.clear();
+            }
+            continue;
+        }
+        if units >= location.offset {
+            if location.length > 0 && units >= end {
+                break;
+            }
+            if location.length == 0 && taken > EXCERPT_LENGTH * 4 {
+                break;
+            }
+            passage.push(c);
+            taken += 1;
+        } else if location.length == 0 && !c.is_whitespace() {
+            // Start a point's excerpt at the beginning of its word.
+            passage.push(c);
+        } else {
+            passage.clear();
+        }
+        units += c.len_utf16();
+    }
+
+    let mut excerpt = String::new();
+    let mut length = 0;
+    for word in passage.split_whitespace() {
+        let word_length = word.chars().count();
+        if length > 0 {
+            if length + 1 + word_length > EXCERPT_LENGTH {
+                excerpt.push('…');
+                break;
+            }
+            excerpt.push(' ');
+            length += 1;
+        }
+        excerpt.push_str(word);
+        length += word_length;
+    }
+    excerpt
+}
+
+/// Collapses whitespace and block separators to single spaces, along with
+/// the offset (as in [`CfiLocation`]) of every byte of the result, plus one
+/// for the end.
+fn squash(text: &str) -> (String, Vec<usize>) {
+    let mut out = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    let mut units = 0;
+    let mut in_space = false;
+    for c in text.chars() {
+        let space = c.is_whitespace() || c == BLOCK_SEPARATOR;
+        if !(space && in_space) {
+            let kept = if space { ' ' } else { c };
+            out.push(kept);
+            offsets.extend(std::iter::repeat_n(units, kept.len_utf8()));
+        }
+        in_space = space;
+        if c != BLOCK_SEPARATOR {
+            units += c.len_utf16();
+        }
+    }
+    offsets.push(units);
+    (out, offsets)
+}
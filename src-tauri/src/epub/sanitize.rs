@@ -0,0 +1,534 @@
+use kuchikiki::NodeRef;
+
+/// Elements removed along with everything in them. None of their text
+/// reaches the page, so text offsets skip it as well.
+const DROPPED: &[&str] = &[
+    "applet", "base", "embed", "frame", "frameset", "iframe", "link", "meta", "noembed",
+    "noframes", "noscript", "object", "param", "portal", "script", "style", "template", "title",
+];
+
+/// Elements kept as they are, from HTML, SVG and MathML. Anything else is
+/// replaced by its children, so its text stays in place.
+const ELEMENTS: &[&str] = &[
+    // Document structure.
+    "html",
+    "head",
+    "body",
+    // HTML.
+    "a",
+    "abbr",
+    "address",
+    "article",
+    "aside",
+    "audio",
+    "b",
+    "bdi",
+    "bdo",
+    "big",
+    "blockquote",
+    "br",
+    "caption",
+    "center",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "del",
+    "details",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "font",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "main",
+    "mark",
+    "nav",
+    "ol",
+    "p",
+    "picture",
+    "pre",
+    "q",
+    "rb",
+    "rp",
+    "rt",
+    "rtc",
+    "ruby",
+    "s",
+    "samp",
+    "section",
+    "small",
+    "source",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "time",
+    "tr",
+    "track",
+    "tt",
+    "u",
+    "ul",
+    "var",
+    "video",
+    "wbr",
+    // SVG, lowercased.
+    "circle",
+    "clippath",
+    "defs",
+    "desc",
+    "ellipse",
+    "g",
+    "image",
+    "line",
+    "lineargradient",
+    "marker",
+    "mask",
+    "path",
+    "pattern",
+    "polygon",
+    "polyline",
+    "radialgradient",
+    "rect",
+    "stop",
+    "svg",
+    "switch",
+    "symbol",
+    "text",
+    "textpath",
+    "tspan",
+    "use",
+    // MathML.
+    "annotation",
+    "maction",
+    "math",
+    "menclose",
+    "merror",
+    "mfenced",
+    "mfrac",
+    "mi",
+    "mmultiscripts",
+    "mn",
+    "mo",
+    "mover",
+    "mpadded",
+    "mphantom",
+    "mprescripts",
+    "mroot",
+    "mrow",
+    "ms",
+    "mspace",
+    "msqrt",
+    "mstyle",
+    "msub",
+    "msubsup",
+    "msup",
+    "mtable",
+    "mtd",
+    "mtext",
+    "mtr",
+    "munder",
+    "munderover",
+    "none",
+    "semantics",
+];
+
+/// Attributes kept on any allowed element, lowercased; `aria-*` is kept
+/// too. URL-valued attributes are checked separately.
+const ATTRIBUTES: &[&str] = &[
+    // HTML.
+    "abbr",
+    "align",
+    "alt",
+    "border",
+    "cellpadding",
+    "cellspacing",
+    "class",
+    "colspan",
+    "controls",
+    "datetime",
+    "default",
+    "dir",
+    "epub:type",
+    "headers",
+    "height",
+    "hidden",
+    "id",
+    "kind",
+    "label",
+    "lang",
+    "loop",
+    "muted",
+    "reversed",
+    "role",
+    "rowspan",
+    "scope",
+    "span",
+    "srclang",
+    "start",
+    "summary",
+    "title",
+    "type",
+    "valign",
+    "value",
+    "width",
+    "xml:lang",
+    // SVG presentation and geometry.
+    "clip-path",
+    "clip-rule",
+    "clippathunits",
+    "cx",
+    "cy",
+    "d",
+    "display",
+    "dominant-baseline",
+    "dx",
+    "dy",
+    "fill",
+    "fill-opacity",
+    "fill-rule",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "fx",
+    "fy",
+    "gradienttransform",
+    "gradientunits",
+    "lengthadjust",
+    "marker-end",
+    "marker-mid",
+    "marker-start",
+    "markerheight",
+    "markerunits",
+    "markerwidth",
+    "mask",
+    "maskunits",
+    "offset",
+    "opacity",
+    "orient",
+    "patterncontentunits",
+    "patterntransform",
+    "patternunits",
+    "points",
+    "preserveaspectratio",
+    "r",
+    "refx",
+    "refy",
+    "rotate",
+    "rx",
+    "ry",
+    "spreadmethod",
+    "startoffset",
+    "stop-color",
+    "stop-opacity",
+    "stroke",
+    "stroke-dasharray",
+    "stroke-dashoffset",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-miterlimit",
+    "stroke-opacity",
+    "stroke-width",
+    "text-anchor",
+    "textlength",
+    "transform",
+    "version",
+    "viewbox",
+    "visibility",
+    "x",
+    "x1",
+    "x2",
+    "y",
+    "y1",
+    "y2",
+    // MathML.
+    "accent",
+    "accentunder",
+    "close",
+    "columnalign",
+    "columnlines",
+    "columnspacing",
+    "columnspan",
+    "depth",
+    "displaystyle",
+    "encoding",
+    "fence",
+    "form",
+    "frame",
+    "largeop",
+    "linethickness",
+    "lspace",
+    "mathbackground",
+    "mathcolor",
+    "mathsize",
+    "mathvariant",
+    "maxsize",
+    "minsize",
+    "movablelimits",
+    "notation",
+    "open",
+    "rowalign",
+    "rowlines",
+    "rowspacing",
+    "rspace",
+    "scriptlevel",
+    "separator",
+    "separators",
+    "stretchy",
+    "symmetric",
+    "voffset",
+];
+
+/// Attributes holding a URL, kept only if [`safe_url`] accepts them.
+const URL_ATTRIBUTES: &[&str] = &["cite", "href", "poster", "src"];
+
+/// Attributes holding a list of ids.
+const ID_REFERENCES: &[&str] = &[
+    "aria-activedescendant",
+    "aria-controls",
+    "aria-describedby",
+    "aria-details",
+    "aria-errormessage",
+    "aria-flowto",
+    "aria-labelledby",
+    "aria-owns",
+    "headers",
+];
+
+/// Put in front of every id in a chapter and every reference to one. Ids
+/// and names become properties of `window` and `document`, so a book could
+/// otherwise shadow ones the app relies on, like `document.querySelector`,
+/// or take over the app's own elements' ids. `name` is removed outright.
+pub const ID_PREFIX: &str = "epub-";
+
+/// Strips a content document down to what the reader needs to display it:
+/// no scripts, event handlers, frames, plugins, style sheets or URLs that
+/// could reach outside the book, such as `javascript:` or `asset:`.
+/// Chapters are injected into the app's own page, where anything that runs
+/// could call the app's commands. Ids are prefixed with [`ID_PREFIX`].
+pub fn sanitize(doc: &NodeRef) {
+    let elements: Vec<NodeRef> = doc
+        .descendants()
+        .filter(|n| n.as_element().is_some())
+        .collect();
+    for node in elements {
+        let Some(el) = node.as_element() else {
+            continue;
+        };
+        let name = el.name.local.to_lowercase();
+        if DROPPED.contains(&name.as_str()) {
+            node.detach();
+            continue;
+        }
+        if !ELEMENTS.contains(&name.as_str()) {
+            let children: Vec<NodeRef> = node.children().collect();
+            for child in children {
+                node.insert_before(child);
+            }
+            node.detach();
+            continue;
+        }
+        el.attributes.borrow_mut().map.retain(|attr, value| {
+            let attr = attr.local.to_lowercase();
+            let keep = if URL_ATTRIBUTES.contains(&attr.as_str()) {
+                safe_url(&name, &value.value)
+            } else {
+                ATTRIBUTES.contains(&attr.as_str()) || attr.starts_with("aria-")
+            };
+            if keep {
+                prefix_ids(&attr, &mut value.value);
+            }
+            keep
+        });
+    }
+}
+
+/// Whether [`sanitize`] removes `node` together with its content.
+pub fn is_dropped(node: &NodeRef) -> bool {
+    node.as_element()
+        .is_some_and(|el| DROPPED.contains(&el.name.local.to_lowercase().as_str()))
+}
+
+/// Prefixes the ids an attribute sets or refers to: the element's own id,
+/// the fragment of a link within the book, id lists, and SVG paint and
+/// clip references like `url(#gradient)`.
+fn prefix_ids(attr: &str, value: &mut String) {
+    if attr == "id" {
+        value.insert_str(0, ID_PREFIX);
+    } else if attr == "href" {
+        if scheme(value).is_none() {
+            if let Some((path, fragment)) = value.split_once('#').filter(|(_, f)| !f.is_empty()) {
+                *value = format!("{path}#{ID_PREFIX}{fragment}");
+            }
+        }
+    } else if ID_REFERENCES.contains(&attr) {
+        let ids: Vec<String> = value
+            .split_whitespace()
+            .map(|id| format!("{ID_PREFIX}{id}"))
+            .collect();
+        *value = ids.join(" ");
+    } else if value.contains("url(") {
+        *value = prefix_url_references(value);
+    }
+}
+
+/// Prefixes the ids in `url(#id)` references, quoted or not.
+pub fn prefix_url_references(value: &str) -> String {
+    ["url(#", "url('#", "url(\"#"]
+        .iter()
+        .fold(value.to_string(), |out, open| {
+            out.replace(open, &format!("{open}{ID_PREFIX}"))
+        })
+}
+
+/// The lowercased scheme of a URL, if it has one.
+fn scheme(url: &str) -> Option<String> {
+    url.split_once(':')
+        .filter(|(scheme, _)| !scheme.contains(['/', '?', '#']))
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+}
+
+/// Links may leave the book for the web or email; everything else must
+/// stay inside it, apart from inline images.
+fn safe_url(element: &str, url: &str) -> bool {
+    // Browsers ignore tabs, newlines and leading spaces or controls in a
+    // URL, so `java\tscript:` is still `javascript:`, and read `\` as `/`.
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .map(|c| if c == '\\' { '/' } else { c })
+        .collect();
+    let scheme = scheme(&url);
+    let external = url.starts_with("//");
+    match (element, scheme.as_deref()) {
+        ("a", Some("http" | "https" | "mailto")) => true,
+        ("a" | "blockquote" | "q" | "del" | "ins", None) => true,
+        ("img" | "image", Some("data")) => url[5..].to_ascii_lowercase().starts_with("image/"),
+        (_, None) => !external,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::content::{body_of, inner_html, parse_html};
+    use super::*;
+
+    fn clean(body: &str) -> String {
+        let doc = parse_html(&format!("<html><body>{body}</body></html>"));
+        sanitize(&doc);
+        inner_html(&body_of(&doc))
+    }
+
+    #[test]
+    fn drops_scripts_and_styles() {
+        assert_eq!(clean("<p>a<script>alert(1)</script>b</p>"), "<p>ab</p>");
+        assert_eq!(clean("<style>p { color: red }</style><p>a</p>"), "<p>a</p>");
+        assert_eq!(
+            clean("<iframe src=\"x.html\">a</iframe><p>b</p>"),
+            "<p>b</p>"
+        );
+        assert_eq!(
+            clean("<p style=\"background: url(https://x)\">a</p>"),
+            "<p>a</p>"
+        );
+    }
+
+    #[test]
+    fn drops_event_handlers() {
+        assert_eq!(
+            clean("<img src=\"a.png\" onerror=\"alert(1)\" onload=\"alert(2)\">"),
+            "<img src=\"a.png\">"
+        );
+        assert_eq!(
+            clean("<svg onload=\"alert(1)\"><circle r=\"1\"></circle></svg>"),
+            "<svg><circle r=\"1\"></circle></svg>"
+        );
+    }
+
+    #[test]
+    fn drops_unsafe_urls() {
+        for href in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "java\tscript:alert(1)",
+            " javascript:alert(1)",
+            "asset://localhost/etc/passwd",
+            "data:text/html,<script>alert(1)</script>",
+        ] {
+            let html = clean(&format!("<a href=\"{href}\">a</a>"));
+            assert_eq!(html, "<a>a</a>", "{href}");
+        }
+        assert_eq!(clean("<img src=\"//evil.example/a.png\">"), "<img>");
+        assert_eq!(
+            clean("<a href=\"https://example.com\">a</a>"),
+            "<a href=\"https://example.com\">a</a>"
+        );
+        assert_eq!(
+            clean("<img src=\"data:image/png;base64,AA\">"),
+            "<img src=\"data:image/png;base64,AA\">"
+        );
+    }
+
+    #[test]
+    fn unwraps_unknown_elements() {
+        assert_eq!(clean("<p><blink>a</blink></p>"), "<p>a</p>");
+        assert_eq!(clean("<form><p>a</p></form>"), "<p>a</p>");
+    }
+
+    #[test]
+    fn prevents_clobbering() {
+        assert_eq!(clean("<img name=\"querySelector\">"), "<img>");
+        assert_eq!(
+            clean("<img id=\"querySelector\"><p id=\"root\">a</p>"),
+            "<img id=\"epub-querySelector\"><p id=\"epub-root\">a</p>"
+        );
+    }
+
+    #[test]
+    fn prefixes_id_references() {
+        assert_eq!(
+            clean("<a href=\"#n1\">1</a><a href=\"ch2.xhtml#n2\">2</a><a href=\"ch2.xhtml\">3</a>"),
+            "<a href=\"#epub-n1\">1</a><a href=\"ch2.xhtml#epub-n2\">2</a><a href=\"ch2.xhtml\">3</a>"
+        );
+        assert_eq!(
+            clean("<a href=\"https://example.com/#top\">a</a>"),
+            "<a href=\"https://example.com/#top\">a</a>"
+        );
+        assert_eq!(
+            clean("<table><tr><td headers=\"a b\" aria-describedby=\"c\">x</td></tr></table>"),
+            "<table><tbody><tr><td headers=\"epub-a epub-b\" aria-describedby=\"epub-c\">x</td></tr></tbody></table>"
+        );
+        assert_eq!(
+            clean("<svg><rect fill=\"url(#g)\" clip-path=\"url('#c')\"></rect></svg>"),
+            "<svg><rect fill=\"url(#epub-g)\" clip-path=\"url('#epub-c')\"></rect></svg>"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use roxmltree::Node;
+
+use super::opf::{child, text_of, LocalName};
+use super::{parse_xml, resolve_href, BookError, Landmark, PageTarget, TocEntry};
+
+const OPS_NS: &str = "http://www.idpf.org/2007/ops";
+
+pub fn parse_ncx(ncx_path: &str, xml: &str) -> Result<Vec<TocEntry>, BookError> {
+    let doc = parse_xml(ncx_path, xml)?;
+    let nav_map = doc.descendants().find(|n| n.has_tag_name_local("navMap"));
+    Ok(nav_map.map(|m| ncx_points(m, ncx_path)).unwrap_or_default())
+}
+
+fn ncx_points(parent: Node, ncx_path: &str) -> Vec<TocEntry> {
+    parent
+        .children()
+        .filter(|n| n.has_tag_name_local("navPoint"))
+        .map(|point| TocEntry {
+            label: child(point, "navLabel")
+                .and_then(|l| child(l, "text"))
+                .and_then(text_of)
+                .unwrap_or_default(),
+            href: child(point, "content")
+                .and_then(|c| c.attribute("src"))
+                .map(|src| resolve_href(ncx_path, src))
+                .unwrap_or_default(),
+            subitems: ncx_points(point, ncx_path),
+        })
+        .collect()
+}
+
+/// The NCX `pageList`, in the order given.
+pub fn parse_ncx_pages(ncx_path: &str, xml: &str) -> Result<Vec<PageTarget>, BookError> {
+    let doc = parse_xml(ncx_path, xml)?;
+    let Some(page_list) = doc.descendants().find(|n| n.has_tag_name_local("pageList")) else {
+        return Ok(Vec::new());
+    };
+    Ok(page_list
+        .children()
+        .filter(|n| n.has_tag_name_local("pageTarget"))
+        .filter_map(|target| {
+            let src = child(target, "content")?.attribute("src")?;
+            // The label is what's printed on the page; `value` is only a
+            // number, and missing for roman numerals.
+            let label = child(target, "navLabel")
+                .and_then(|l| child(l, "text"))
+                .and_then(text_of)
+                .or_else(|| target.attribute("value").map(str::to_string))?;
+            Some(PageTarget {
+                label,
+                href: resolve_href(ncx_path, src),
+            })
+        })
+        .collect())
+}
+
+pub fn parse_nav(nav_path: &str, xml: &str) -> Result<Vec<TocEntry>, BookError> {
+    let doc = parse_xml(nav_path, xml)?;
+    let navs: Vec<Node> = doc
+        .descendants()
+        .filter(|n| n.has_tag_name_local("nav"))
+        .collect();
+    let nav = navs
+        .iter()
+        .find(|n| has_epub_type(**n, "toc"))
+        .or(navs.first());
+    Ok(nav
+        .and_then(|n| n.descendants().find(|d| d.has_tag_name_local("ol")))
+        .map(|ol| nav_list(ol, nav_path))
+        .unwrap_or_default())
+}
+
+fn nav_list(ol: Node, nav_path: &str) -> Vec<TocEntry> {
+    ol.children()
+        .filter(|n| n.has_tag_name_local("li"))
+        .filter_map(|li| {
+            // Unlinked headings are written as <span> and only group children.
+            let label_el = child(li, "a").or_else(|| child(li, "span"))?;
+            Some(TocEntry {
+                label: text_of(label_el).unwrap_or_default(),
+                href: label_el
+                    .attribute("href")
+                    .map(|href| resolve_href(nav_path, href))
+                    .unwrap_or_default(),
+                subitems: child(li, "ol")
+                    .map(|sub| nav_list(sub, nav_path))
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// The `<nav epub:type="page-list">` of an EPUB 3 navigation document.
+pub fn parse_nav_pages(nav_path: &str, xml: &str) -> Result<Vec<PageTarget>, BookError> {
+    let doc = parse_xml(nav_path, xml)?;
+    let nav = doc
+        .descendants()
+        .find(|n| n.has_tag_name_local("nav") && has_epub_type(*n, "page-list"));
+    let Some(ol) = nav.and_then(|n| n.descendants().find(|d| d.has_tag_name_local("ol"))) else {
+        return Ok(Vec::new());
+    };
+    Ok(ol
+        .children()
+        .filter(|n| n.has_tag_name_local("li"))
+        .filter_map(|li| {
+            let a = child(li, "a")?;
+            Some(PageTarget {
+                label: text_of(a)?,
+                href: resolve_href(nav_path, a.attribute("href")?),
+            })
+        })
+        .collect())
+}
+
+/// The `<nav epub:type="landmarks">` of an EPUB 3 navigation document.
+pub fn parse_nav_landmarks(nav_path: &str, xml: &str) -> Result<Vec<Landmark>, BookError> {
+    let doc = parse_xml(nav_path, xml)?;
+    let nav = doc
+        .descendants()
+        .find(|n| n.has_tag_name_local("nav") && has_epub_type(*n, "landmarks"));
+    let Some(ol) = nav.and_then(|n| n.descendants().find(|d| d.has_tag_name_local("ol"))) else {
+        return Ok(Vec::new());
+    };
+    Ok(ol
+        .children()
+        .filter(|n| n.has_tag_name_local("li"))
+        .filter_map(|li| {
+            let a = child(li, "a")?;
+            let kind = a.attribute((OPS_NS, "type"))?.split_whitespace().next()?;
+            Some(Landmark {
+                kind: kind.to_string(),
+                label: text_of(a).unwrap_or_else(|| kind.to_string()),
+                href: resolve_href(nav_path, a.attribute("href")?),
+            })
+        })
+        .collect())
+}
+
+fn has_epub_type(node: Node, name: &str) -> bool {
+    node.attribute((OPS_NS, "type"))
+        .is_some_and(|t| t.split_whitespace().any(|t| t == name))
+}
@@ -0,0 +1,263 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::content::document_text;
+use super::{
+    read_structure, BookError, BookStructure, EpubArchive, BLOCK_SEPARATOR, CONTENT_TYPES,
+};
+
+/// A position in a book as the reader reports it: a spine index and an
+/// offset into that chapter's text, in UTF-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TextPosition {
+    pub index: usize,
+    pub offset: usize,
+}
+
+/// Size of a spine item's text. Items the reader doesn't show as chapters
+/// have none.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpineLength {
+    pub words: usize,
+    /// In UTF-16 code units as the DOM counts them, like offsets.
+    pub chars: usize,
+}
+
+/// Where each word of `text` starts, in UTF-16 code units as the DOM counts
+/// them. A word is a run of anything but whitespace from its first letter or
+/// digit on, except in scripts written without spaces between words, where
+/// each character counts as one.
+fn word_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut pos = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        if c == BLOCK_SEPARATOR {
+            in_word = false;
+            continue;
+        }
+        if c.is_whitespace() {
+            in_word = false;
+        } else if is_unspaced(c) || (!in_word && c.is_alphanumeric()) {
+            // Punctuation and combining marks after a character of an
+            // unspaced script belong to it.
+            in_word = true;
+            starts.push(pos);
+        }
+        pos += c.len_utf16();
+    }
+    starts
+}
+
+/// Chinese and Japanese ideographs and kana, and Thai and Lao letters, whose
+/// words aren't separated by spaces. Korean is.
+fn is_unspaced(c: char) -> bool {
+    matches!(c,
+        '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{3ffff}'
+        | '\u{3041}'..='\u{309f}'
+        | '\u{30a1}'..='\u{30fa}'
+        | '\u{30fc}'..='\u{30ff}'
+        | '\u{31f0}'..='\u{31ff}'
+        | '\u{ff66}'..='\u{ff9d}'
+        | '\u{0e01}'..='\u{0e30}'
+        | '\u{0e32}'..='\u{0e33}'
+        | '\u{0e40}'..='\u{0e46}'
+        | '\u{0e81}'..='\u{0eb0}'
+        | '\u{0eb2}'..='\u{0eb3}'
+        | '\u{0ebd}'..='\u{0ec6}'
+        | '\u{0edc}'..='\u{0edf}')
+}
+
+/// Number of words starting before `offset`.
+fn words_to(starts: &[usize], offset: usize) -> usize {
+    starts.partition_point(|&start| start < offset)
+}
+
+fn measure(text: &str) -> SpineLength {
+    SpineLength {
+        words: word_starts(text).len(),
+        chars: text
+            .chars()
+            .filter(|&c| c != BLOCK_SEPARATOR)
+            .map(char::len_utf16)
+            .sum(),
+    }
+}
+
+/// Text of a spine item, or `None` for items the reader doesn't show as
+/// chapters.
+fn chapter_text(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    index: usize,
+) -> Result<Option<String>, BookError> {
+    let item = structure
+        .spine
+        .get(index)
+        .ok_or(BookError::SpineIndexOutOfRange(index))?;
+    if !CONTENT_TYPES.contains(&item.media_type.as_str()) {
+        return Ok(None);
+    }
+    Ok(archive
+        .read_text(&item.full_path)?
+        .map(|raw| document_text(&raw)))
+}
+
+/// Length of every spine item, in spine order.
+pub fn read_lengths(path: &Path) -> Result<Vec<SpineLength>, BookError> {
+    let mut archive = EpubArchive::open(path)?;
+    let structure = read_structure(&mut archive)?;
+    (0..structure.spine.len())
+        .map(|index| {
+            let text = chapter_text(&mut archive, &structure, index)?;
+            Ok(text.map(|text| measure(&text)).unwrap_or_default())
+        })
+        .collect()
+}
+
+/// Where the words of an open book's chapters start, read from each chapter
+/// the first time it's needed. Counting words read on every heartbeat would
+/// otherwise re-read and re-parse the chapter each time.
+#[derive(Default)]
+pub struct WordIndex {
+    chapters: HashMap<usize, Option<Vec<usize>>>,
+}
+
+impl WordIndex {
+    /// Word starts of a spine item, or `None` for items the reader doesn't
+    /// show as chapters.
+    fn chapter(
+        &mut self,
+        archive: &mut EpubArchive,
+        structure: &BookStructure,
+        index: usize,
+    ) -> Result<Option<&[usize]>, BookError> {
+        let starts = match self.chapters.entry(index) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let text = chapter_text(archive, structure, index)?;
+                entry.insert(text.map(|text| word_starts(&text)))
+            }
+        };
+        Ok(starts.as_deref())
+    }
+
+    /// Words in a chapter before a position in it.
+    pub fn words_before(
+        &mut self,
+        archive: &mut EpubArchive,
+        structure: &BookStructure,
+        at: TextPosition,
+    ) -> Result<usize, BookError> {
+        let starts = self.chapter(archive, structure, at.index)?;
+        Ok(starts.map_or(0, |starts| words_to(starts, at.offset)))
+    }
+
+    /// Words read going from `from` to `to`, or 0 if `to` is not further on.
+    pub fn words_between(
+        &mut self,
+        archive: &mut EpubArchive,
+        structure: &BookStructure,
+        from: TextPosition,
+        to: TextPosition,
+    ) -> Result<usize, BookError> {
+        if to <= from {
+            return Ok(0);
+        }
+        let mut words = 0;
+        for index in from.index..=to.index {
+            let Some(starts) = self.chapter(archive, structure, index)? else {
+                continue;
+            };
+            let end = if index == to.index {
+                words_to(starts, to.offset)
+            } else {
+                starts.len()
+            };
+            let start = if index == from.index {
+                words_to(starts, from.offset)
+            } else {
+                0
+            };
+            words += end.saturating_sub(start);
+        }
+        Ok(words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_epub, ScratchDir};
+
+    fn words_in(text: &str) -> usize {
+        word_starts(text).len()
+    }
+
+    #[test]
+    fn counts_words_between_spaces() {
+        assert_eq!(words_in("The cat\u{a0}sat, on\tthe mat."), 6);
+        assert_eq!(words_in("well-known — “quoted” words"), 3);
+        assert_eq!(words_in(&format!("end{BLOCK_SEPARATOR}start")), 2);
+        assert_eq!(words_in("  — …  "), 0);
+    }
+
+    #[test]
+    fn counts_unspaced_scripts_by_character() {
+        assert_eq!(words_in("我爱读书。"), 4);
+        assert_eq!(words_in("「本を読む」"), 4);
+        assert_eq!(words_in("カタカナ"), 4);
+        // Vowel and tone marks don't count apart from their letter.
+        assert_eq!(words_in("ภาษาไทย"), 7);
+        assert_eq!(words_in("สวัสดี"), 4);
+        assert_eq!(words_in("한국어 단어"), 2);
+        assert_eq!(words_in("iPhoneを買った"), 5);
+    }
+
+    #[test]
+    fn counts_words_before_an_offset() {
+        let starts = word_starts("One two 三四 five");
+        assert_eq!(starts, [0, 4, 8, 9, 11]);
+        assert_eq!(words_to(&starts, 0), 0);
+        assert_eq!(words_to(&starts, 1), 1);
+        assert_eq!(words_to(&starts, 4), 1);
+        assert_eq!(words_to(&starts, 9), 3);
+        assert_eq!(words_to(&starts, usize::MAX), 5);
+        // Offsets are in UTF-16 code units.
+        assert_eq!(word_starts("𠀀 a"), [0, 3]);
+    }
+
+    #[test]
+    fn counts_words_read_across_chapters() {
+        let dir = ScratchDir::new("words-between");
+        let path = dir.join("book.epub");
+        write_epub(
+            &path,
+            "Words",
+            &["<p>one two three</p>", "<p>four five</p>"],
+        );
+        let mut archive = EpubArchive::open(&path).unwrap();
+        let structure = read_structure(&mut archive).unwrap();
+        let mut index = WordIndex::default();
+        let at = |index, offset| TextPosition { index, offset };
+
+        assert_eq!(
+            index
+                .words_before(&mut archive, &structure, at(0, 4))
+                .unwrap(),
+            1
+        );
+        let read = index.words_between(&mut archive, &structure, at(0, 4), at(1, 5));
+        assert_eq!(read.unwrap(), 3);
+        let back = index.words_between(&mut archive, &structure, at(1, 5), at(0, 4));
+        assert_eq!(back.unwrap(), 0);
+        assert_eq!(index.chapters.len(), 2);
+    }
+}
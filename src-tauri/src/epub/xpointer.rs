@@ -0,0 +1,171 @@
+use super::cfi::offset_before;
+use super::content::{body_of, parse_html};
+use super::{BookError, BookStructure, CfiLocation, EpubArchive};
+
+/// A position as KOReader's crengine records it, e.g.
+/// `/body/DocFragment[12]/body/div/p[3]/text().15`: a spine item, the
+/// elements down from its `body`, and a character offset into a text node.
+struct XPointer {
+    spine_index: usize,
+    /// Element names with their 1-based index among same-named siblings.
+    steps: Vec<(String, usize)>,
+    /// 1-based index of the text node within the last element, if any.
+    text: Option<usize>,
+    /// Unicode characters into the text node.
+    offset: usize,
+}
+
+/// Resolves a KOReader XPointer to a position in the book. `None` when the
+/// pointer is malformed or the document has no such node, as happens with
+/// a different edition of the book.
+pub fn resolve_xpointer(
+    archive: &mut EpubArchive,
+    structure: &BookStructure,
+    xpointer: &str,
+) -> Result<Option<CfiLocation>, BookError> {
+    let Some(pointer) = parse(xpointer) else {
+        return Ok(None);
+    };
+    let Some(item) = structure.spine.get(pointer.spine_index) else {
+        return Ok(None);
+    };
+    let Some(raw) = archive.read_text(&item.full_path)? else {
+        return Ok(None);
+    };
+    let doc = parse_html(&raw);
+    let body = body_of(&doc);
+
+    let mut node = body.clone();
+    for (name, index) in &pointer.steps {
+        let child = node
+            .children()
+            .filter(|c| {
+                c.as_element()
+                    .is_some_and(|el| str::eq_ignore_ascii_case(&el.name.local, name))
+            })
+            .nth(index - 1);
+        match child {
+            Some(child) => node = child,
+            None => return Ok(None),
+        }
+    }
+    let offset = match pointer.text {
+        None => offset_before(&body, &node),
+        Some(index) => {
+            // crengine drops whitespace-only text between elements, so those
+            // nodes don't count towards the index.
+            let Some(text) = node
+                .children()
+                .filter(|c| c.as_text().is_some_and(|t| !t.borrow().trim().is_empty()))
+                .nth(index - 1)
+            else {
+                return Ok(None);
+            };
+            let within: usize = text
+                .as_text()
+                .map(|t| {
+                    t.borrow()
+                        .chars()
+                        .take(pointer.offset)
+                        .map(char::len_utf16)
+                        .sum()
+                })
+                .unwrap_or_default();
+            offset_before(&body, &text) + within
+        }
+    };
+    Ok(Some(CfiLocation {
+        spine_index: pointer.spine_index,
+        offset,
+        length: 0,
+    }))
+}
+
+fn parse(xpointer: &str) -> Option<XPointer> {
+    let rest = xpointer.trim().strip_prefix("/body/DocFragment[")?;
+    let (fragment, rest) = rest.split_once(']')?;
+    let spine_index = fragment.parse::<usize>().ok()?.checked_sub(1)?;
+    let (path, offset) = match rest.rsplit_once('.') {
+        Some((path, offset))
+            if !offset.is_empty() && offset.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            (path, offset.parse().ok()?)
+        }
+        _ => (rest, 0),
+    };
+
+    let mut segments = path.split('/').filter(|s| !s.is_empty());
+    // The fragment wraps the document's own body.
+    if segments.next().is_some_and(|s| s != "body") {
+        return None;
+    }
+    let mut steps = Vec::new();
+    let mut text = None;
+    for segment in segments {
+        if text.is_some() {
+            return None;
+        }
+        let (name, index) = match segment.split_once('[') {
+            Some((name, index)) => (name, index.strip_suffix(']')?.parse::<usize>().ok()?),
+            None => (segment, 1),
+        };
+        if index == 0 {
+            return None;
+        }
+        if name == "text()" {
+            text = Some(index);
+        } else {
+            steps.push((name.to_string(), index));
+        }
+    }
+    Some(XPointer {
+        spine_index,
+        steps,
+        text,
+        offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_xpointers() {
+        let p = parse("/body/DocFragment[12]/body/div/p[3]/text().15").unwrap();
+        assert_eq!(p.spine_index, 11);
+        assert_eq!(p.steps, [("div".to_string(), 1), ("p".to_string(), 3)]);
+        assert_eq!(p.text, Some(1));
+        assert_eq!(p.offset, 15);
+
+        let p = parse(" /body/DocFragment[1]/body/section[2]/p.0 ").unwrap();
+        assert_eq!(p.spine_index, 0);
+        assert_eq!(p.steps, [("section".to_string(), 2), ("p".to_string(), 1)]);
+        assert_eq!(p.text, None);
+        assert_eq!(p.offset, 0);
+
+        let p = parse("/body/DocFragment[2]/body/p[4]/text()[2]").unwrap();
+        assert_eq!(p.text, Some(2));
+        assert_eq!(p.offset, 0);
+
+        let p = parse("/body/DocFragment[3]").unwrap();
+        assert!(p.steps.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_xpointers() {
+        for bad in [
+            "",
+            "/body/p[1]",
+            "/body/DocFragment[0]/body/p",
+            "/body/DocFragment[x]/body/p",
+            "/body/DocFragment[1/body/p",
+            "/body/DocFragment[1]/html/p",
+            "/body/DocFragment[1]/body/p[0]",
+            "/body/DocFragment[1]/body/p[2",
+            "/body/DocFragment[1]/body/p/text()/span",
+        ] {
+            assert!(parse(bad).is_none(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::io;
+
+use serde::Serialize;
+use zip::result::ZipError;
+
+use crate::annotation_import::ImportError;
+use crate::covers::CoverError;
+use crate::epub::{BookError, CfiError, DrmScheme};
+use crate::export::ExportError;
+use crate::library::{
+    AccessError, AddError, AnnotationError, BookmarkError, SearchError, StatsError,
+};
+
+/// What a command failed with, as the frontend receives it: `code` and any
+/// details from [`ErrorKind`], flattened, plus a message to show.
+#[derive(Debug, thiserror::Error, Serialize)]
+#[error("{message}")]
+pub struct CommandError {
+    #[serde(flatten)]
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+/// The error codes the frontend decides what to offer by. They are part of
+/// its contract with the backend: add new ones rather than renaming these.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(
+    tag = "code",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ErrorKind {
+    /// A file isn't where it was, typically a book moved or deleted outside
+    /// the app.
+    NotFound,
+    PermissionDenied,
+    /// Any other failure to read or write a file.
+    Io,
+    /// Not an EPUB that can be read: a broken zip, or a container or package
+    /// document that is missing or malformed.
+    InvalidArchive,
+    /// The package document the container points at isn't in the archive.
+    MissingOpf,
+    DrmProtected {
+        scheme: DrmScheme,
+    },
+    /// No book, annotation or bookmark has the id given.
+    UnknownId,
+    /// The command needs the book opened with `open_book` first.
+    BookNotOpen,
+    /// A spine index past the end of the book.
+    OutOfRange,
+    /// A CFI that is malformed or doesn't lead anywhere in the book.
+    InvalidCfi,
+    /// A file picked for annotation import that KOReader or Calibre didn't
+    /// write.
+    InvalidAnnotationFile,
+    /// A book can't be moved to a file that is another book's.
+    PathTaken,
+    /// Imported annotations belong to a book that isn't in the library.
+    NoMatchingBook,
+    /// Imported annotations don't say which book they belong to, so they
+    /// have to be imported from that book.
+    BookNotNamed,
+    LegacyImportClosed,
+    /// A book's text hasn't been indexed for search yet; that happens in the
+    /// background.
+    NotIndexed,
+    Database,
+    /// A folder couldn't be watched for changes.
+    Watch,
+    /// A cover couldn't be decoded or resized.
+    Image,
+}
+
+impl CommandError {
+    pub fn new(kind: ErrorKind, message: impl ToString) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn book_not_open() -> Self {
+        Self::new(ErrorKind::BookNotOpen, "Book is not open")
+    }
+
+    pub fn unknown_book() -> Self {
+        Self::new(ErrorKind::UnknownId, AccessError::UnknownBook)
+    }
+}
+
+fn io_kind(error: &io::Error) -> ErrorKind {
+    match error.kind() {
+        io::ErrorKind::NotFound => ErrorKind::NotFound,
+        io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+        _ => ErrorKind::Io,
+    }
+}
+
+fn db_kind(error: &rusqlite::Error) -> ErrorKind {
+    match error {
+        rusqlite::Error::QueryReturnedNoRows => ErrorKind::UnknownId,
+        _ => ErrorKind::Database,
+    }
+}
+
+fn book_kind(error: &BookError) -> ErrorKind {
+    match error {
+        BookError::Io(e) | BookError::Zip(ZipError::Io(e)) => io_kind(e),
+        BookError::Zip(_)
+        | BookError::MissingContainer
+        | BookError::MissingRootfile
+        | BookError::Malformed { .. }
+        | BookError::MissingEntry(_)
+        | BookError::EntryTooLarge(_) => ErrorKind::InvalidArchive,
+        BookError::MissingOpf(_) => ErrorKind::MissingOpf,
+        BookError::SpineIndexOutOfRange(_) => ErrorKind::OutOfRange,
+        BookError::DrmProtected { scheme } => ErrorKind::DrmProtected { scheme: *scheme },
+        BookError::Cfi(_) => ErrorKind::InvalidCfi,
+    }
+}
+
+impl From<io::Error> for CommandError {
+    fn from(error: io::Error) -> Self {
+        Self::new(io_kind(&error), error)
+    }
+}
+
+impl From<rusqlite::Error> for CommandError {
+    fn from(error: rusqlite::Error) -> Self {
+        Self::new(db_kind(&error), error)
+    }
+}
+
+impl From<notify::Error> for CommandError {
+    fn from(error: notify::Error) -> Self {
+        let kind = match &error.kind {
+            notify::ErrorKind::Io(e) => io_kind(e),
+            notify::ErrorKind::PathNotFound => ErrorKind::NotFound,
+            _ => ErrorKind::Watch,
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<BookError> for CommandError {
+    fn from(error: BookError) -> Self {
+        Self::new(book_kind(&error), error)
+    }
+}
+
+impl From<CfiError> for CommandError {
+    fn from(error: CfiError) -> Self {
+        Self::new(ErrorKind::InvalidCfi, error)
+    }
+}
+
+impl From<AccessError> for CommandError {
+    fn from(error: AccessError) -> Self {
+        let kind = match &error {
+            AccessError::UnknownBook => ErrorKind::UnknownId,
+            AccessError::LegacyImportClosed => ErrorKind::LegacyImportClosed,
+            AccessError::Db(e) => db_kind(e),
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<AddError> for CommandError {
+    fn from(error: AddError) -> Self {
+        let kind = match &error {
+            AddError::Io(e) => io_kind(e),
+            AddError::Book(e) => book_kind(e),
+            AddError::Db(e) => db_kind(e),
+            AddError::PathTaken(_) => ErrorKind::PathTaken,
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<SearchError> for CommandError {
+    fn from(error: SearchError) -> Self {
+        let kind = match &error {
+            SearchError::Book(e) => book_kind(e),
+            SearchError::Io(e) => io_kind(e),
+            SearchError::Db(e) => db_kind(e),
+            SearchError::NotIndexed => ErrorKind::NotIndexed,
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<StatsError> for CommandError {
+    fn from(error: StatsError) -> Self {
+        let kind = match &error {
+            StatsError::Book(e) => book_kind(e),
+            StatsError::Db(e) => db_kind(e),
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<AnnotationError> for CommandError {
+    fn from(error: AnnotationError) -> Self {
+        let kind = match &error {
+            AnnotationError::Cfi(_) => ErrorKind::InvalidCfi,
+            AnnotationError::Db(e) => db_kind(e),
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<BookmarkError> for CommandError {
+    fn from(error: BookmarkError) -> Self {
+        let kind = match &error {
+            BookmarkError::Book(e) => book_kind(e),
+            BookmarkError::Cfi(_) => ErrorKind::InvalidCfi,
+            BookmarkError::Db(e) => db_kind(e),
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<ImportError> for CommandError {
+    fn from(error: ImportError) -> Self {
+        let kind = match &error {
+            ImportError::Io(e) => io_kind(e),
+            ImportError::Json(_) | ImportError::Lua(_) => ErrorKind::InvalidAnnotationFile,
+            ImportError::Book(e) => book_kind(e),
+            ImportError::Annotation(AnnotationError::Cfi(_)) => ErrorKind::InvalidCfi,
+            ImportError::Annotation(AnnotationError::Db(e)) | ImportError::Db(e) => db_kind(e),
+            ImportError::NoMatchingBook => ErrorKind::NoMatchingBook,
+            ImportError::BookNotNamed => ErrorKind::BookNotNamed,
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<ExportError> for CommandError {
+    fn from(error: ExportError) -> Self {
+        let kind = match &error {
+            ExportError::Db(e) => db_kind(e),
+            // Serializing plain structs can't really fail.
+            ExportError::Json(_) => ErrorKind::Io,
+            ExportError::Io(e) => io_kind(e),
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<CoverError> for CommandError {
+    fn from(error: CoverError) -> Self {
+        let kind = match &error {
+            CoverError::Book(e) => book_kind(e),
+            CoverError::Image(_) => ErrorKind::Image,
+            CoverError::Io(e) => io_kind(e),
+        };
+        Self::new(kind, error)
+    }
+}
@@ -0,0 +1,338 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::epub::{self, Cfi, EpubArchive};
+use crate::library::{Annotation, Book, Library};
+use crate::timestamp;
+
+const CSV_HEADER: &[&str] = &[
+    "book", "author", "chapter", "text", "note", "color", "tags", "cfi", "created", "updated",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Annotation store error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("Failed to encode export: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Failed to write export: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Json => "json",
+            Self::Csv => "csv",
+        }
+    }
+
+    /// Name for the save dialog's file type filter.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Markdown => "Markdown",
+            Self::Json => "JSON",
+            Self::Csv => "CSV",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookAnnotations {
+    pub book_id: String,
+    pub title: String,
+    pub author: String,
+    pub annotations: Vec<ExportedAnnotation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedAnnotation {
+    #[serde(flatten)]
+    pub annotation: Annotation,
+    pub chapter_title: Option<String>,
+}
+
+/// Annotations of one book, or of every annotated book sorted by title, in
+/// reading order with the chapters they're in.
+pub fn collect(
+    library: &Library,
+    book_id: Option<&str>,
+) -> Result<Vec<BookAnnotations>, ExportError> {
+    let books = match book_id {
+        Some(id) => library.get(id)?.into_iter().collect(),
+        None => library.list()?,
+    };
+    let mut out = Vec::new();
+    for book in books {
+        let annotations = library.annotations(&book.id)?;
+        if annotations.is_empty() && book_id.is_none() {
+            continue;
+        }
+        let titles = chapter_titles(&book, &annotations);
+        out.push(BookAnnotations {
+            book_id: book.id,
+            title: book.title,
+            author: book.author,
+            annotations: annotations
+                .into_iter()
+                .zip(titles)
+                .map(|(annotation, chapter_title)| ExportedAnnotation {
+                    annotation,
+                    chapter_title,
+                })
+                .collect(),
+        });
+    }
+    out.sort_by_cached_key(|b| b.title.to_lowercase());
+    Ok(out)
+}
+
+/// Chapter title for each annotation. A book that can't be read any more
+/// (moved, deleted) still exports, just without chapters.
+fn chapter_titles(book: &Book, annotations: &[Annotation]) -> Vec<Option<String>> {
+    let opened = EpubArchive::open(Path::new(&book.path)).and_then(|mut archive| {
+        let structure = epub::read_structure(&mut archive)?;
+        Ok((archive, structure))
+    });
+    let Ok((mut archive, structure)) = opened else {
+        return vec![None; annotations.len()];
+    };
+    annotations
+        .iter()
+        .map(|a| {
+            let cfi: Cfi = a.cfi.parse().ok()?;
+            epub::passage_at(&mut archive, &structure, &cfi)
+                .ok()?
+                .chapter_title
+        })
+        .collect()
+}
+
+/// Suggested file name for an export of `books`.
+pub fn file_name(books: &[BookAnnotations], single_book: bool, format: ExportFormat) -> String {
+    let stem = match books.first() {
+        Some(book) if single_book => {
+            let title: String = book
+                .title
+                .chars()
+                .map(|c| match c {
+                    '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                    c => c,
+                })
+                .collect();
+            format!("{} - Annotations", title.trim())
+        }
+        _ => "Annotations".to_string(),
+    };
+    format!("{stem}.{}", format.extension())
+}
+
+pub fn write(
+    path: &Path,
+    format: ExportFormat,
+    books: &[BookAnnotations],
+) -> Result<(), ExportError> {
+    let contents = match format {
+        ExportFormat::Markdown => markdown(books),
+        ExportFormat::Json => serde_json::to_string_pretty(books)?,
+        ExportFormat::Csv => csv(books),
+    };
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+/// A section per book, with a heading whenever the chapter changes.
+fn markdown(books: &[BookAnnotations]) -> String {
+    let mut out = String::new();
+    for book in books {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("# {}\n", book.title));
+        if !book.author.is_empty() {
+            out.push_str(&format!("\n*{}*\n", book.author));
+        }
+        let mut chapter = None;
+        for entry in &book.annotations {
+            if entry.chapter_title.is_some() && entry.chapter_title != chapter {
+                chapter = entry.chapter_title.clone();
+                out.push_str(&format!(
+                    "\n## {}\n",
+                    chapter.as_deref().unwrap_or_default()
+                ));
+            }
+            let a = &entry.annotation;
+            if let Some(text) = a.text.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+                out.push('\n');
+                for line in text.lines().map(str::trim) {
+                    if line.is_empty() {
+                        out.push_str(">\n");
+                    } else {
+                        out.push_str(&format!("> {line}\n"));
+                    }
+                }
+            }
+            if let Some(note) = &a.note {
+                out.push_str(&format!("\n{}\n", note.trim()));
+            }
+            if !a.tags.is_empty() {
+                out.push_str(&format!("\n*Tags: {}*\n", a.tags.join(", ")));
+            }
+        }
+    }
+    out
+}
+
+/// One row per annotation, following RFC 4180.
+fn csv(books: &[BookAnnotations]) -> String {
+    let mut out = String::new();
+    push_row(&mut out, CSV_HEADER);
+    for book in books {
+        for entry in &book.annotations {
+            let a = &entry.annotation;
+            let tags = a.tags.join("; ");
+            let created = timestamp::iso_utc(a.created_at);
+            let updated = timestamp::iso_utc(a.updated_at);
+            push_row(
+                &mut out,
+                &[
+                    &book.title,
+                    &book.author,
+                    entry.chapter_title.as_deref().unwrap_or_default(),
+                    a.text.as_deref().unwrap_or_default(),
+                    a.note.as_deref().unwrap_or_default(),
+                    &a.color,
+                    &tags,
+                    &a.cfi,
+                    &created,
+                    &updated,
+                ],
+            );
+        }
+    }
+    out
+}
+
+/// Appends a row. Cells a spreadsheet would read as a formula get a leading
+/// `'`, so a highlight starting with `=` can't run anything when opened.
+fn push_row(out: &mut String, fields: &[&str]) {
+    let fields: Vec<Cow<str>> = fields
+        .iter()
+        .map(|f| {
+            let f = if f.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+                Cow::Owned(format!("'{f}"))
+            } else {
+                Cow::Borrowed(*f)
+            };
+            if f.contains([',', '"', '\n', '\r']) {
+                Cow::Owned(format!("\"{}\"", f.replace('"', "\"\"")))
+            } else {
+                f
+            }
+        })
+        .collect();
+    out.push_str(&fields.join(","));
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(annotations: Vec<(Option<&str>, Annotation)>) -> BookAnnotations {
+        BookAnnotations {
+            book_id: "book".into(),
+            title: "Middlemarch".into(),
+            author: "George Eliot".into(),
+            annotations: annotations
+                .into_iter()
+                .map(|(chapter, annotation)| ExportedAnnotation {
+                    annotation,
+                    chapter_title: chapter.map(Into::into),
+                })
+                .collect(),
+        }
+    }
+
+    fn highlight(text: &str, note: Option<&str>) -> Annotation {
+        Annotation {
+            id: "a".into(),
+            book_id: "book".into(),
+            cfi: "epubcfi(/6/4!/4/2,/1:0,/1:5)".into(),
+            text: Some(text.into()),
+            note: note.map(Into::into),
+            color: "yellow".into(),
+            tags: Vec::new(),
+            created_at: 1_714_555_800_000,
+            updated_at: 1_714_555_800_000,
+        }
+    }
+
+    /// The cells of the first row after the header.
+    fn first_row(csv: &str) -> &str {
+        let (_, rows) = csv.split_once("\r\n").unwrap();
+        rows.strip_suffix("\r\n").unwrap()
+    }
+
+    #[test]
+    fn quotes_csv_cells() {
+        let books = [book(vec![(None, highlight("Plain, \"quoted\"", None))])];
+        assert_eq!(
+            first_row(&csv(&books)),
+            "Middlemarch,George Eliot,,\"Plain, \"\"quoted\"\"\",,yellow,,\
+             \"epubcfi(/6/4!/4/2,/1:0,/1:5)\",2024-05-01T09:30:00Z,2024-05-01T09:30:00Z"
+        );
+    }
+
+    #[test]
+    fn keeps_newlines_inside_csv_cells() {
+        let books = [book(vec![(None, highlight("one\ntwo", Some("a\r\nb")))])];
+        let csv = csv(&books);
+        assert!(first_row(&csv).starts_with("Middlemarch,George Eliot,,\"one\ntwo\",\"a\r\nb\","));
+    }
+
+    #[test]
+    fn escapes_csv_formulas() {
+        let mut annotation = highlight("=HYPERLINK(\"http://x\")", Some("+1"));
+        annotation.tags = vec!["-x".into()];
+        annotation.color = "@yellow".into();
+        let books = [book(vec![(Some("\tTab"), annotation)])];
+        assert!(first_row(&csv(&books)).starts_with(
+            "Middlemarch,George Eliot,'\tTab,\"'=HYPERLINK(\"\"http://x\"\")\",'+1,'@yellow,'-x,"
+        ));
+        let books = [book(vec![(None, highlight("\r", None))])];
+        assert!(first_row(&csv(&books)).contains(",\"'\r\","));
+    }
+
+    #[test]
+    fn writes_markdown() {
+        let mut tagged = highlight("Second", Some(" A note. "));
+        tagged.tags = vec!["love".into(), "money".into()];
+        let books = [book(vec![
+            (
+                Some("Prelude"),
+                highlight("First line\n\n  second line", None),
+            ),
+            (Some("Book I"), tagged),
+            (Some("Book I"), highlight("   ", Some("Only a note"))),
+        ])];
+        assert_eq!(
+            markdown(&books),
+            "# Middlemarch\n\n*George Eliot*\n\n## Prelude\n\n> First line\n>\n> second line\n\
+             \n## Book I\n\n> Second\n\nA note.\n\n*Tags: love, money*\n\nOnly a note\n"
+        );
+    }
+}
@@ -1,17 +1,643 @@
-use tauri::Manager;
+mod annotation_import;
+mod books;
+mod covers;
+mod epub;
+mod error;
+mod export;
+mod library;
+mod protocol;
+#[cfg(test)]
+mod test_support;
+mod timestamp;
 
+use std::path::{Path, PathBuf};
+
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_dialog::{DialogExt, FilePath};
+
+use annotation_import::AnnotationImport;
+use books::{OpenBooks, OpenedBook};
+use covers::{CoverCache, ThumbnailSize};
+use error::{CommandError, ErrorKind};
+use export::ExportFormat;
+use library::{
+    Annotation, AnnotationUpdate, Book, BookLength, BookUpdate, Bookmark, FsChange, Heartbeat,
+    ImportSummary, LegacyBook, Library, LibraryChange, LibraryWatcher, NewAnnotation,
+    ReadingSession, ReadingStats, SearchHit, TimeLeft,
+};
+
+const IMPORT_PROGRESS_EVENT: &str = "library://import-progress";
+const LIBRARY_CHANGED_EVENT: &str = "library://changed";
+
+#[tauri::command]
+fn parse_epub(
+    book_id: String,
+    library: State<'_, Library>,
+) -> Result<epub::BookStructure, CommandError> {
+    let path = library.book_path(&book_id)?;
+    Ok(epub::parse(&path)?)
+}
+
+#[tauri::command]
+async fn read_metadata(
+    book_id: String,
+    library: State<'_, Library>,
+) -> Result<epub::Metadata, CommandError> {
+    let path = library.book_path(&book_id)?;
+    Ok(epub::read_metadata(&path)?)
+}
+
+#[tauri::command]
+async fn open_book(
+    book_id: String,
+    library: State<'_, Library>,
+    books: State<'_, OpenBooks>,
+) -> Result<OpenedBook, CommandError> {
+    let path = library.book_path(&book_id)?;
+    Ok(books.open(&book_id, &path)?)
+}
+
+#[tauri::command]
+async fn get_spine_item(
+    book_id: String,
+    index: usize,
+    books: State<'_, OpenBooks>,
+) -> Result<epub::Chapter, CommandError> {
+    let book = books
+        .get(&book_id)
+        .ok_or_else(CommandError::book_not_open)?;
+    let mut book = book.lock().unwrap();
+    let book = &mut *book;
+    let resource_base = protocol::base_url(&book_id);
+    Ok(epub::load_chapter(
+        &mut book.archive,
+        &book.structure,
+        index,
+        &resource_base,
+    )?)
+}
+
+/// CFI of a position in an open book, given as a chapter and an offset into
+/// its text content; a range CFI when `length` is given.
+#[tauri::command]
+async fn cfi_at(
+    book_id: String,
+    index: usize,
+    offset: usize,
+    length: Option<usize>,
+    books: State<'_, OpenBooks>,
+) -> Result<String, CommandError> {
+    let book = books
+        .get(&book_id)
+        .ok_or_else(CommandError::book_not_open)?;
+    let mut book = book.lock().unwrap();
+    let book = &mut *book;
+    let length = length.unwrap_or(0);
+    Ok(
+        epub::cfi_at(&mut book.archive, &book.structure, index, offset, length)
+            .map(|cfi| cfi.to_string())?,
+    )
+}
+
+#[tauri::command]
+async fn resolve_cfi(
+    book_id: String,
+    cfi: String,
+    books: State<'_, OpenBooks>,
+) -> Result<epub::CfiLocation, CommandError> {
+    let cfi: epub::Cfi = cfi.parse()?;
+    let book = books
+        .get(&book_id)
+        .ok_or_else(CommandError::book_not_open)?;
+    let mut book = book.lock().unwrap();
+    let book = &mut *book;
+    Ok(epub::resolve_cfi(&mut book.archive, &book.structure, &cfi)?)
+}
+
+/// Where each print page in the book's page list starts.
+#[tauri::command]
+async fn page_map(
+    book_id: String,
+    books: State<'_, OpenBooks>,
+) -> Result<Vec<epub::PageMark>, CommandError> {
+    let book = books
+        .get(&book_id)
+        .ok_or_else(CommandError::book_not_open)?;
+    let mut book = book.lock().unwrap();
+    let book = &mut *book;
+    Ok(epub::page_map(&mut book.archive, &book.structure)?)
+}
+
+/// Where a link in a chapter of an open book leads, with the note it points
+/// at if it's a footnote or endnote. `None` for links out of the book.
+#[tauri::command]
+async fn resolve_link(
+    book_id: String,
+    index: usize,
+    href: String,
+    books: State<'_, OpenBooks>,
+) -> Result<Option<epub::LinkTarget>, CommandError> {
+    let book = books
+        .get(&book_id)
+        .ok_or_else(CommandError::book_not_open)?;
+    let mut book = book.lock().unwrap();
+    let book = &mut *book;
+    let resource_base = protocol::base_url(&book_id);
+    Ok(epub::resolve_link(
+        &mut book.archive,
+        &book.structure,
+        index,
+        &href,
+        &resource_base,
+    )?)
+}
+
+#[tauri::command]
+fn close_book(book_id: String, books: State<'_, OpenBooks>) {
+    books.close(&book_id);
+}
+
+#[tauri::command]
+fn library_list(library: State<'_, Library>) -> Result<Vec<Book>, CommandError> {
+    Ok(library.list()?)
+}
+
+/// Adds an EPUB the user picks to the library, or marks it opened if it is
+/// already there. Returns `None` if they cancelled.
+#[tauri::command]
+async fn library_open_file(
+    app: AppHandle,
+    library: State<'_, Library>,
+) -> Result<Option<Book>, CommandError> {
+    let Some(path) = pick_epub(&app, "Open book")? else {
+        return Ok(None);
+    };
+    let book = library::add_file(&library, &path)?;
+    index_in_background(&app);
+    Ok(Some(book))
+}
+
+/// Asks where a book whose file went missing is now, and points it there.
+/// Returns `None` if the user cancelled.
+#[tauri::command]
+async fn library_locate(
+    id: String,
+    app: AppHandle,
+    library: State<'_, Library>,
+) -> Result<Option<Book>, CommandError> {
+    let book = library.get(&id)?.ok_or_else(CommandError::unknown_book)?;
+    let Some(path) = pick_epub(&app, &format!("Locate “{}”", book.title))? else {
+        return Ok(None);
+    };
+    let book = library::locate_file(&library, &id, &path)?;
+    index_in_background(&app);
+    Ok(Some(book))
+}
+
+/// Moves the library kept in localStorage by older builds into the store.
+#[tauri::command]
+async fn library_import_legacy(
+    books: Vec<LegacyBook>,
+    library: State<'_, Library>,
+) -> Result<Vec<Book>, CommandError> {
+    Ok(library.import_legacy(books)?)
+}
+
+#[tauri::command]
+fn library_update(
+    id: String,
+    updates: BookUpdate,
+    library: State<'_, Library>,
+) -> Result<Book, CommandError> {
+    Ok(library.update(&id, updates)?)
+}
+
+/// Path of a cover thumbnail, for loading through the asset protocol.
+#[tauri::command]
+async fn library_cover(
+    id: String,
+    size: ThumbnailSize,
+    library: State<'_, Library>,
+    covers: State<'_, CoverCache>,
+) -> Result<Option<String>, CommandError> {
+    let book = library.get(&id)?.ok_or_else(CommandError::unknown_book)?;
+    let hash = match book.content_hash {
+        Some(hash) => hash,
+        None => {
+            let hash = library::hash_file(Path::new(&book.path))?;
+            library.set_content_hash(&id, &hash)?;
+            hash
+        }
+    };
+    let thumbnail = covers.thumbnail(Path::new(&book.path), &hash, size)?;
+    Ok(thumbnail.map(|p| p.to_string_lossy().into_owned()))
+}
+
+#[tauri::command]
+fn library_remove(id: String, library: State<'_, Library>) -> Result<(), CommandError> {
+    Ok(library.remove(&id)?)
+}
+
+/// Imports the EPUBs in a folder the user picks, once. Returns `None` if
+/// they cancelled.
+#[tauri::command]
+async fn import_directory(
+    recursive: bool,
+    app: AppHandle,
+    library: State<'_, Library>,
+) -> Result<Option<ImportSummary>, CommandError> {
+    let Some(path) = pick_folder(&app, "Import folder")? else {
+        return Ok(None);
+    };
+    let summary = library::import_directory(&library, &path, recursive, |progress| {
+        let _ = app.emit(IMPORT_PROGRESS_EVENT, progress);
+    })?;
+    index_in_background(&app);
+    Ok(Some(summary))
+}
+
+#[tauri::command]
+async fn search_book(
+    book_id: String,
+    query: String,
+    library: State<'_, Library>,
+) -> Result<Vec<SearchHit>, CommandError> {
+    library
+        .get(&book_id)?
+        .ok_or_else(CommandError::unknown_book)?;
+    Ok(library.search_book(&book_id, &query)?)
+}
+
+#[tauri::command]
+async fn search_library(
+    query: String,
+    library: State<'_, Library>,
+) -> Result<Vec<SearchHit>, CommandError> {
+    Ok(library.search_library(&query)?)
+}
+
+#[tauri::command]
+fn annotation_add(
+    annotation: NewAnnotation,
+    library: State<'_, Library>,
+) -> Result<Annotation, CommandError> {
+    Ok(library.add_annotation(annotation)?)
+}
+
+#[tauri::command]
+fn annotation_update(
+    id: String,
+    updates: AnnotationUpdate,
+    library: State<'_, Library>,
+) -> Result<Annotation, CommandError> {
+    Ok(library.update_annotation(&id, updates)?)
+}
+
+#[tauri::command]
+fn annotation_remove(id: String, library: State<'_, Library>) -> Result<(), CommandError> {
+    Ok(library.remove_annotation(&id)?)
+}
+
+#[tauri::command]
+fn annotation_list(
+    book_id: String,
+    library: State<'_, Library>,
+) -> Result<Vec<Annotation>, CommandError> {
+    Ok(library.annotations(&book_id)?)
+}
+
+/// Every annotation in the library, optionally only those tagged `tag`.
+#[tauri::command]
+fn annotation_list_all(
+    tag: Option<String>,
+    library: State<'_, Library>,
+) -> Result<Vec<Annotation>, CommandError> {
+    Ok(library.all_annotations(tag.as_deref())?)
+}
+
+/// Exports one book's annotations, or the whole library's, to a file the
+/// user picks. Returns the path written, or `None` if they cancelled.
+#[tauri::command]
+async fn export_annotations(
+    book_id: Option<String>,
+    format: ExportFormat,
+    app: AppHandle,
+    library: State<'_, Library>,
+) -> Result<Option<String>, CommandError> {
+    let books = export::collect(&library, book_id.as_deref())?;
+    let picked = app
+        .dialog()
+        .file()
+        .set_title("Export annotations")
+        .set_file_name(export::file_name(&books, book_id.is_some(), format))
+        .add_filter(format.label(), &[format.extension()])
+        .blocking_save_file();
+    let Some(path) = picked else {
+        return Ok(None);
+    };
+    let path = local_path(path)?;
+    export::write(&path, format, &books)?;
+    Ok(Some(path.to_string_lossy().into_owned()))
+}
+
+/// Imports highlights, notes and bookmarks from a KOReader sidecar or a
+/// Calibre annotations file picked in a dialog: into `book_id` when given,
+/// otherwise into the library book the file belongs to.
 #[tauri::command]
-fn read_file_bytes(path: String) -> Result<Vec<u8>, String> {
-    std::fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))
+async fn import_annotations(
+    book_id: Option<String>,
+    app: AppHandle,
+    library: State<'_, Library>,
+) -> Result<Option<AnnotationImport>, CommandError> {
+    let picked = app
+        .dialog()
+        .file()
+        .set_title("Import annotations")
+        .add_filter("KOReader or Calibre annotations", &["lua", "json"])
+        .blocking_pick_file();
+    let Some(path) = picked else {
+        return Ok(None);
+    };
+    let path = local_path(path)?;
+    Ok(annotation_import::import_file(&library, &path, book_id.as_deref()).map(Some)?)
+}
+
+/// Bookmarks a position in a book. Without a name, the bookmark is named
+/// after its chapter.
+#[tauri::command]
+async fn bookmark_add(
+    book_id: String,
+    cfi: String,
+    name: Option<String>,
+    library: State<'_, Library>,
+) -> Result<Bookmark, CommandError> {
+    Ok(library.add_bookmark(&book_id, &cfi, name.as_deref())?)
+}
+
+#[tauri::command]
+fn bookmark_list(
+    book_id: String,
+    library: State<'_, Library>,
+) -> Result<Vec<Bookmark>, CommandError> {
+    Ok(library.bookmarks(&book_id)?)
+}
+
+#[tauri::command]
+fn bookmark_remove(id: String, library: State<'_, Library>) -> Result<(), CommandError> {
+    Ok(library.remove_bookmark(&id)?)
+}
+
+/// Records that the reader is still reading, returning the session to pass
+/// along with the next heartbeat.
+#[tauri::command]
+async fn reading_heartbeat(
+    heartbeat: Heartbeat,
+    library: State<'_, Library>,
+    books: State<'_, OpenBooks>,
+) -> Result<ReadingSession, CommandError> {
+    let book = books
+        .get(&heartbeat.book_id)
+        .ok_or_else(CommandError::book_not_open)?;
+    let mut book = book.lock().unwrap();
+    let book = &mut *book;
+    Ok(library.record_heartbeat(
+        &heartbeat,
+        &mut book.archive,
+        &book.structure,
+        &mut book.words,
+    )?)
+}
+
+#[tauri::command]
+fn reading_sessions(
+    book_id: String,
+    library: State<'_, Library>,
+) -> Result<Vec<ReadingSession>, CommandError> {
+    Ok(library.reading_sessions(&book_id)?)
+}
+
+/// Reading totals, with days counted in the caller's time zone, given as
+/// minutes ahead of UTC.
+#[tauri::command]
+fn reading_stats(
+    utc_offset: i64,
+    library: State<'_, Library>,
+) -> Result<ReadingStats, CommandError> {
+    Ok(library.reading_stats(utc_offset)?)
+}
+
+/// Words and characters in each spine item, for positions that don't
+/// depend on the layout.
+#[tauri::command]
+async fn book_length(
+    book_id: String,
+    library: State<'_, Library>,
+) -> Result<BookLength, CommandError> {
+    Ok(library.book_length(&book_id)?)
+}
+
+/// Estimated time to finish the chapter and the book from a position in an
+/// open book.
+#[tauri::command]
+async fn reading_time_left(
+    book_id: String,
+    index: usize,
+    offset: usize,
+    library: State<'_, Library>,
+    books: State<'_, OpenBooks>,
+) -> Result<TimeLeft, CommandError> {
+    let book = books
+        .get(&book_id)
+        .ok_or_else(CommandError::book_not_open)?;
+    let mut book = book.lock().unwrap();
+    let book = &mut *book;
+    let at = epub::TextPosition { index, offset };
+    Ok(library.time_left(
+        &book_id,
+        &mut book.archive,
+        &book.structure,
+        &mut book.words,
+        at,
+    )?)
+}
+
+#[tauri::command]
+fn library_roots(library: State<'_, Library>) -> Result<Vec<String>, CommandError> {
+    Ok(library.roots()?)
+}
+
+/// Starts watching a folder the user picks and imports what is already in
+/// it. Returns `None` if they cancelled.
+#[tauri::command]
+async fn library_add_root(
+    app: AppHandle,
+    library: State<'_, Library>,
+    watcher: State<'_, LibraryWatcher>,
+) -> Result<Option<ImportSummary>, CommandError> {
+    let Some(path) = pick_folder(&app, "Watch folder")? else {
+        return Ok(None);
+    };
+    library.add_root(&path.to_string_lossy())?;
+    watcher.watch(&path)?;
+    let summary = library::import_directory(&library, &path, true, |progress| {
+        let _ = app.emit(IMPORT_PROGRESS_EVENT, progress);
+    })?;
+
+    let added = summary
+        .imported
+        .iter()
+        .map(|book| LibraryChange::Added { book: book.clone() });
+    let moved = summary.relocated.iter().map(|r| LibraryChange::Moved {
+        book: r.book.clone(),
+        from: r.from.clone(),
+    });
+    let changes: Vec<_> = added.chain(moved).collect();
+    if !changes.is_empty() {
+        let _ = app.emit(LIBRARY_CHANGED_EVENT, changes);
+    }
+    index_in_background(&app);
+    Ok(Some(summary))
+}
+
+/// Stops watching a folder. Books already imported from it stay.
+#[tauri::command]
+fn library_remove_root(
+    path: String,
+    library: State<'_, Library>,
+    watcher: State<'_, LibraryWatcher>,
+) -> Result<(), CommandError> {
+    library.remove_root(&path)?;
+    // The folder may already be gone, which drops the watch anyway.
+    let _ = watcher.unwatch(Path::new(&path));
+    Ok(())
+}
+
+fn pick_epub(app: &AppHandle, title: &str) -> Result<Option<PathBuf>, CommandError> {
+    let picked = app
+        .dialog()
+        .file()
+        .set_title(title)
+        .add_filter("EPUB Files", &["epub"])
+        .blocking_pick_file();
+    picked.map(local_path).transpose()
+}
+
+fn pick_folder(app: &AppHandle, title: &str) -> Result<Option<PathBuf>, CommandError> {
+    let picked = app.dialog().file().set_title(title).blocking_pick_folder();
+    picked.map(local_path).transpose()
+}
+
+/// The file system path of a file picked in a dialog. Only mobile platforms
+/// hand out URIs that aren't paths.
+fn local_path(path: FilePath) -> Result<PathBuf, CommandError> {
+    path.into_path()
+        .map_err(|e| CommandError::new(ErrorKind::Io, e))
+}
+
+/// Brings the search index up to date with the library, so that searches
+/// don't have to. Only new and changed books are read.
+fn index_in_background(app: &AppHandle) {
+    let handle = app.clone();
+    std::thread::spawn(move || {
+        let _ = handle.state::<Library>().index_all();
+    });
+}
+
+/// Watches every library root, and rescans them in the background to pick
+/// up whatever changed while the app was closed.
+fn watch_library_roots(app: &AppHandle) -> Result<LibraryWatcher, Box<dyn std::error::Error>> {
+    let handle = app.clone();
+    let watcher = LibraryWatcher::start(move |changes| {
+        let library = handle.state::<Library>();
+        let changes: Vec<_> = changes
+            .iter()
+            .flat_map(|change| library::reconcile(&library, change))
+            .collect();
+        if !changes.is_empty() {
+            let _ = handle.emit(LIBRARY_CHANGED_EVENT, changes);
+            index_in_background(&handle);
+        }
+    })?;
+
+    let roots = app.state::<Library>().roots()?;
+    for root in &roots {
+        // Roots on unplugged drives are kept; they are picked up next launch.
+        let _ = watcher.watch(Path::new(root));
+    }
+    let handle = app.clone();
+    std::thread::spawn(move || {
+        let library = handle.state::<Library>();
+        for root in roots {
+            let changes = library::reconcile(&library, &FsChange::Touched(root.into()));
+            if !changes.is_empty() {
+                let _ = handle.emit(LIBRARY_CHANGED_EVENT, changes);
+            }
+        }
+        // Get the search index up to date before anyone searches.
+        let _ = library.index_all();
+    });
+    Ok(watcher)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
-        .plugin(tauri_plugin_fs::init())
-        .invoke_handler(tauri::generate_handler![read_file_bytes])
+        .manage(OpenBooks::default())
+        .register_asynchronous_uri_scheme_protocol(protocol::SCHEME, |ctx, request, responder| {
+            let app = ctx.app_handle().clone();
+            std::thread::spawn(move || {
+                let books = app.state::<OpenBooks>();
+                responder.respond(protocol::handle(&books, &request));
+            });
+        })
+        .invoke_handler(tauri::generate_handler![
+            parse_epub,
+            read_metadata,
+            open_book,
+            get_spine_item,
+            cfi_at,
+            resolve_cfi,
+            page_map,
+            resolve_link,
+            close_book,
+            library_list,
+            library_open_file,
+            library_locate,
+            library_import_legacy,
+            library_update,
+            library_cover,
+            library_remove,
+            import_directory,
+            search_book,
+            search_library,
+            annotation_add,
+            annotation_update,
+            annotation_remove,
+            annotation_list,
+            annotation_list_all,
+            export_annotations,
+            import_annotations,
+            bookmark_add,
+            bookmark_list,
+            bookmark_remove,
+            reading_heartbeat,
+            reading_sessions,
+            reading_stats,
+            reading_time_left,
+            book_length,
+            library_roots,
+            library_add_root,
+            library_remove_root
+        ])
         .setup(|app| {
+            let data_dir = app.path().app_data_dir()?;
+            std::fs::create_dir_all(&data_dir)?;
+            app.manage(Library::open(&data_dir.join("library.sqlite3"))?);
+            app.manage(CoverCache::new(app.path().app_cache_dir()?.join("covers"))?);
+            let watcher = watch_library_roots(app.handle())?;
+            app.manage(watcher);
+
             let window = app.get_webview_window("main").unwrap();
             let rgba = include_bytes!("../icons/icon.png");
             let img = image::load_from_memory(rgba).unwrap().to_rgba8();
@@ -0,0 +1,379 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::{hash_file, Book, BookUpdate, Library, NewBook};
+
+/// Set in the `flags` table once the localStorage library has had its
+/// chance to be imported.
+const LEGACY_IMPORTED: &str = "legacy_imported";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AccessError {
+    #[error("Book not found")]
+    UnknownBook,
+    #[error("The old library has already been carried over and can't be imported again")]
+    LegacyImportClosed,
+    #[error("Library store error: {0}")]
+    Db(#[from] rusqlite::Error),
+}
+
+/// A book from the localStorage library kept by builds before the SQLite
+/// store.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyBook {
+    pub path: String,
+    pub title: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub progress: f64,
+    pub current_cfi: Option<String>,
+    pub last_opened: Option<i64>,
+}
+
+impl Library {
+    /// The file of library book `id`. Commands name books by these opaque
+    /// ids, never by path, so the webview can only reach files that were
+    /// picked in a dialog or found under a library root.
+    pub fn book_path(&self, id: &str) -> Result<PathBuf, AccessError> {
+        let book = self.get(id)?.ok_or(AccessError::UnknownBook)?;
+        Ok(PathBuf::from(book.path))
+    }
+
+    /// Whether `path` is inside one of the library roots once symlinks and
+    /// `..` are resolved. A path that doesn't exist is judged by its parent.
+    pub fn is_under_root(&self, path: &Path) -> rusqlite::Result<bool> {
+        let Some(path) = resolve(path) else {
+            return Ok(false);
+        };
+        Ok(self
+            .roots()?
+            .iter()
+            .filter_map(|root| Path::new(root).canonicalize().ok())
+            .any(|root| path.starts_with(root)))
+    }
+
+    /// Carries over the localStorage library. Its paths come from the
+    /// webview rather than a dialog, so this is only allowed once, into an
+    /// empty library, as the frontend does on every launch until it's done:
+    /// the first launch after upgrading, before any book content has been
+    /// shown. Later calls with nothing to import are no-ops.
+    pub fn import_legacy(&self, books: Vec<LegacyBook>) -> Result<Vec<Book>, AccessError> {
+        let closed: bool = {
+            let conn = self.conn.lock().unwrap();
+            let closed = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM flags WHERE name = ?1)
+                     OR EXISTS(SELECT 1 FROM books)",
+                [LEGACY_IMPORTED],
+                |row| row.get(0),
+            )?;
+            conn.execute(
+                "INSERT OR IGNORE INTO flags (name) VALUES (?1)",
+                [LEGACY_IMPORTED],
+            )?;
+            closed
+        };
+        if books.is_empty() {
+            return Ok(Vec::new());
+        }
+        if closed {
+            return Err(AccessError::LegacyImportClosed);
+        }
+        let mut imported = Vec::new();
+        for legacy in books {
+            // Books that have gone missing since are kept, to be relocated
+            // if their file turns up again.
+            let hash = hash_file(Path::new(&legacy.path)).ok();
+            let book = NewBook {
+                path: legacy.path,
+                title: legacy.title,
+                author: legacy.author,
+            };
+            let book = self.add(book, hash.as_deref())?;
+            let update = BookUpdate {
+                progress: Some(legacy.progress),
+                current_cfi: legacy.current_cfi,
+                last_opened: legacy.last_opened,
+                ..BookUpdate::default()
+            };
+            imported.push(self.update(&book.id, update)?);
+        }
+        Ok(imported)
+    }
+}
+
+/// `path` with symlinks and `..` resolved, or for a path that doesn't exist,
+/// its parent's resolved path joined with its file name.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Some(path);
+    }
+    let name = path.file_name()?;
+    Some(path.parent()?.canonicalize().ok()?.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tauri::http::{Request, StatusCode};
+
+    use super::*;
+    use crate::books::OpenBooks;
+    use crate::library::{import_directory, reconcile, FsChange};
+    use crate::protocol;
+    use crate::test_support::{write_epub, ScratchDir};
+
+    /// A scratch directory holding a library root and a folder outside it,
+    /// each with one book, and the library store.
+    struct Fixture {
+        root: PathBuf,
+        outside: PathBuf,
+        library: Library,
+        dir: ScratchDir,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = ScratchDir::new(&format!("access-{name}"));
+            let root = dir.join("library");
+            let outside = dir.join("private");
+            fs::create_dir_all(&root).unwrap();
+            fs::create_dir_all(&outside).unwrap();
+            write_epub(&root.join("inside.epub"), "Inside", &["<p>Inside</p>"]);
+            write_epub(&outside.join("secret.epub"), "Secret", &["<p>Secret</p>"]);
+            fs::write(outside.join("secret.txt"), "secret").unwrap();
+            let library = Library::open(&dir.join("library.sqlite3")).unwrap();
+            library.add_root(&root.to_string_lossy()).unwrap();
+            Self {
+                root,
+                outside,
+                library,
+                dir,
+            }
+        }
+
+        fn paths(&self) -> Vec<String> {
+            self.library
+                .list()
+                .unwrap()
+                .into_iter()
+                .map(|b| b.path)
+                .collect()
+        }
+    }
+
+    #[cfg(unix)]
+    fn symlink(target: &Path, link: &Path) {
+        std::os::unix::fs::symlink(target, link).unwrap();
+    }
+
+    #[test]
+    fn ids_never_resolve_to_arbitrary_paths() {
+        let fx = Fixture::new("ids");
+        let book = import_directory(&fx.library, &fx.root, true, |_| {})
+            .unwrap()
+            .imported
+            .remove(0);
+        let secret = fx.outside.join("secret.epub");
+        let secret = secret.to_string_lossy();
+        for id in [
+            "",
+            "..",
+            "../private/secret.epub",
+            "../../../../etc/passwd",
+            "/etc/passwd",
+            "%2e%2e%2fprivate%2fsecret.epub",
+            "..\\private\\secret.epub",
+            &*secret,
+            book.path.as_str(),
+        ] {
+            assert!(
+                matches!(fx.library.book_path(id), Err(AccessError::UnknownBook)),
+                "{id:?} resolved"
+            );
+        }
+        assert_eq!(
+            fx.library.book_path(&book.id).unwrap(),
+            PathBuf::from(&book.path)
+        );
+    }
+
+    #[test]
+    fn paths_outside_roots_are_rejected() {
+        let fx = Fixture::new("roots");
+        let lib = &fx.library;
+        assert!(lib.is_under_root(&fx.root.join("inside.epub")).unwrap());
+        assert!(lib.is_under_root(&fx.root.join("not-yet.epub")).unwrap());
+        assert!(!lib.is_under_root(&fx.outside.join("secret.epub")).unwrap());
+        assert!(!lib
+            .is_under_root(&fx.root.join("../private/secret.epub"))
+            .unwrap());
+        assert!(!lib
+            .is_under_root(&fx.root.join("missing/../../private/secret.epub"))
+            .unwrap());
+        assert!(!lib.is_under_root(Path::new("/etc/passwd")).unwrap());
+
+        // A sibling whose name merely starts with the root's.
+        let sibling = fx.dir.join("library-other");
+        fs::create_dir_all(&sibling).unwrap();
+        write_epub(&sibling.join("other.epub"), "Other", &["<p>Other</p>"]);
+        assert!(!lib.is_under_root(&sibling.join("other.epub")).unwrap());
+
+        #[cfg(unix)]
+        {
+            let link = fx.root.join("link.epub");
+            symlink(&fx.outside.join("secret.epub"), &link);
+            assert!(!lib.is_under_root(&link).unwrap());
+            symlink(&fx.outside, &fx.root.join("private"));
+            assert!(!lib
+                .is_under_root(&fx.root.join("private/secret.epub"))
+                .unwrap());
+        }
+    }
+
+    #[test]
+    fn folder_import_stays_inside_the_folder() {
+        let fx = Fixture::new("import");
+        #[cfg(unix)]
+        {
+            symlink(&fx.outside.join("secret.epub"), &fx.root.join("link.epub"));
+            symlink(&fx.outside, &fx.root.join("linked-folder"));
+        }
+        let summary = import_directory(&fx.library, &fx.root, true, |_| {}).unwrap();
+        assert_eq!(summary.imported.len(), 1);
+        assert_eq!(
+            fx.paths(),
+            [fx.root.join("inside.epub").to_string_lossy().into_owned()]
+        );
+    }
+
+    #[test]
+    fn watcher_changes_outside_roots_are_ignored() {
+        let fx = Fixture::new("watch");
+        for path in [
+            fx.outside.join("secret.epub"),
+            fx.outside.clone(),
+            fx.root.join("../private/secret.epub"),
+            fx.root.join(".."),
+        ] {
+            let changes = reconcile(&fx.library, &FsChange::Touched(path.clone()));
+            assert!(changes.is_empty(), "{path:?} was imported");
+        }
+        let changes = reconcile(
+            &fx.library,
+            &FsChange::Renamed {
+                from: fx.root.join("gone.epub"),
+                to: fx.outside.join("secret.epub"),
+            },
+        );
+        assert!(changes.is_empty());
+        assert!(fx.paths().is_empty());
+
+        let inside = fx.root.join("inside.epub");
+        assert_eq!(reconcile(&fx.library, &FsChange::Touched(inside)).len(), 1);
+    }
+
+    #[test]
+    fn legacy_import_only_once_into_an_empty_library() {
+        let fx = Fixture::new("legacy");
+        let legacy = |path: &Path| LegacyBook {
+            path: path.to_string_lossy().into_owned(),
+            title: "Legacy".into(),
+            author: String::new(),
+            progress: 0.5,
+            current_cfi: None,
+            last_opened: None,
+        };
+        let imported = fx
+            .library
+            .import_legacy(vec![legacy(&fx.root.join("inside.epub"))])
+            .unwrap();
+        assert_eq!(imported[0].progress, 0.5);
+        let again = fx
+            .library
+            .import_legacy(vec![legacy(&fx.outside.join("secret.epub"))]);
+        assert!(matches!(again, Err(AccessError::LegacyImportClosed)));
+        assert_eq!(fx.paths().len(), 1);
+
+        // Emptying the library doesn't reopen it.
+        fx.library.remove(&imported[0].id).unwrap();
+        assert!(fx.paths().is_empty());
+        let again = fx
+            .library
+            .import_legacy(vec![legacy(&fx.outside.join("secret.epub"))]);
+        assert!(matches!(again, Err(AccessError::LegacyImportClosed)));
+        assert!(fx.paths().is_empty());
+    }
+
+    #[test]
+    fn legacy_import_closes_with_nothing_to_import() {
+        let fx = Fixture::new("legacy-empty");
+        let secret = LegacyBook {
+            path: fx
+                .outside
+                .join("secret.epub")
+                .to_string_lossy()
+                .into_owned(),
+            title: "Secret".into(),
+            author: String::new(),
+            progress: 0.0,
+            current_cfi: None,
+            last_opened: None,
+        };
+        assert!(fx.library.import_legacy(Vec::new()).unwrap().is_empty());
+        assert!(fx.library.import_legacy(Vec::new()).unwrap().is_empty());
+        let late = fx.library.import_legacy(vec![secret]);
+        assert!(matches!(late, Err(AccessError::LegacyImportClosed)));
+        assert!(fx.paths().is_empty());
+    }
+
+    #[test]
+    fn resource_paths_cannot_leave_the_archive() {
+        let fx = Fixture::new("protocol");
+        let book = import_directory(&fx.library, &fx.root, true, |_| {})
+            .unwrap()
+            .imported
+            .remove(0);
+        let books = OpenBooks::default();
+        books
+            .open(&book.id, &fx.library.book_path(&book.id).unwrap())
+            .unwrap();
+        let get = |uri: String| {
+            let request = Request::builder().uri(uri).body(Vec::new()).unwrap();
+            protocol::handle(&books, &request)
+        };
+
+        let id = &book.id;
+        let ok = get(format!("epub://{id}/OEBPS/ch1.xhtml"));
+        assert_eq!(ok.status(), StatusCode::OK);
+        for path in [
+            "../private/secret.txt",
+            "../../../../etc/passwd",
+            "OEBPS/../../private/secret.txt",
+            "%2e%2e/private/secret.txt",
+            "..%2fprivate%2fsecret.txt",
+            "%2e%2e%5cprivate%5csecret.txt",
+            "/etc/passwd",
+            "%2fetc%2fpasswd",
+        ] {
+            for uri in [
+                format!("epub://{id}/{path}"),
+                format!("http://epub.localhost/{id}/{path}"),
+            ] {
+                let response = get(uri.clone());
+                assert!(
+                    response.status().is_client_error(),
+                    "{uri} answered {}",
+                    response.status()
+                );
+                assert!(response.body().is_empty());
+            }
+        }
+        // Nor can a book id be swapped for a path.
+        let response = get("epub://localhost/..%2fprivate%2fsecret.epub/OEBPS/ch1.xhtml".into());
+        assert!(response.status().is_client_error());
+    }
+}
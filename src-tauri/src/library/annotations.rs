@@ -0,0 +1,223 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use super::{now_millis, Library};
+use crate::epub::{Cfi, CfiError};
+
+const DEFAULT_COLOR: &str = "yellow";
+
+const ANNOTATION_COLUMNS: &str =
+    "id, book_id, cfi, text, note, color, tags, created_at, updated_at";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AnnotationError {
+    #[error(transparent)]
+    Cfi(#[from] CfiError),
+    #[error("Annotation store error: {0}")]
+    Db(#[from] rusqlite::Error),
+}
+
+/// A highlight, with or without a note.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Annotation {
+    pub id: String,
+    pub book_id: String,
+    /// Range CFI of the highlighted text; a point CFI for a note that isn't
+    /// attached to any text.
+    pub cfi: String,
+    /// The highlighted text, as it was when the highlight was made.
+    pub text: Option<String>,
+    pub note: Option<String>,
+    pub color: String,
+    pub tags: Vec<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewAnnotation {
+    pub book_id: String,
+    pub cfi: String,
+    pub text: Option<String>,
+    pub note: Option<String>,
+    pub color: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotationUpdate {
+    /// Moves the highlight; `text` should be updated along with it.
+    pub cfi: Option<String>,
+    pub text: Option<String>,
+    /// An empty note removes it.
+    pub note: Option<String>,
+    pub color: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+impl Library {
+    pub fn add_annotation(&self, new: NewAnnotation) -> Result<Annotation, AnnotationError> {
+        let cfi: Cfi = new.cfi.parse()?;
+        let conn = self.conn.lock().unwrap();
+        let now = now_millis();
+        Ok(insert_annotation(&conn, new, &cfi, now, now)?)
+    }
+
+    /// Adds an annotation brought over from another reader, keeping its
+    /// timestamps. Returns `None` if the book already has one at the same
+    /// position, so importing a file twice doesn't duplicate it.
+    pub fn import_annotation(
+        &self,
+        new: NewAnnotation,
+        created_at: i64,
+        updated_at: i64,
+    ) -> Result<Option<Annotation>, AnnotationError> {
+        let cfi: Cfi = new.cfi.parse()?;
+        let conn = self.conn.lock().unwrap();
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM annotations WHERE book_id = ?1 AND cfi = ?2)",
+            params![new.book_id, cfi.to_string()],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Ok(None);
+        }
+        Ok(Some(insert_annotation(
+            &conn, new, &cfi, created_at, updated_at,
+        )?))
+    }
+
+    pub fn update_annotation(
+        &self,
+        id: &str,
+        update: AnnotationUpdate,
+    ) -> Result<Annotation, AnnotationError> {
+        let cfi = match update.cfi {
+            Some(cfi) => Some(cfi.parse::<Cfi>()?.to_string()),
+            None => None,
+        };
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE annotations SET
+                 cfi = COALESCE(?2, cfi),
+                 text = COALESCE(?3, text),
+                 note = CASE WHEN ?4 IS NULL THEN note ELSE NULLIF(?4, '') END,
+                 color = COALESCE(?5, color),
+                 tags = COALESCE(?6, tags),
+                 updated_at = ?7
+             WHERE id = ?1",
+            params![
+                id,
+                cfi,
+                update.text,
+                update.note,
+                update.color,
+                update.tags.map(tags_json),
+                now_millis()
+            ],
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows.into());
+        }
+        Ok(get_annotation(&conn, id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)?)
+    }
+
+    pub fn remove_annotation(&self, id: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM annotations WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// A book's annotations in reading order.
+    pub fn annotations(&self, book_id: &str) -> rusqlite::Result<Vec<Annotation>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ANNOTATION_COLUMNS} FROM annotations WHERE book_id = ?1"
+        ))?;
+        let mut annotations: Vec<Annotation> = stmt
+            .query_map([book_id], annotation_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        annotations.sort_by_cached_key(|a| a.cfi.parse::<Cfi>().ok());
+        Ok(annotations)
+    }
+
+    /// Annotations across the library, most recently changed first,
+    /// optionally only those with a given tag.
+    pub fn all_annotations(&self, tag: Option<&str>) -> rusqlite::Result<Vec<Annotation>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ANNOTATION_COLUMNS} FROM annotations
+             WHERE ?1 IS NULL OR EXISTS (SELECT 1 FROM json_each(tags) WHERE value = ?1)
+             ORDER BY updated_at DESC"
+        ))?;
+        let annotations = stmt.query_map([tag], annotation_from_row)?.collect();
+        annotations
+    }
+}
+
+fn insert_annotation(
+    conn: &Connection,
+    new: NewAnnotation,
+    cfi: &Cfi,
+    created_at: i64,
+    updated_at: i64,
+) -> rusqlite::Result<Annotation> {
+    let id = uuid::Uuid::new_v4().to_string();
+    conn.execute(
+        "INSERT INTO annotations
+             (id, book_id, cfi, text, note, color, tags, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, NULLIF(?5, ''), ?6, ?7, ?8, ?9)",
+        params![
+            id,
+            new.book_id,
+            cfi.to_string(),
+            new.text,
+            new.note,
+            new.color.as_deref().unwrap_or(DEFAULT_COLOR),
+            tags_json(new.tags),
+            created_at,
+            updated_at
+        ],
+    )?;
+    get_annotation(conn, &id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+/// Trims tags and drops empty and repeated ones.
+fn tags_json(tags: Vec<String>) -> String {
+    let mut clean: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !clean.iter().any(|t| t == tag) {
+            clean.push(tag.to_string());
+        }
+    }
+    serde_json::to_string(&clean).unwrap()
+}
+
+fn get_annotation(conn: &Connection, id: &str) -> rusqlite::Result<Option<Annotation>> {
+    conn.query_row(
+        &format!("SELECT {ANNOTATION_COLUMNS} FROM annotations WHERE id = ?1"),
+        [id],
+        annotation_from_row,
+    )
+    .optional()
+}
+
+fn annotation_from_row(row: &Row) -> rusqlite::Result<Annotation> {
+    let tags: String = row.get(6)?;
+    Ok(Annotation {
+        id: row.get(0)?,
+        book_id: row.get(1)?,
+        cfi: row.get(2)?,
+        text: row.get(3)?,
+        note: row.get(4)?,
+        color: row.get(5)?,
+        tags: serde_json::from_str(&tags).unwrap_or_default(),
+        created_at: row.get(7)?,
+        updated_at: row.get(8)?,
+    })
+}
@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+
+use super::{now_millis, Library};
+use crate::epub::{self, BookError, Cfi, CfiError, Passage};
+
+const BOOKMARK_COLUMNS: &str = "id, book_id, name, cfi, chapter_title, excerpt, created_at";
+
+#[derive(Debug, thiserror::Error)]
+pub enum BookmarkError {
+    #[error(transparent)]
+    Book(#[from] BookError),
+    #[error(transparent)]
+    Cfi(#[from] CfiError),
+    #[error("Bookmark store error: {0}")]
+    Db(#[from] rusqlite::Error),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    pub id: String,
+    pub book_id: String,
+    pub name: String,
+    pub cfi: String,
+    /// TOC label of the chapter the bookmark is in.
+    pub chapter_title: Option<String>,
+    /// Text at the bookmarked position.
+    pub excerpt: String,
+    pub created_at: i64,
+}
+
+impl Library {
+    /// Bookmarks a position, looking up its chapter title and text in the
+    /// book.
+    pub fn add_bookmark(
+        &self,
+        book_id: &str,
+        cfi: &str,
+        name: Option<&str>,
+    ) -> Result<Bookmark, BookmarkError> {
+        let cfi: Cfi = cfi.parse()?;
+        let book = self
+            .get(book_id)?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+        // Reading the book is the slow part; keep it outside the lock.
+        let passage = epub::read_passage(Path::new(&book.path), &cfi)?;
+        let conn = self.conn.lock().unwrap();
+        Ok(insert_bookmark(
+            &conn,
+            &book.id,
+            name,
+            &cfi,
+            &passage,
+            now_millis(),
+        )?)
+    }
+
+    /// Adds a bookmark brought over from another reader, given its passage.
+    /// Returns `None` if the book already has a bookmark there.
+    pub fn import_bookmark(
+        &self,
+        book_id: &str,
+        cfi: &Cfi,
+        name: Option<&str>,
+        passage: &Passage,
+        created_at: i64,
+    ) -> rusqlite::Result<Option<Bookmark>> {
+        let conn = self.conn.lock().unwrap();
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM bookmarks WHERE book_id = ?1 AND cfi = ?2)",
+            params![book_id, cfi.to_string()],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Ok(None);
+        }
+        insert_bookmark(&conn, book_id, name, cfi, passage, created_at).map(Some)
+    }
+
+    /// A book's bookmarks in reading order.
+    pub fn bookmarks(&self, book_id: &str) -> rusqlite::Result<Vec<Bookmark>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {BOOKMARK_COLUMNS} FROM bookmarks WHERE book_id = ?1"
+        ))?;
+        let mut bookmarks: Vec<Bookmark> = stmt
+            .query_map([book_id], bookmark_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        bookmarks.sort_by_cached_key(|b| b.cfi.parse::<Cfi>().ok());
+        Ok(bookmarks)
+    }
+
+    pub fn remove_bookmark(&self, id: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM bookmarks WHERE id = ?1", [id])?;
+        Ok(())
+    }
+}
+
+/// Without a name, the bookmark is named after its chapter.
+fn insert_bookmark(
+    conn: &Connection,
+    book_id: &str,
+    name: Option<&str>,
+    cfi: &Cfi,
+    passage: &Passage,
+    created_at: i64,
+) -> rusqlite::Result<Bookmark> {
+    let name = name
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .or(passage.chapter_title.as_deref())
+        .unwrap_or("Bookmark");
+    conn.query_row(
+        &format!(
+            "INSERT INTO bookmarks
+                 (id, book_id, name, cfi, chapter_title, excerpt, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             RETURNING {BOOKMARK_COLUMNS}"
+        ),
+        params![
+            uuid::Uuid::new_v4().to_string(),
+            book_id,
+            name,
+            cfi.to_string(),
+            passage.chapter_title,
+            passage.excerpt,
+            created_at
+        ],
+        bookmark_from_row,
+    )
+}
+
+fn bookmark_from_row(row: &Row) -> rusqlite::Result<Bookmark> {
+    Ok(Bookmark {
+        id: row.get(0)?,
+        book_id: row.get(1)?,
+        name: row.get(2)?,
+        cfi: row.get(3)?,
+        chapter_title: row.get(4)?,
+        excerpt: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use rusqlite::{params, OptionalExtension};
+
+use super::{book_from_row, Book, Library, BOOK_COLUMNS};
+use crate::epub;
+
+impl Library {
+    /// Records a book's `dc:identifier` values.
+    pub fn set_identifiers(&self, id: &str, identifiers: &[String]) -> rusqlite::Result<()> {
+        let mut keys: Vec<String> = Vec::new();
+        for key in identifiers.iter().filter_map(|i| identifier_key(i)) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE books SET identifiers = ?2 WHERE id = ?1",
+            params![id, serde_json::to_string(&keys).unwrap()],
+        )?;
+        Ok(())
+    }
+
+    /// The book sharing any of `identifiers`. Books whose identifiers haven't
+    /// been recorded yet are read first.
+    pub fn find_by_identifier(&self, identifiers: &[String]) -> rusqlite::Result<Option<Book>> {
+        let keys: Vec<String> = identifiers
+            .iter()
+            .filter_map(|i| identifier_key(i))
+            .collect();
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        let unread: Vec<Book> = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {BOOK_COLUMNS} FROM books WHERE identifiers IS NULL"
+            ))?;
+            let books = stmt
+                .query_map([], book_from_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            books
+        };
+        for book in unread {
+            // Missing files are retried next time.
+            if let Ok(metadata) = epub::read_metadata(Path::new(&book.path)) {
+                let values: Vec<String> =
+                    metadata.identifiers.into_iter().map(|i| i.value).collect();
+                self.set_identifiers(&book.id, &values)?;
+            }
+        }
+
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT {BOOK_COLUMNS} FROM books
+                 WHERE EXISTS (
+                     SELECT 1 FROM json_each(books.identifiers)
+                     WHERE value IN (SELECT value FROM json_each(?1))
+                 )
+                 ORDER BY COALESCE(last_opened, added_at) DESC"
+            ),
+            [serde_json::to_string(&keys).unwrap()],
+            book_from_row,
+        )
+        .optional()
+    }
+}
+
+/// Identifier in a form that survives the ways apps write it: lowercase,
+/// without `urn:` or a scheme prefix, and without hyphens or spaces, so
+/// `urn:ISBN:978-0-14-044913-6` and `9780140449136` match. Values too short
+/// to identify a book, like a Calibre database id, give `None`.
+fn identifier_key(value: &str) -> Option<String> {
+    let lower = value.trim().to_lowercase();
+    let mut key = lower.strip_prefix("urn:").unwrap_or(&lower);
+    if let Some((scheme, rest)) = key.split_once(':') {
+        if matches!(
+            scheme,
+            "isbn"
+                | "uuid"
+                | "doi"
+                | "issn"
+                | "asin"
+                | "mobi-asin"
+                | "amazon"
+                | "calibre"
+                | "google"
+        ) {
+            key = rest;
+        }
+    }
+    let key: String = key.chars().filter(|c| !matches!(c, '-' | ' ')).collect();
+    (key.chars().count() >= 8).then_some(key)
+}
@@ -0,0 +1,265 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use super::{Book, BookUpdate, Library, NewBook};
+use crate::epub::{self, BookError};
+
+const EPUB_MIMETYPE: &[u8] = b"application/epub+zip";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AddError {
+    #[error("Failed to read file: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Book(#[from] BookError),
+    #[error("Library store error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("Another book in the library is already at {0}")]
+    PathTaken(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportProgress {
+    pub processed: usize,
+    pub total: usize,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSummary {
+    pub imported: Vec<Book>,
+    /// Known books found at a new path; they keep their progress.
+    pub relocated: Vec<Relocated>,
+    /// Files whose content (or path) is already in the library.
+    pub duplicates: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Relocated {
+    pub book: Book,
+    pub from: String,
+}
+
+pub enum ImportOutcome {
+    Added(Book),
+    Relocated(Relocated),
+    Duplicate,
+}
+
+/// Adds every EPUB under `root` to the library, reporting progress after
+/// each candidate file.
+pub fn import_directory(
+    library: &Library,
+    root: &Path,
+    recursive: bool,
+    mut on_progress: impl FnMut(&ImportProgress),
+) -> io::Result<ImportSummary> {
+    if !root.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", root.display()),
+        ));
+    }
+
+    let walker = WalkDir::new(root).max_depth(if recursive { usize::MAX } else { 1 });
+    let candidates: Vec<PathBuf> = walker
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| is_epub(path))
+        .collect();
+
+    let mut summary = ImportSummary::default();
+    for (i, path) in candidates.iter().enumerate() {
+        match import_file(library, path) {
+            Ok(ImportOutcome::Added(book)) => summary.imported.push(book),
+            Ok(ImportOutcome::Relocated(moved)) => summary.relocated.push(moved),
+            Ok(ImportOutcome::Duplicate) => summary.duplicates += 1,
+            Err(error) => summary.failed.push(ImportFailure {
+                path: path.display().to_string(),
+                error: error.to_string(),
+            }),
+        }
+        on_progress(&ImportProgress {
+            processed: i + 1,
+            total: candidates.len(),
+            path: path.display().to_string(),
+        });
+    }
+    Ok(summary)
+}
+
+/// Imports a single file. A file whose content matches a book that is no
+/// longer at its recorded path is treated as that book having moved.
+pub fn import_file(library: &Library, path: &Path) -> Result<ImportOutcome, AddError> {
+    let path_str = path.to_string_lossy();
+    if library.contains_path(&path_str)? {
+        return Ok(ImportOutcome::Duplicate);
+    }
+    let hash = hash_file(path)?;
+    if let Some(existing) = library.find_by_hash(&hash)? {
+        if Path::new(&existing.path).exists() {
+            return Ok(ImportOutcome::Duplicate);
+        }
+        let book = library.relocate(&existing.id, &path_str)?;
+        return Ok(ImportOutcome::Relocated(Relocated {
+            book,
+            from: existing.path,
+        }));
+    }
+
+    add_new(library, path, &hash).map(ImportOutcome::Added)
+}
+
+/// Adds a file the user picked, or refreshes its entry and marks it opened
+/// if its path is already in the library.
+pub fn add_file(library: &Library, path: &Path) -> Result<Book, AddError> {
+    let hash = hash_file(path)?;
+    add_new(library, path, &hash)
+}
+
+/// Points book `id` at a file the user found after it went missing,
+/// keeping its progress and annotations.
+pub fn locate_file(library: &Library, id: &str, path: &Path) -> Result<Book, AddError> {
+    // Checks that the file is a readable EPUB before the book is moved.
+    epub::read_metadata(path)?;
+    let hash = hash_file(path)?;
+    library.relocate(id, &path.to_string_lossy())?;
+    library.set_content_hash(id, &hash)?;
+    record_lengths(library, id, path)?;
+    library
+        .get(id)?
+        .ok_or(AddError::Db(rusqlite::Error::QueryReturnedNoRows))
+}
+
+/// Re-reads a book whose file was changed in place. Returns `None` if its
+/// content is what was imported.
+pub fn refresh_file(library: &Library, book: &Book) -> Result<Option<Book>, AddError> {
+    let path = Path::new(&book.path);
+    let hash = hash_file(path)?;
+    if book.content_hash.as_deref() == Some(hash.as_str()) {
+        return Ok(None);
+    }
+    let (details, identifiers) = read_details(path)?;
+    let update = BookUpdate {
+        title: Some(details.title),
+        author: Some(details.author),
+        ..BookUpdate::default()
+    };
+    library.update(&book.id, update)?;
+    library.set_content_hash(&book.id, &hash)?;
+    library.set_identifiers(&book.id, &identifiers)?;
+    record_lengths(library, &book.id, path)?;
+    Ok(library.get(&book.id)?)
+}
+
+fn add_new(library: &Library, path: &Path, hash: &str) -> Result<Book, AddError> {
+    let (book, identifiers) = read_details(path)?;
+    let book = library.add(book, Some(hash))?;
+    library.set_identifiers(&book.id, &identifiers)?;
+    record_lengths(library, &book.id, path)?;
+    Ok(book)
+}
+
+/// A book's library details and `dc:identifier`s, from its metadata.
+fn read_details(path: &Path) -> Result<(NewBook, Vec<String>), AddError> {
+    let path_str = path.to_string_lossy();
+    let metadata = epub::read_metadata(path)?;
+    let identifiers: Vec<String> = metadata
+        .identifiers
+        .iter()
+        .map(|i| i.value.clone())
+        .collect();
+    let author = author_of(&metadata);
+    let title = metadata.title.unwrap_or_else(|| {
+        path.file_stem()
+            .map_or_else(|| "Untitled".into(), |s| s.to_string_lossy().into_owned())
+    });
+    let book = NewBook {
+        path: path_str.into_owned(),
+        title,
+        author,
+    };
+    Ok((book, identifiers))
+}
+
+/// Measures a book's spine items for locations and reading estimates.
+/// DRM-protected books are still added, just without lengths.
+fn record_lengths(library: &Library, id: &str, path: &Path) -> rusqlite::Result<()> {
+    match epub::read_lengths(path) {
+        Ok(lengths) => library.set_spine_lengths(id, &lengths),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Display author: the creators credited as authors, or every creator when
+/// no roles are given.
+fn author_of(metadata: &epub::Metadata) -> String {
+    let authors: Vec<&str> = metadata
+        .creators
+        .iter()
+        .filter(|c| c.roles.iter().any(|r| r == "aut"))
+        .map(|c| c.name.as_str())
+        .collect();
+    if !authors.is_empty() {
+        return authors.join(", ");
+    }
+    let creators: Vec<&str> = metadata.creators.iter().map(|c| c.name.as_str()).collect();
+    creators.join(", ")
+}
+
+/// Detects an EPUB by its OCF `mimetype` entry rather than its extension.
+pub fn is_epub(path: &Path) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    // Conforming files start with an uncompressed `mimetype` entry, so the
+    // media type sits at a fixed offset right after the local file header.
+    let mut header = [0u8; 58];
+    let Ok(()) = file.read_exact(&mut header) else {
+        return false;
+    };
+    if &header[..4] != b"PK\x03\x04" {
+        return false;
+    }
+    if &header[30..38] == b"mimetype" && &header[38..] == EPUB_MIMETYPE {
+        return true;
+    }
+    // Plenty of tools get the ordering or compression wrong; fall back to
+    // looking the entry up in the central directory.
+    let Ok(mut zip) = zip::ZipArchive::new(file) else {
+        return false;
+    };
+    let Ok(mut entry) = zip.by_name("mimetype") else {
+        return false;
+    };
+    let mut contents = Vec::new();
+    entry.by_ref().take(64).read_to_end(&mut contents).is_ok()
+        && contents.trim_ascii() == EPUB_MIMETYPE
+}
+
+/// Hex SHA-256 of a file's contents, used to recognise the same book under
+/// a different path.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
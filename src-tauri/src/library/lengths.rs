@@ -0,0 +1,151 @@
+use std::path::Path;
+
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+
+use super::stats::StatsError;
+use super::Library;
+use crate::epub::{self, SpineLength};
+
+/// Characters of text per location. Locations number positions in a book
+/// by its text alone, so they are the same whatever the layout or device.
+const LOCATION_CHARS: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookLength {
+    /// By spine index.
+    pub spine: Vec<ChapterLength>,
+    pub words: usize,
+    pub chars: usize,
+    pub locations: usize,
+    /// Characters per location: location `n` (from 1) starts `(n - 1) *
+    /// locationChars` characters into the book.
+    pub location_chars: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChapterLength {
+    pub words: usize,
+    pub chars: usize,
+    /// Characters in the spine items before this one.
+    pub start: usize,
+}
+
+impl BookLength {
+    fn new(lengths: &[SpineLength]) -> Self {
+        let mut start = 0;
+        let spine = lengths
+            .iter()
+            .map(|length| {
+                let chapter = ChapterLength {
+                    words: length.words,
+                    chars: length.chars,
+                    start,
+                };
+                start += length.chars;
+                chapter
+            })
+            .collect();
+        Self {
+            spine,
+            words: lengths.iter().map(|l| l.words).sum(),
+            chars: start,
+            locations: start.div_ceil(LOCATION_CHARS).max(1),
+            location_chars: LOCATION_CHARS,
+        }
+    }
+}
+
+impl Library {
+    /// Records the length of each of a book's spine items.
+    pub fn set_spine_lengths(&self, id: &str, lengths: &[SpineLength]) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE books SET spine_lengths = ?2 WHERE id = ?1",
+            params![id, serde_json::to_string(lengths).unwrap()],
+        )?;
+        Ok(())
+    }
+
+    /// A book's length, measured when it was added. Books added before
+    /// lengths were recorded are measured now.
+    pub fn book_length(&self, id: &str) -> Result<BookLength, StatsError> {
+        let (path, stored): (String, Option<String>) = {
+            let conn = self.conn.lock().unwrap();
+            conn.query_row(
+                "SELECT path, spine_lengths FROM books WHERE id = ?1",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)?
+        };
+        let stored: Option<Vec<SpineLength>> =
+            stored.and_then(|json| serde_json::from_str(&json).ok());
+        let lengths = match stored {
+            Some(lengths) => lengths,
+            None => {
+                let lengths = epub::read_lengths(Path::new(&path))?;
+                self.set_spine_lengths(id, &lengths)?;
+                lengths
+            }
+        };
+        Ok(BookLength::new(&lengths))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::NewBook;
+    use crate::test_support::{write_epub, ScratchDir};
+
+    fn length(chars: usize) -> SpineLength {
+        SpineLength { words: 1, chars }
+    }
+
+    #[test]
+    fn counts_whole_and_partial_locations() {
+        let locations = |lengths: &[SpineLength]| BookLength::new(lengths).locations;
+        assert_eq!(locations(&[]), 1);
+        assert_eq!(locations(&[length(0)]), 1);
+        assert_eq!(locations(&[length(1)]), 1);
+        assert_eq!(locations(&[length(1024)]), 1);
+        assert_eq!(locations(&[length(1025)]), 2);
+        assert_eq!(locations(&[length(1000), length(24)]), 1);
+        assert_eq!(locations(&[length(1000), length(24), length(1)]), 2);
+        assert_eq!(locations(&[length(2048)]), 2);
+    }
+
+    #[test]
+    fn measures_a_book_by_chapter() {
+        let dir = ScratchDir::new("lengths");
+        let path = dir.join("book.epub");
+        let first = format!("<p>{}</p>", "a".repeat(1000));
+        let second = format!("<p>{}</p>", "b".repeat(24));
+        write_epub(&path, "Lengths", &[&first, &second, "<p>c d</p>"]);
+        let library = Library::open(&dir.join("library.sqlite3")).unwrap();
+        let book = NewBook {
+            path: path.to_string_lossy().into_owned(),
+            title: "Lengths".to_string(),
+            author: String::new(),
+        };
+        let book = library.add(book, None).unwrap();
+
+        let length = library.book_length(&book.id).unwrap();
+        let chapters: Vec<_> = length
+            .spine
+            .iter()
+            .map(|c| (c.start, c.chars, c.words))
+            .collect();
+        assert_eq!(chapters, [(0, 1000, 1), (1000, 24, 1), (1024, 3, 2)]);
+        assert_eq!((length.chars, length.words, length.locations), (1027, 4, 2));
+        assert_eq!(length.location_chars, LOCATION_CHARS);
+
+        // Measured once, then read back from the library.
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(library.book_length(&book.id).unwrap().chars, 1027);
+    }
+}
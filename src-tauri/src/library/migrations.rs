@@ -0,0 +1,112 @@
+use rusqlite::Connection;
+
+/// Schema changes, applied in order. The index of the last applied entry + 1
+/// is stored in `PRAGMA user_version`, so entries must never be edited or
+/// reordered once released — only appended.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE books (
+        id          TEXT PRIMARY KEY,
+        path        TEXT NOT NULL UNIQUE,
+        title       TEXT NOT NULL,
+        author      TEXT NOT NULL DEFAULT '',
+        cover       TEXT,
+        progress    REAL NOT NULL DEFAULT 0,
+        current_cfi TEXT,
+        added_at    INTEGER NOT NULL,
+        last_opened INTEGER
+    );
+",
+    "
+    ALTER TABLE books ADD COLUMN content_hash TEXT;
+    CREATE INDEX books_content_hash ON books (content_hash);
+",
+    "CREATE TABLE library_roots (
+         path     TEXT PRIMARY KEY,
+         added_at INTEGER NOT NULL
+     );",
+    // Covers used to be stored inline as data URLs; thumbnails now live in
+    // the cover cache, keyed by content hash.
+    "ALTER TABLE books DROP COLUMN cover;",
+    // One row per chapter. The porter stemmer sits on top of unicode61 so
+    // that "running" finds "run" and "café" finds "cafe".
+    "CREATE VIRTUAL TABLE search_chapters USING fts5(
+         text,
+         book_id UNINDEXED,
+         chapter UNINDEXED,
+         tokenize = 'porter unicode61 remove_diacritics 2'
+     );
+     CREATE TABLE search_books (
+         book_id      TEXT PRIMARY KEY REFERENCES books (id) ON DELETE CASCADE,
+         content_hash TEXT NOT NULL,
+         indexed_at   INTEGER NOT NULL
+     );
+     CREATE TRIGGER books_search_cleanup AFTER DELETE ON books BEGIN
+         DELETE FROM search_chapters WHERE book_id = old.id;
+     END;",
+    // Tags are a JSON array of strings.
+    "CREATE TABLE annotations (
+         id         TEXT PRIMARY KEY,
+         book_id    TEXT NOT NULL REFERENCES books (id) ON DELETE CASCADE,
+         cfi        TEXT NOT NULL,
+         text       TEXT,
+         note       TEXT,
+         color      TEXT NOT NULL,
+         tags       TEXT NOT NULL DEFAULT '[]',
+         created_at INTEGER NOT NULL,
+         updated_at INTEGER NOT NULL
+     );
+     CREATE INDEX annotations_book ON annotations (book_id);",
+    "CREATE TABLE bookmarks (
+         id            TEXT PRIMARY KEY,
+         book_id       TEXT NOT NULL REFERENCES books (id) ON DELETE CASCADE,
+         name          TEXT NOT NULL,
+         cfi           TEXT NOT NULL,
+         chapter_title TEXT,
+         excerpt       TEXT NOT NULL,
+         created_at    INTEGER NOT NULL
+     );
+     CREATE INDEX bookmarks_book ON bookmarks (book_id);",
+    // Normalised `dc:identifier`s as a JSON array, for recognising a book in
+    // files from other readers. NULL until the book's metadata is read.
+    "ALTER TABLE books ADD COLUMN identifiers TEXT;",
+    // Chapter text no longer includes scripts and other sanitized content,
+    // so indexed offsets are stale; `index_all` rebuilds it.
+    "DELETE FROM search_chapters;
+     DELETE FROM search_books;",
+    "ALTER TABLE books ADD COLUMN publisher_styles INTEGER NOT NULL DEFAULT 1;",
+    // `end_index` and `end_offset` are where the last heartbeat was, for
+    // counting words from there at the next.
+    "CREATE TABLE reading_sessions (
+         id             TEXT PRIMARY KEY,
+         book_id        TEXT NOT NULL REFERENCES books (id) ON DELETE CASCADE,
+         started_at     INTEGER NOT NULL,
+         ended_at       INTEGER NOT NULL,
+         start_cfi      TEXT NOT NULL,
+         end_cfi        TEXT NOT NULL,
+         start_progress REAL NOT NULL,
+         end_progress   REAL NOT NULL,
+         end_index      INTEGER NOT NULL,
+         end_offset     INTEGER NOT NULL,
+         words          INTEGER NOT NULL DEFAULT 0
+     );
+     CREATE INDEX reading_sessions_book ON reading_sessions (book_id, started_at);",
+    // Words and characters in each spine item as a JSON array of
+    // `{words, chars}`. NULL until the book is measured.
+    "ALTER TABLE books ADD COLUMN spine_lengths TEXT;",
+    // One-off events that have happened, by name. A library that already
+    // has books has taken its chance to import the localStorage one.
+    "CREATE TABLE flags (name TEXT PRIMARY KEY);
+     INSERT INTO flags (name) SELECT 'legacy_imported' WHERE EXISTS (SELECT 1 FROM books);",
+];
+
+pub fn run(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
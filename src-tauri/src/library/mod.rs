@@ -0,0 +1,303 @@
+mod access;
+mod annotations;
+mod bookmarks;
+mod identifiers;
+mod import;
+mod lengths;
+mod migrations;
+mod search;
+mod stats;
+mod watch;
+
+use std::path::{Path, MAIN_SEPARATOR};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+pub use access::{AccessError, LegacyBook};
+pub use annotations::{Annotation, AnnotationError, AnnotationUpdate, NewAnnotation};
+pub use bookmarks::{Bookmark, BookmarkError};
+pub use import::{add_file, hash_file, import_directory, locate_file, AddError, ImportSummary};
+pub use lengths::BookLength;
+pub use search::{SearchError, SearchHit};
+pub use stats::{Heartbeat, ReadingSession, ReadingStats, StatsError, TimeLeft};
+pub use watch::{reconcile, FsChange, LibraryChange, LibraryWatcher};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Book {
+    pub id: String,
+    pub path: String,
+    pub title: String,
+    pub author: String,
+    pub progress: f64,
+    pub current_cfi: Option<String>,
+    /// Milliseconds since the Unix epoch, like JavaScript's `Date.now()`.
+    pub added_at: i64,
+    pub last_opened: Option<i64>,
+    /// Hex SHA-256 of the file, shared by copies of the same book.
+    pub content_hash: Option<String>,
+    /// Whether chapters are shown with the book's own style sheets.
+    pub publisher_styles: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewBook {
+    pub path: String,
+    pub title: String,
+    #[serde(default)]
+    pub author: String,
+}
+
+/// Fields to change on an existing book; `None` leaves a field untouched.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookUpdate {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub progress: Option<f64>,
+    pub current_cfi: Option<String>,
+    pub last_opened: Option<i64>,
+    pub publisher_styles: Option<bool>,
+}
+
+const BOOK_COLUMNS: &str = "id, path, title, author, progress, current_cfi, added_at, \
+                            last_opened, content_hash, publisher_styles";
+
+pub struct Library {
+    conn: Mutex<Connection>,
+}
+
+impl Library {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrations::run(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn list(&self) -> rusqlite::Result<Vec<Book>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {BOOK_COLUMNS} FROM books
+             ORDER BY COALESCE(last_opened, added_at) DESC"
+        ))?;
+        let books = stmt.query_map([], book_from_row)?.collect();
+        books
+    }
+
+    /// Adds a book, or refreshes its details and marks it opened if a book
+    /// with the same path is already in the library.
+    pub fn add(&self, book: NewBook, content_hash: Option<&str>) -> rusqlite::Result<Book> {
+        let conn = self.conn.lock().unwrap();
+        let now = now_millis();
+        let id: String = conn.query_row(
+            "INSERT INTO books
+                 (id, path, title, author, added_at, last_opened, content_hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6)
+             ON CONFLICT(path) DO UPDATE SET
+                 title = excluded.title,
+                 author = excluded.author,
+                 last_opened = excluded.last_opened,
+                 content_hash = COALESCE(excluded.content_hash, content_hash)
+             RETURNING id",
+            params![
+                uuid::Uuid::new_v4().to_string(),
+                book.path,
+                book.title,
+                book.author,
+                now,
+                content_hash
+            ],
+            |row| row.get(0),
+        )?;
+        get_book(&conn, &id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+    }
+
+    pub fn update(&self, id: &str, update: BookUpdate) -> rusqlite::Result<Book> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE books SET
+                 title = COALESCE(?2, title),
+                 author = COALESCE(?3, author),
+                 progress = COALESCE(?4, progress),
+                 current_cfi = COALESCE(?5, current_cfi),
+                 last_opened = COALESCE(?6, last_opened),
+                 publisher_styles = COALESCE(?7, publisher_styles)
+             WHERE id = ?1",
+            params![
+                id,
+                update.title,
+                update.author,
+                update.progress,
+                update.current_cfi,
+                update.last_opened,
+                update.publisher_styles
+            ],
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        get_book(&conn, id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+    }
+
+    pub fn get(&self, id: &str) -> rusqlite::Result<Option<Book>> {
+        let conn = self.conn.lock().unwrap();
+        get_book(&conn, id)
+    }
+
+    /// Backfills the hash of a book added before hashes were recorded.
+    pub fn set_content_hash(&self, id: &str, content_hash: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE books SET content_hash = ?2 WHERE id = ?1",
+            [id, content_hash],
+        )?;
+        Ok(())
+    }
+
+    pub fn contains_path(&self, path: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM books WHERE path = ?1)",
+            [path],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn find_by_hash(&self, content_hash: &str) -> rusqlite::Result<Option<Book>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {BOOK_COLUMNS} FROM books WHERE content_hash = ?1"),
+            [content_hash],
+            book_from_row,
+        )
+        .optional()
+    }
+
+    /// The one book with this title, ignoring case. `None` if several have it.
+    pub fn find_by_title(&self, title: &str) -> rusqlite::Result<Option<Book>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {BOOK_COLUMNS} FROM books WHERE lower(trim(title)) = lower(trim(?1)) LIMIT 2"
+        ))?;
+        let mut books = stmt
+            .query_map([title], book_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(if books.len() == 1 { books.pop() } else { None })
+    }
+
+    pub fn find_by_path(&self, path: &str) -> rusqlite::Result<Option<Book>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {BOOK_COLUMNS} FROM books WHERE path = ?1"),
+            [path],
+            book_from_row,
+        )
+        .optional()
+    }
+
+    /// Points an existing book at a new file, keeping its progress. Fails
+    /// with [`AddError::PathTaken`] if another book is at `path`.
+    pub fn relocate(&self, id: &str, path: &str) -> Result<Book, AddError> {
+        let conn = self.conn.lock().unwrap();
+        let taken: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM books WHERE path = ?2 AND id != ?1)",
+            [id, path],
+            |row| row.get(0),
+        )?;
+        if taken {
+            return Err(AddError::PathTaken(path.to_string()));
+        }
+        conn.execute("UPDATE books SET path = ?2 WHERE id = ?1", [id, path])?;
+        Ok(get_book(&conn, id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)?)
+    }
+
+    /// Rewrites the paths of every book under `from` after a directory move.
+    pub fn relocate_dir(&self, from: &str, to: &str) -> rusqlite::Result<Vec<Book>> {
+        let conn = self.conn.lock().unwrap();
+        let from = format!("{}{MAIN_SEPARATOR}", from.trim_end_matches(MAIN_SEPARATOR));
+        let to = format!("{}{MAIN_SEPARATOR}", to.trim_end_matches(MAIN_SEPARATOR));
+        let mut stmt = conn.prepare(&format!(
+            "UPDATE books SET path = ?2 || substr(path, length(?1) + 1)
+             WHERE substr(path, 1, length(?1)) = ?1
+             RETURNING {BOOK_COLUMNS}"
+        ))?;
+        let books = stmt.query_map([&from, &to], book_from_row)?.collect();
+        books
+    }
+
+    pub fn books_under(&self, dir: &str) -> rusqlite::Result<Vec<Book>> {
+        let conn = self.conn.lock().unwrap();
+        let prefix = format!("{}{MAIN_SEPARATOR}", dir.trim_end_matches(MAIN_SEPARATOR));
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {BOOK_COLUMNS} FROM books WHERE substr(path, 1, length(?1)) = ?1"
+        ))?;
+        let books = stmt.query_map([&prefix], book_from_row)?.collect();
+        books
+    }
+
+    pub fn roots(&self) -> rusqlite::Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT path FROM library_roots ORDER BY added_at")?;
+        let roots = stmt.query_map([], |row| row.get(0))?.collect();
+        roots
+    }
+
+    pub fn add_root(&self, path: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO library_roots (path, added_at) VALUES (?1, ?2)",
+            params![path, now_millis()],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_root(&self, path: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM library_roots WHERE path = ?1", [path])?;
+        Ok(())
+    }
+
+    pub fn remove(&self, id: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM books WHERE id = ?1", [id])?;
+        Ok(())
+    }
+}
+
+fn get_book(conn: &Connection, id: &str) -> rusqlite::Result<Option<Book>> {
+    conn.query_row(
+        &format!("SELECT {BOOK_COLUMNS} FROM books WHERE id = ?1"),
+        [id],
+        book_from_row,
+    )
+    .optional()
+}
+
+fn book_from_row(row: &Row) -> rusqlite::Result<Book> {
+    Ok(Book {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        title: row.get(2)?,
+        author: row.get(3)?,
+        progress: row.get(4)?,
+        current_cfi: row.get(5)?,
+        added_at: row.get(6)?,
+        last_opened: row.get(7)?,
+        content_hash: row.get(8)?,
+        publisher_styles: row.get(9)?,
+    })
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
@@ -0,0 +1,399 @@
+use std::ops::Range;
+use std::path::Path;
+
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+
+use super::{hash_file, now_millis, Book, Library};
+use crate::epub::{self, BookError, BLOCK_SEPARATOR};
+
+/// Characters of context kept on each side of a match in a snippet.
+const SNIPPET_CONTEXT: usize = 60;
+/// Matches returned by a search inside one book.
+const MAX_BOOK_HITS: usize = 200;
+/// Matches shown per book in a library-wide search, and books shown.
+const MAX_HITS_PER_BOOK: usize = 3;
+const MAX_LIBRARY_BOOKS: usize = 50;
+/// Best matching chapters read in a library-wide search. Plenty to fill
+/// [`MAX_LIBRARY_BOOKS`], without reading every chapter a common word is in.
+const MAX_LIBRARY_CHAPTERS: usize = 500;
+
+// `highlight()` wraps each match in these (`char(2)` and `char(3)` in the
+// queries). Control characters can't appear in well-formed XHTML, and
+// indexing replaces any that slip through.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+#[derive(Debug, thiserror::Error)]
+pub enum SearchError {
+    #[error(transparent)]
+    Book(#[from] BookError),
+    #[error("Failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Search index error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("This book hasn't been indexed for search yet")]
+    NotIndexed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub book_id: String,
+    /// Spine index of the chapter containing the match.
+    pub chapter_index: usize,
+    /// Where the match starts in the chapter's text content, in UTF-16 code
+    /// units as the DOM counts them.
+    pub offset: usize,
+    /// Length of the match in UTF-16 code units.
+    pub length: usize,
+    pub snippet: String,
+    /// Where the match starts within `snippet`, in UTF-16 code units.
+    pub snippet_offset: usize,
+}
+
+impl Library {
+    /// Indexes a book's text unless the index already matches its content.
+    /// Returns whether anything was (re)indexed.
+    pub fn index_book(&self, book: &Book) -> Result<bool, SearchError> {
+        let hash = match &book.content_hash {
+            Some(hash) => hash.clone(),
+            None => {
+                let hash = hash_file(Path::new(&book.path))?;
+                self.set_content_hash(&book.id, &hash)?;
+                hash
+            }
+        };
+        let indexed: Option<String> = {
+            let conn = self.conn.lock().unwrap();
+            conn.query_row(
+                "SELECT content_hash FROM search_books WHERE book_id = ?1",
+                [&book.id],
+                |row| row.get(0),
+            )
+            .ok()
+        };
+        if indexed.as_deref() == Some(hash.as_str()) {
+            return Ok(false);
+        }
+
+        // Extraction is the slow part; keep it outside the connection lock.
+        // A book that can't be read (DRM, a broken archive) is recorded as
+        // indexed with no text, so it isn't read again until its file
+        // changes. A missing file may come back, so that is retried.
+        let chapters = match epub::read_text(Path::new(&book.path)) {
+            Ok(chapters) => chapters,
+            Err(BookError::Io(e)) => return Err(e.into()),
+            Err(_) => Vec::new(),
+        };
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM search_chapters WHERE book_id = ?1", [&book.id])?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO search_chapters (text, book_id, chapter) VALUES (?1, ?2, ?3)",
+            )?;
+            for (index, text) in chapters {
+                let text = text.replace([MATCH_START, MATCH_END], " ");
+                insert.execute(params![text, book.id, index])?;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO search_books (book_id, content_hash, indexed_at)
+             VALUES (?1, ?2, ?3)",
+            params![book.id, hash, now_millis()],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Indexes every book that isn't indexed yet or whose file changed.
+    /// Books whose files are missing are skipped.
+    pub fn index_all(&self) -> Result<(), SearchError> {
+        for book in self.list()? {
+            let _ = self.index_book(&book);
+        }
+        Ok(())
+    }
+
+    /// Searches one book's index. Books are indexed in the background, on
+    /// import and at startup, so one may not have been yet.
+    pub fn search_book(&self, book_id: &str, query: &str) -> Result<Vec<SearchHit>, SearchError> {
+        let Some(query) = fts_query(query) else {
+            return Ok(Vec::new());
+        };
+        let conn = self.conn.lock().unwrap();
+        let indexed = conn
+            .query_row(
+                "SELECT 1 FROM search_books WHERE book_id = ?1",
+                [book_id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if indexed.is_none() {
+            return Err(SearchError::NotIndexed);
+        }
+        let mut stmt = conn.prepare(
+            "SELECT chapter, highlight(search_chapters, 0, char(2), char(3))
+             FROM search_chapters
+             WHERE search_chapters MATCH ?1 AND book_id = ?2
+             ORDER BY chapter",
+        )?;
+        let mut rows = stmt.query(params![query, book_id])?;
+        let mut hits = Vec::new();
+        while let Some(row) = rows.next()? {
+            let chapter: usize = row.get(0)?;
+            let highlighted: String = row.get(1)?;
+            let limit = MAX_BOOK_HITS - hits.len();
+            hits.extend(hits_in(book_id, chapter, &highlighted, limit));
+            if hits.len() >= MAX_BOOK_HITS {
+                break;
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Searches every indexed book, best matching chapters first, with a
+    /// few hits per book.
+    pub fn search_library(&self, query: &str) -> Result<Vec<SearchHit>, SearchError> {
+        let Some(query) = fts_query(query) else {
+            return Ok(Vec::new());
+        };
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT book_id, chapter, highlight(search_chapters, 0, char(2), char(3))
+             FROM search_chapters
+             WHERE search_chapters MATCH ?1
+             ORDER BY rank
+             LIMIT ?2",
+        )?;
+        let mut rows = stmt.query(params![query, MAX_LIBRARY_CHAPTERS])?;
+        let mut hits: Vec<SearchHit> = Vec::new();
+        let mut books: Vec<String> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let book_id: String = row.get(0)?;
+            let found = hits.iter().filter(|h| h.book_id == book_id).count();
+            if found >= MAX_HITS_PER_BOOK {
+                continue;
+            }
+            if !books.contains(&book_id) {
+                if books.len() >= MAX_LIBRARY_BOOKS {
+                    continue;
+                }
+                books.push(book_id.clone());
+            }
+            let chapter: usize = row.get(1)?;
+            let highlighted: String = row.get(2)?;
+            hits.extend(hits_in(
+                &book_id,
+                chapter,
+                &highlighted,
+                MAX_HITS_PER_BOOK - found,
+            ));
+        }
+        // Keep each book's hits together, in the order books were ranked.
+        hits.sort_by_key(|h| books.iter().position(|b| *b == h.book_id));
+        Ok(hits)
+    }
+}
+
+/// Turns free text into an FTS5 query matching documents that contain every
+/// word. Quoting each word keeps user input from being read as FTS syntax.
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| format!("\"{t}\""))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Splits `highlight()` output into hits, one per marked match.
+fn hits_in(book_id: &str, chapter: usize, highlighted: &str, limit: usize) -> Vec<SearchHit> {
+    let mut text = String::with_capacity(highlighted.len());
+    let mut matches: Vec<Range<usize>> = Vec::new();
+    let mut start = None;
+    for c in highlighted.chars() {
+        match c {
+            MATCH_START => start = Some(text.len()),
+            MATCH_END => {
+                if let Some(start) = start.take() {
+                    matches.push(start..text.len());
+                }
+            }
+            c => text.push(c),
+        }
+    }
+
+    matches
+        .into_iter()
+        .take(limit)
+        .map(|range| {
+            let before = &text[..range.start];
+            let found = &text[range.clone()];
+            let after = &text[range.end..];
+
+            let context_start = before
+                .char_indices()
+                .rev()
+                .nth(SNIPPET_CONTEXT - 1)
+                .map(|(i, _)| i);
+            let context_end = after.char_indices().nth(SNIPPET_CONTEXT).map(|(i, _)| i);
+            let mut lead = String::new();
+            if let Some(i) = context_start {
+                lead.push('…');
+                lead.push_str(&squash(&before[i..]));
+            } else {
+                lead.push_str(squash(before).trim_start());
+            }
+            let mut snippet = lead.clone();
+            snippet.push_str(found);
+            match context_end {
+                Some(i) => {
+                    snippet.push_str(&squash(&after[..i]));
+                    snippet.push('…');
+                }
+                None => snippet.push_str(squash(after).trim_end()),
+            }
+
+            SearchHit {
+                book_id: book_id.to_string(),
+                chapter_index: chapter,
+                offset: utf16_len(before),
+                length: utf16_len(found),
+                snippet,
+                snippet_offset: utf16_len(&lead),
+            }
+        })
+        .collect()
+}
+
+/// Collapses runs of whitespace and block separators to single spaces,
+/// keeping a space at either end if there was whitespace there.
+fn squash(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() || c == BLOCK_SEPARATOR {
+            if !in_space {
+                out.push(' ');
+            }
+            in_space = true;
+        } else {
+            out.push(c);
+            in_space = false;
+        }
+    }
+    out
+}
+
+/// Length as the DOM counts it: UTF-16 code units, without the block
+/// separators that only exist in the index.
+fn utf16_len(text: &str) -> usize {
+    text.chars()
+        .filter(|&c| c != BLOCK_SEPARATOR)
+        .map(char::len_utf16)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::NewBook;
+    use crate::test_support::{write_epub, ScratchDir};
+
+    /// A library holding one indexed book with these chapters.
+    fn indexed(name: &str, chapters: &[&str]) -> (ScratchDir, Library, Book) {
+        let dir = ScratchDir::new(&format!("search-{name}"));
+        let path = dir.join("book.epub");
+        write_epub(&path, "Search", chapters);
+        let library = Library::open(&dir.join("library.sqlite3")).unwrap();
+        let book = NewBook {
+            path: path.to_string_lossy().into_owned(),
+            title: "Search".to_string(),
+            author: String::new(),
+        };
+        let book = library.add(book, None).unwrap();
+        assert!(library.index_book(&book).unwrap());
+        (dir, library, book)
+    }
+
+    /// The text of a snippet from its match on, as the frontend slices it.
+    fn from_match(hit: &SearchHit) -> String {
+        let units: Vec<u16> = hit.snippet.encode_utf16().collect();
+        String::from_utf16(&units[hit.snippet_offset..]).unwrap()
+    }
+
+    #[test]
+    fn finds_other_forms_of_a_word() {
+        let (_dir, library, book) = indexed("stem", &["<p>She was running home.</p>"]);
+        for query in ["run", "runs", "RUNNING"] {
+            let hits = library.search_book(&book.id, query).unwrap();
+            assert_eq!(hits.len(), 1, "{query}");
+            assert_eq!((hits[0].offset, hits[0].length), (8, 7));
+        }
+        assert!(library.search_book(&book.id, "walk").unwrap().is_empty());
+        assert!(!library.index_book(&book).unwrap());
+    }
+
+    #[test]
+    fn ignores_diacritics() {
+        let (_dir, library, book) = indexed(
+            "fold",
+            &["<p>Un café noir.</p>", "<p>A Cafe latte, naïve.</p>"],
+        );
+        let hits = library.search_book(&book.id, "cafe").unwrap();
+        let found: Vec<_> = hits.iter().map(|h| (h.chapter_index, h.offset)).collect();
+        assert_eq!(found, [(0, 3), (1, 2)]);
+        assert_eq!(library.search_book(&book.id, "CAFÉ").unwrap().len(), 2);
+        assert_eq!(library.search_book(&book.id, "naive").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn highlights_matches_in_snippets() {
+        let long = "word ".repeat(30);
+        let chapter = format!("<p>{long}needle</p><p>{long}</p>");
+        let (_dir, library, book) = indexed("snippet", &["<p>A needle here.</p>", &chapter]);
+        let hits = library.search_book(&book.id, "needle").unwrap();
+        assert_eq!(hits.len(), 2);
+
+        assert_eq!(hits[0].snippet, "A needle here.");
+        assert_eq!(hits[0].snippet_offset, 2);
+        assert_eq!(hits[0].length, 6);
+
+        // Long context is cut, and the paragraph break shows as a space.
+        let snippet = &hits[1].snippet;
+        assert!(
+            snippet.starts_with('…') && snippet.ends_with('…'),
+            "{snippet}"
+        );
+        assert!(from_match(&hits[1]).starts_with("needle word"));
+        assert_eq!(hits[1].offset, long.len());
+    }
+
+    #[test]
+    fn offsets_count_utf16_units() {
+        let (_dir, library, book) = indexed("utf16", &["<p>𝔄 emoji 😀</p><p>x target</p>"]);
+        let hits = library.search_book(&book.id, "target").unwrap();
+        assert_eq!(hits.len(), 1);
+        // Two units each for 𝔄 and 😀; the block break isn't in the DOM.
+        assert_eq!((hits[0].offset, hits[0].length), (13, 6));
+        assert!(from_match(&hits[0]).starts_with("target"));
+    }
+
+    #[test]
+    fn unindexed_books_are_reported() {
+        let (_dir, library, _) = indexed("unindexed", &["<p>Text</p>"]);
+        let other = NewBook {
+            path: "/nowhere/other.epub".to_string(),
+            title: "Other".to_string(),
+            author: String::new(),
+        };
+        let other = library.add(other, None).unwrap();
+        assert!(matches!(
+            library.search_book(&other.id, "text"),
+            Err(SearchError::NotIndexed)
+        ));
+        assert_eq!(library.search_library("text").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,502 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use super::{now_millis, Library};
+use crate::epub::{self, BookError, BookStructure, EpubArchive, TextPosition, WordIndex};
+
+/// A heartbeat later than this after the last one starts a new session:
+/// the reader was closed, hidden or left idle in between.
+const SESSION_GAP_MS: i64 = 2 * 60 * 1000;
+/// Moving on faster than this is skipping, through the TOC or a search
+/// hit, rather than reading, and doesn't count towards words read.
+const MAX_WORDS_PER_MINUTE: f64 = 1500.0;
+/// Speed assumed until there is enough reading time to measure one.
+const DEFAULT_WORDS_PER_MINUTE: f64 = 250.0;
+const MIN_MEASURED_MS: i64 = 10 * 60 * 1000;
+/// Words on a printed page, for counting pages read.
+const WORDS_PER_PAGE: f64 = 250.0;
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+const SESSION_COLUMNS: &str = "id, book_id, started_at, ended_at, start_cfi, end_cfi, \
+                               start_progress, end_progress, words";
+
+#[derive(Debug, thiserror::Error)]
+pub enum StatsError {
+    #[error(transparent)]
+    Book(#[from] BookError),
+    #[error("Reading stats store error: {0}")]
+    Db(#[from] rusqlite::Error),
+}
+
+/// Sent by the reader every so often while someone is reading.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Heartbeat {
+    /// The session the previous heartbeat returned, if any.
+    pub session_id: Option<String>,
+    pub book_id: String,
+    /// Spine index of the chapter on screen.
+    pub index: usize,
+    /// Offset of the text at the top of the page, in UTF-16 code units.
+    pub offset: usize,
+    /// Fraction of the book read, as shown in the reader.
+    pub progress: f64,
+}
+
+/// A stretch of continuous reading in one book.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadingSession {
+    pub id: String,
+    pub book_id: String,
+    pub started_at: i64,
+    /// Time of the last heartbeat.
+    pub ended_at: i64,
+    pub start_cfi: String,
+    pub end_cfi: String,
+    pub start_progress: f64,
+    pub end_progress: f64,
+    /// Words moved past going forward, not counting skips.
+    pub words: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadingStats {
+    /// Milliseconds spent reading.
+    pub time: i64,
+    pub words: i64,
+    pub words_per_minute: Option<f64>,
+    /// Consecutive days read up to today, or up to yesterday if nothing has
+    /// been read yet today.
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    /// Most recently read first.
+    pub books: Vec<BookStats>,
+    /// Days with any reading, oldest first.
+    pub days: Vec<DayStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookStats {
+    pub book_id: String,
+    pub title: String,
+    pub time: i64,
+    pub words: i64,
+    pub sessions: i64,
+    pub last_read: i64,
+    pub words_per_minute: Option<f64>,
+}
+
+/// Reading on one local day. Sessions count towards the day they started.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DayStats {
+    /// `YYYY-MM-DD`.
+    pub date: String,
+    pub time: i64,
+    pub words: i64,
+    pub pages: f64,
+}
+
+/// Estimated reading time to the end of the chapter and of the book, at the
+/// speed measured for the book, or failing that for the whole library.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeLeft {
+    pub chapter_words: usize,
+    pub book_words: usize,
+    pub chapter_minutes: f64,
+    pub book_minutes: f64,
+    pub words_per_minute: f64,
+}
+
+/// Where the last heartbeat of a session left off.
+struct SessionEnd {
+    book_id: String,
+    ended_at: i64,
+    position: TextPosition,
+}
+
+impl Library {
+    /// Records that the reader is at `heartbeat`'s position now, extending
+    /// its session or starting a new one. The book must be open in
+    /// `archive`, to count the words covered since the last heartbeat with
+    /// `words`.
+    pub fn record_heartbeat(
+        &self,
+        heartbeat: &Heartbeat,
+        archive: &mut EpubArchive,
+        structure: &BookStructure,
+        words: &mut WordIndex,
+    ) -> Result<ReadingSession, StatsError> {
+        let now = now_millis();
+        let position = TextPosition {
+            index: heartbeat.index,
+            offset: heartbeat.offset,
+        };
+        let cfi = epub::cfi_at(archive, structure, position.index, position.offset, 0)?;
+        let last = match &heartbeat.session_id {
+            Some(id) => {
+                let conn = self.conn.lock().unwrap();
+                session_end(&conn, id)?
+            }
+            None => None,
+        };
+        let last = last
+            .filter(|end| end.book_id == heartbeat.book_id && now - end.ended_at <= SESSION_GAP_MS);
+        let (Some(id), Some(last)) = (&heartbeat.session_id, last) else {
+            let conn = self.conn.lock().unwrap();
+            return Ok(start_session(&conn, heartbeat, &cfi.to_string(), now)?);
+        };
+
+        // Reading the book is the slow part; keep it outside the lock.
+        let words = words.words_between(archive, structure, last.position, position)?;
+        let minutes = (now - last.ended_at) as f64 / 60_000.0;
+        let words = if words as f64 > minutes * MAX_WORDS_PER_MINUTE {
+            0
+        } else {
+            words
+        };
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.query_row(
+            &format!(
+                "UPDATE reading_sessions SET
+                     ended_at = ?2,
+                     end_cfi = ?3,
+                     end_progress = ?4,
+                     end_index = ?5,
+                     end_offset = ?6,
+                     words = words + ?7
+                 WHERE id = ?1
+                 RETURNING {SESSION_COLUMNS}"
+            ),
+            params![
+                id,
+                now,
+                cfi.to_string(),
+                heartbeat.progress,
+                position.index,
+                position.offset,
+                words
+            ],
+            session_from_row,
+        )?)
+    }
+
+    /// A book's reading sessions, most recent first.
+    pub fn reading_sessions(&self, book_id: &str) -> rusqlite::Result<Vec<ReadingSession>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {SESSION_COLUMNS} FROM reading_sessions
+             WHERE book_id = ?1 ORDER BY started_at DESC"
+        ))?;
+        let sessions = stmt.query_map([book_id], session_from_row)?.collect();
+        sessions
+    }
+
+    /// Totals over every session. Days run midnight to midnight at
+    /// `utc_offset` minutes from UTC, as JavaScript's
+    /// `-new Date().getTimezoneOffset()` gives it.
+    pub fn reading_stats(&self, utc_offset: i64) -> rusqlite::Result<ReadingStats> {
+        let conn = self.conn.lock().unwrap();
+        let offset_ms = utc_offset * 60 * 1000;
+
+        let (time, words): (i64, i64) = conn.query_row(
+            "SELECT COALESCE(SUM(ended_at - started_at), 0), COALESCE(SUM(words), 0)
+             FROM reading_sessions",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let mut stmt = conn.prepare(
+            "SELECT s.book_id, b.title, SUM(s.ended_at - s.started_at), SUM(s.words),
+                    COUNT(*), MAX(s.ended_at)
+             FROM reading_sessions s JOIN books b ON b.id = s.book_id
+             GROUP BY s.book_id
+             ORDER BY MAX(s.ended_at) DESC",
+        )?;
+        let books = stmt
+            .query_map([], |row| {
+                let time = row.get(2)?;
+                let words = row.get(3)?;
+                Ok(BookStats {
+                    book_id: row.get(0)?,
+                    title: row.get(1)?,
+                    time,
+                    words,
+                    sessions: row.get(4)?,
+                    last_read: row.get(5)?,
+                    words_per_minute: words_per_minute(time, words),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT (started_at + ?1) / ?2 AS day,
+                    date((started_at + ?1) / 1000, 'unixepoch'),
+                    SUM(ended_at - started_at), SUM(words)
+             FROM reading_sessions
+             GROUP BY day
+             ORDER BY day",
+        )?;
+        let mut day_numbers = Vec::new();
+        let days = stmt
+            .query_map(params![offset_ms, DAY_MS], |row| {
+                day_numbers.push(row.get::<_, i64>(0)?);
+                let words: i64 = row.get(3)?;
+                Ok(DayStats {
+                    date: row.get(1)?,
+                    time: row.get(2)?,
+                    words,
+                    pages: words as f64 / WORDS_PER_PAGE,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let today = (now_millis() + offset_ms).div_euclid(DAY_MS);
+        let (current_streak, longest_streak) = streaks(&day_numbers, today);
+        Ok(ReadingStats {
+            time,
+            words,
+            words_per_minute: words_per_minute(time, words),
+            current_streak,
+            longest_streak,
+            books,
+            days,
+        })
+    }
+
+    /// How long the rest of the chapter and book should take from `at`, in
+    /// the book open in `archive`.
+    pub fn time_left(
+        &self,
+        book_id: &str,
+        archive: &mut EpubArchive,
+        structure: &BookStructure,
+        words: &mut WordIndex,
+        at: TextPosition,
+    ) -> Result<TimeLeft, StatsError> {
+        let speed = {
+            let conn = self.conn.lock().unwrap();
+            measured_speed(&conn, Some(book_id))?
+                .or(measured_speed(&conn, None)?)
+                .unwrap_or(DEFAULT_WORDS_PER_MINUTE)
+        };
+        let length = self.book_length(book_id)?;
+        let chapter = length.spine.get(at.index).map_or(0, |c| c.words);
+        let read = words.words_before(archive, structure, at)?;
+        let chapter_words = chapter.saturating_sub(read);
+        let later: usize = length
+            .spine
+            .iter()
+            .skip(at.index + 1)
+            .map(|c| c.words)
+            .sum();
+        let book_words = chapter_words + later;
+        Ok(TimeLeft {
+            chapter_words,
+            book_words,
+            chapter_minutes: chapter_words as f64 / speed,
+            book_minutes: book_words as f64 / speed,
+            words_per_minute: speed,
+        })
+    }
+}
+
+fn start_session(
+    conn: &Connection,
+    heartbeat: &Heartbeat,
+    cfi: &str,
+    now: i64,
+) -> rusqlite::Result<ReadingSession> {
+    conn.query_row(
+        &format!(
+            "INSERT INTO reading_sessions
+                 (id, book_id, started_at, ended_at, start_cfi, end_cfi,
+                  start_progress, end_progress, end_index, end_offset)
+             VALUES (?1, ?2, ?3, ?3, ?4, ?4, ?5, ?5, ?6, ?7)
+             RETURNING {SESSION_COLUMNS}"
+        ),
+        params![
+            uuid::Uuid::new_v4().to_string(),
+            heartbeat.book_id,
+            now,
+            cfi,
+            heartbeat.progress,
+            heartbeat.index,
+            heartbeat.offset
+        ],
+        session_from_row,
+    )
+}
+
+fn session_end(conn: &Connection, id: &str) -> rusqlite::Result<Option<SessionEnd>> {
+    conn.query_row(
+        "SELECT book_id, ended_at, end_index, end_offset FROM reading_sessions WHERE id = ?1",
+        [id],
+        |row| {
+            Ok(SessionEnd {
+                book_id: row.get(0)?,
+                ended_at: row.get(1)?,
+                position: TextPosition {
+                    index: row.get(2)?,
+                    offset: row.get(3)?,
+                },
+            })
+        },
+    )
+    .optional()
+}
+
+/// Words per minute over a book's sessions, or all of them, once there is
+/// enough reading time for it to mean anything.
+fn measured_speed(conn: &Connection, book_id: Option<&str>) -> rusqlite::Result<Option<f64>> {
+    let (time, words): (i64, i64) = conn.query_row(
+        "SELECT COALESCE(SUM(ended_at - started_at), 0), COALESCE(SUM(words), 0)
+         FROM reading_sessions WHERE ?1 IS NULL OR book_id = ?1",
+        [book_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    if time < MIN_MEASURED_MS {
+        return Ok(None);
+    }
+    Ok(words_per_minute(time, words).filter(|&speed| speed > 0.0))
+}
+
+fn words_per_minute(time: i64, words: i64) -> Option<f64> {
+    (time > 0).then(|| words as f64 / (time as f64 / 60_000.0))
+}
+
+/// The run of consecutive days ending today or yesterday, and the longest
+/// run, given the days read in ascending order.
+fn streaks(days: &[i64], today: i64) -> (u32, u32) {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous = None;
+    for &day in days {
+        run = if previous == Some(day - 1) {
+            run + 1
+        } else {
+            1
+        };
+        longest = longest.max(run);
+        previous = Some(day);
+    }
+    let current = match previous {
+        Some(last) if last >= today - 1 => run,
+        _ => 0,
+    };
+    (current, longest)
+}
+
+fn session_from_row(row: &Row) -> rusqlite::Result<ReadingSession> {
+    Ok(ReadingSession {
+        id: row.get(0)?,
+        book_id: row.get(1)?,
+        started_at: row.get(2)?,
+        ended_at: row.get(3)?,
+        start_cfi: row.get(4)?,
+        end_cfi: row.get(5)?,
+        start_progress: row.get(6)?,
+        end_progress: row.get(7)?,
+        words: row.get(8)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::NewBook;
+    use crate::test_support::ScratchDir;
+
+    /// 2024-05-01T00:00:00Z.
+    const MAY_1: i64 = 1_714_521_600_000;
+    const HOUR_MS: i64 = 60 * 60 * 1000;
+
+    fn add_session(library: &Library, book_id: &str, started_at: i64, minutes: i64, words: i64) {
+        library
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO reading_sessions
+                     (id, book_id, started_at, ended_at, start_cfi, end_cfi,
+                      start_progress, end_progress, end_index, end_offset, words)
+                 VALUES (?1, ?2, ?3, ?4, '', '', 0, 0, 0, 0, ?5)",
+                params![
+                    uuid::Uuid::new_v4().to_string(),
+                    book_id,
+                    started_at,
+                    started_at + minutes * 60_000,
+                    words
+                ],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn counts_streaks() {
+        assert_eq!(streaks(&[], 100), (0, 0));
+        assert_eq!(streaks(&[100], 100), (1, 1));
+        // Nothing read yet today doesn't break the streak...
+        assert_eq!(streaks(&[97, 98, 99], 100), (3, 3));
+        // ...but nothing read yesterday does.
+        assert_eq!(streaks(&[97, 98], 100), (0, 2));
+        assert_eq!(streaks(&[90, 91, 92, 93, 98, 99, 100], 100), (3, 4));
+        assert_eq!(streaks(&[90, 92, 94], 94), (1, 1));
+    }
+
+    #[test]
+    fn rolls_sessions_up_by_local_day() {
+        let dir = ScratchDir::new("stats-days");
+        let library = Library::open(&dir.join("library.sqlite3")).unwrap();
+        let book = NewBook {
+            path: dir.join("book.epub").to_string_lossy().into_owned(),
+            title: "Stats".to_string(),
+            author: String::new(),
+        };
+        let book = library.add(book, None).unwrap();
+        add_session(&library, &book.id, MAY_1 + 10 * HOUR_MS, 30, 500);
+        add_session(
+            &library,
+            &book.id,
+            MAY_1 + 23 * HOUR_MS + HOUR_MS / 2,
+            20,
+            250,
+        );
+        add_session(&library, &book.id, MAY_1 + 56 * HOUR_MS, 10, 100);
+
+        let days = |stats: &ReadingStats| -> Vec<(String, i64, i64)> {
+            let days = stats.days.iter();
+            days.map(|d| (d.date.clone(), d.time / 60_000, d.words))
+                .collect()
+        };
+        let utc = library.reading_stats(0).unwrap();
+        assert_eq!(
+            days(&utc),
+            [
+                ("2024-05-01".to_string(), 50, 750),
+                ("2024-05-03".to_string(), 10, 100)
+            ]
+        );
+        assert_eq!(utc.days[0].pages, 3.0);
+        assert_eq!((utc.time, utc.words), (60 * 60_000, 850));
+        assert_eq!((utc.current_streak, utc.longest_streak), (0, 1));
+
+        // Two hours ahead of UTC, the late session falls on the next day.
+        let ahead = library.reading_stats(120).unwrap();
+        assert_eq!(
+            days(&ahead),
+            [
+                ("2024-05-01".to_string(), 30, 500),
+                ("2024-05-02".to_string(), 20, 250),
+                ("2024-05-03".to_string(), 10, 100)
+            ]
+        );
+        assert_eq!(ahead.longest_streak, 3);
+        assert_eq!(ahead.books.len(), 1);
+        assert_eq!(ahead.books[0].sessions, 3);
+    }
+}
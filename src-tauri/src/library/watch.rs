@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+
+use super::import::{
+    import_directory, import_file, is_epub, refresh_file, AddError, ImportOutcome,
+};
+use super::{Book, Library};
+
+/// How long a path has to stay quiet before it is reconciled, so files that
+/// are still being copied or synced aren't imported half-written.
+const SETTLE_TIME: Duration = Duration::from_millis(1500);
+
+/// A filesystem change under a library root, after debouncing.
+#[derive(Debug, Clone)]
+pub enum FsChange {
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// Something happened at this path; look at what is there now.
+    Touched(PathBuf),
+}
+
+/// What a filesystem change did to the library, as sent to the frontend.
+#[derive(Debug, Clone, Serialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum LibraryChange {
+    Added {
+        book: Book,
+    },
+    Moved {
+        book: Book,
+        from: String,
+    },
+    /// The file was changed in place, and its details read again.
+    Updated {
+        book: Book,
+    },
+    /// The file is gone. The entry is kept so that its progress can be
+    /// reattached if the same content shows up elsewhere.
+    Deleted {
+        book_id: String,
+        path: String,
+    },
+}
+
+pub struct LibraryWatcher {
+    watcher: Mutex<RecommendedWatcher>,
+}
+
+impl LibraryWatcher {
+    /// Starts the watcher thread. `on_changes` receives debounced batches of
+    /// changes from that thread; nothing is watched until [`watch`] is called.
+    ///
+    /// [`watch`]: LibraryWatcher::watch
+    pub fn start(on_changes: impl Fn(Vec<FsChange>) + Send + 'static) -> notify::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let watcher = notify::recommended_watcher(tx)?;
+
+        thread::spawn(move || {
+            let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+            let mut ready = Vec::new();
+            loop {
+                match rx.recv_timeout(SETTLE_TIME / 4) {
+                    Ok(Ok(event)) => match event.kind {
+                        // Renames with both ends known are atomic; no need to wait.
+                        EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+                            if event.paths.len() == 2 =>
+                        {
+                            let [from, to] = <[PathBuf; 2]>::try_from(event.paths).unwrap();
+                            pending.remove(&from);
+                            ready.push(FsChange::Renamed { from, to });
+                        }
+                        EventKind::Access(_) => {}
+                        _ => {
+                            let now = Instant::now();
+                            pending.extend(event.paths.into_iter().map(|p| (p, now)));
+                        }
+                    },
+                    Ok(Err(_)) | Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                pending.retain(|path, seen| {
+                    let settled = seen.elapsed() >= SETTLE_TIME;
+                    if settled {
+                        ready.push(FsChange::Touched(path.clone()));
+                    }
+                    !settled
+                });
+                if !ready.is_empty() {
+                    on_changes(std::mem::take(&mut ready));
+                }
+            }
+        });
+
+        Ok(Self {
+            watcher: Mutex::new(watcher),
+        })
+    }
+
+    pub fn watch(&self, root: &Path) -> notify::Result<()> {
+        self.watcher
+            .lock()
+            .unwrap()
+            .watch(root, RecursiveMode::Recursive)
+    }
+
+    pub fn unwatch(&self, root: &Path) -> notify::Result<()> {
+        self.watcher.lock().unwrap().unwatch(root)
+    }
+}
+
+/// Applies a filesystem change to the library. Failures (unreadable or
+/// half-synced files) are skipped; the next change to the path retries.
+pub fn reconcile(library: &Library, change: &FsChange) -> Vec<LibraryChange> {
+    match change {
+        // Only what is under a root may be read, whatever the event says.
+        FsChange::Touched(path)
+            if path.exists() && !library.is_under_root(path).unwrap_or(false) =>
+        {
+            Vec::new()
+        }
+        // Moved out of every root, which as far as the library goes is
+        // deleted.
+        FsChange::Renamed { from, to }
+            if to.exists() && !library.is_under_root(to).unwrap_or(false) =>
+        {
+            reconcile(library, &FsChange::Touched(from.clone()))
+        }
+        FsChange::Renamed { from, to } => {
+            let from_str = from.to_string_lossy();
+            let to_str = to.to_string_lossy();
+            if to.is_dir() {
+                return library
+                    .relocate_dir(&from_str, &to_str)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|book| LibraryChange::Moved {
+                        from: book.path.replacen(&*to_str, &from_str, 1),
+                        book,
+                    })
+                    .collect();
+            }
+            match library.find_by_path(&from_str) {
+                Ok(Some(book)) => match library.relocate(&book.id, &to_str) {
+                    Ok(book) => vec![LibraryChange::Moved {
+                        book,
+                        from: from_str.into_owned(),
+                    }],
+                    // Moved over another book's file: this book's file is
+                    // gone, and that one's was changed in place.
+                    Err(AddError::PathTaken(_)) => {
+                        let mut changes = reconcile(library, &FsChange::Touched(from.clone()));
+                        changes.extend(reconcile(library, &FsChange::Touched(to.clone())));
+                        changes
+                    }
+                    Err(_) => Vec::new(),
+                },
+                // e.g. a download renamed from `book.epub.part` on completion
+                _ => reconcile(library, &FsChange::Touched(to.clone())),
+            }
+        }
+        FsChange::Touched(path) if path.is_dir() => {
+            let Ok(summary) = import_directory(library, path, true, |_| {}) else {
+                return Vec::new();
+            };
+            let added = summary
+                .imported
+                .into_iter()
+                .map(|book| LibraryChange::Added { book });
+            let moved = summary.relocated.into_iter().map(|r| LibraryChange::Moved {
+                book: r.book,
+                from: r.from,
+            });
+            added.chain(moved).collect()
+        }
+        FsChange::Touched(path) if path.is_file() => {
+            if !is_epub(path) {
+                return Vec::new();
+            }
+            if let Ok(Some(book)) = library.find_by_path(&path.to_string_lossy()) {
+                return match refresh_file(library, &book) {
+                    Ok(Some(book)) => vec![LibraryChange::Updated { book }],
+                    Ok(None) | Err(_) => Vec::new(),
+                };
+            }
+            match import_file(library, path) {
+                Ok(ImportOutcome::Added(book)) => vec![LibraryChange::Added { book }],
+                Ok(ImportOutcome::Relocated(r)) => vec![LibraryChange::Moved {
+                    book: r.book,
+                    from: r.from,
+                }],
+                Ok(ImportOutcome::Duplicate) | Err(_) => Vec::new(),
+            }
+        }
+        FsChange::Touched(path) => {
+            // A deleted file, or a directory moved out from under a root.
+            let path = path.to_string_lossy();
+            let gone = match library.find_by_path(&path) {
+                Ok(Some(book)) => vec![book],
+                _ => library.books_under(&path).unwrap_or_default(),
+            };
+            gone.into_iter()
+                .filter(|book| !Path::new(&book.path).exists())
+                .map(|book| LibraryChange::Deleted {
+                    book_id: book.id,
+                    path: book.path,
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_support::{write_epub, ScratchDir};
+
+    /// A library watching `root`, with a book imported from each file name.
+    fn library(dir: &ScratchDir, names: &[&str]) -> (Library, Vec<Book>) {
+        let root = dir.join("root");
+        fs::create_dir_all(&root).unwrap();
+        let library = Library::open(&dir.join("library.sqlite3")).unwrap();
+        library.add_root(&root.to_string_lossy()).unwrap();
+        let books = names
+            .iter()
+            .map(|name| {
+                let path = root.join(name);
+                write_epub(&path, name, &["<p>Text</p>"]);
+                match import_file(&library, &path).unwrap() {
+                    ImportOutcome::Added(book) => book,
+                    _ => panic!("{name} wasn't added"),
+                }
+            })
+            .collect();
+        (library, books)
+    }
+
+    #[test]
+    fn deletes_books_moved_out_of_the_roots() {
+        let dir = ScratchDir::new("watch-out");
+        let (library, books) = library(&dir, &["a.epub"]);
+        let from = dir.join("root/a.epub");
+        let to = dir.join("a.epub");
+        fs::rename(&from, &to).unwrap();
+
+        let changes = reconcile(&library, &FsChange::Renamed { from, to });
+        assert!(matches!(
+            &changes[..],
+            [LibraryChange::Deleted { book_id, .. }] if *book_id == books[0].id
+        ));
+        assert!(library
+            .find_by_path(&dir.join("a.epub").to_string_lossy())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn moves_books_within_the_roots() {
+        let dir = ScratchDir::new("watch-within");
+        let (library, books) = library(&dir, &["a.epub"]);
+        let from = dir.join("root/a.epub");
+        let to = dir.join("root/b.epub");
+        fs::rename(&from, &to).unwrap();
+
+        let changes = reconcile(
+            &library,
+            &FsChange::Renamed {
+                from,
+                to: to.clone(),
+            },
+        );
+        assert!(matches!(
+            &changes[..],
+            [LibraryChange::Moved { book, .. }] if book.id == books[0].id
+        ));
+        assert_eq!(
+            library.get(&books[0].id).unwrap().unwrap().path,
+            to.to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn handles_books_moved_over_others() {
+        let dir = ScratchDir::new("watch-over");
+        let (library, books) = library(&dir, &["a.epub", "b.epub"]);
+        let from = dir.join("root/a.epub");
+        let to = dir.join("root/b.epub");
+        assert!(matches!(
+            library.relocate(&books[0].id, &to.to_string_lossy()),
+            Err(AddError::PathTaken(_))
+        ));
+        fs::rename(&from, &to).unwrap();
+
+        let changes = reconcile(&library, &FsChange::Renamed { from, to });
+        assert!(matches!(
+            &changes[..],
+            [LibraryChange::Deleted { book_id, .. }, LibraryChange::Updated { book }]
+                if *book_id == books[0].id && book.id == books[1].id && book.title == "a.epub"
+        ));
+    }
+}
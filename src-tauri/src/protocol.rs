@@ -0,0 +1,226 @@
+use tauri::http::{header, HeaderValue, Request, Response, StatusCode, Uri};
+
+use crate::books::OpenBooks;
+use crate::epub;
+
+pub const SCHEME: &str = "epub";
+
+/// URL prefix that resources of `book_id` are served under. Windows and
+/// Android webviews only expose custom schemes as `http://<scheme>.localhost`.
+pub fn base_url(book_id: &str) -> String {
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://{SCHEME}.localhost/{book_id}/")
+    } else {
+        format!("{SCHEME}://{book_id}/")
+    }
+}
+
+/// Serves `epub://<book-id>/<path-inside-zip>` from an open book's archive.
+pub fn handle(books: &OpenBooks, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let Some((book_id, path)) = parse_uri(request.uri()) else {
+        return empty(StatusCode::BAD_REQUEST);
+    };
+    let Some(book) = books.get(&book_id) else {
+        return empty(StatusCode::NOT_FOUND);
+    };
+    let mut book = book.lock().unwrap();
+    let book = &mut *book;
+
+    // Entries never change while the archive is open, so the stored CRC is
+    // a good enough validator for the webview cache.
+    let Some(crc) = book.archive.crc32(&path) else {
+        return empty(StatusCode::NOT_FOUND);
+    };
+    let etag = format!("\"{crc:08x}\"");
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH);
+    if if_none_match.and_then(|v| v.to_str().ok()) == Some(etag.as_str()) {
+        return cached(Response::builder(), &etag)
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Vec::new())
+            .unwrap();
+    }
+
+    // Entries are read whole, even for a range: an obfuscated font has to be
+    // deobfuscated from its first byte and a deflated entry can't be sought
+    // in, and the protocol answers with a byte vector anyway. Entries are
+    // capped in size by the archive, and the webview caches what it gets.
+    let bytes = match epub::read_resource(&mut book.archive, &book.structure, &path) {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return empty(StatusCode::NOT_FOUND),
+        Err(_) => return empty(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let media_type = book
+        .structure
+        .manifest
+        .iter()
+        .find(|m| m.full_path == path && !m.media_type.is_empty())
+        .map_or_else(
+            || guess_media_type(&path),
+            |m| font_media_type(&m.media_type),
+        );
+
+    // Resources are only ever embedded in the reader. Should one be opened
+    // as a page of its own, such as an SVG or XHTML file, nothing in it runs.
+    let builder = cached(Response::builder(), &etag)
+        .header(header::CONTENT_TYPE, media_type)
+        .header(header::CONTENT_SECURITY_POLICY, "sandbox")
+        .header(header::ACCEPT_RANGES, "bytes");
+    let total = bytes.len();
+    match request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|v| parse_range(v, total))
+    {
+        None => builder.body(bytes),
+        Some(Some((start, end))) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {start}-{end}/{total}"),
+            )
+            .body(bytes[start..=end].to_vec()),
+        Some(None) => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{total}"))
+            .body(Vec::new()),
+    }
+    .unwrap()
+}
+
+fn cached(builder: tauri::http::response::Builder, etag: &str) -> tauri::http::response::Builder {
+    builder
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, "private, max-age=3600")
+        // Fonts referenced from chapter CSS are fetched cross-origin.
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+}
+
+fn empty(status: StatusCode) -> Response<Vec<u8>> {
+    Response::builder().status(status).body(Vec::new()).unwrap()
+}
+
+fn parse_uri(uri: &Uri) -> Option<(String, String)> {
+    let path = percent_encoding::percent_decode_str(uri.path()).decode_utf8_lossy();
+    let path = path.trim_start_matches('/');
+    let (book_id, path) = match uri.host() {
+        Some(host) if host != "localhost" && host != format!("{SCHEME}.localhost") => {
+            (host.to_string(), path.to_string())
+        }
+        _ => {
+            let (id, rest) = path.split_once('/')?;
+            (id.to_string(), rest.to_string())
+        }
+    };
+    (!book_id.is_empty() && !path.is_empty()).then_some((book_id, path))
+}
+
+/// Resolves a single `bytes=` range against a body of `total` bytes.
+/// Returns `None` when the header should be ignored (malformed or
+/// multi-range) and `Some(None)` when the range is unsatisfiable.
+fn parse_range(value: &HeaderValue, total: usize) -> Option<Option<(usize, usize)>> {
+    let spec = value.to_str().ok()?.strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let last = total.checked_sub(1);
+    let range = match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) if start <= end => {
+            last.filter(|&l| start <= l).map(|l| (start, end.min(l)))
+        }
+        (Ok(start), Err(_)) if end.is_empty() => last.filter(|&l| start <= l).map(|l| (start, l)),
+        // A suffix of zero bytes selects nothing.
+        (Err(_), Ok(len)) if start.is_empty() => last
+            .filter(|_| len > 0)
+            .map(|l| (total.saturating_sub(len), l)),
+        _ => return None,
+    };
+    Some(range)
+}
+
+/// The registered type for fonts declared with one of the older types EPUB
+/// 2 and various tools used, which webviews may refuse to load a font as.
+fn font_media_type(media_type: &str) -> &str {
+    match media_type.to_ascii_lowercase().as_str() {
+        "application/x-font-ttf" | "application/x-font-truetype" | "application/font-sfnt" => {
+            "font/ttf"
+        }
+        "application/vnd.ms-opentype"
+        | "application/x-font-otf"
+        | "application/x-font-opentype" => "font/otf",
+        "application/font-woff" | "application/x-font-woff" => "font/woff",
+        "application/font-woff2" => "font/woff2",
+        _ => media_type,
+    }
+}
+
+fn guess_media_type(path: &str) -> &'static str {
+    let ext = path.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("xhtml" | "xht") => "application/xhtml+xml",
+        Some("html" | "htm") => "text/html",
+        Some("css") => "text/css",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("mp3") => "audio/mpeg",
+        Some("m4a" | "mp4") => "audio/mp4",
+        Some("ogg" | "oga") => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(value: &str, total: usize) -> Option<Option<(usize, usize)>> {
+        parse_range(&HeaderValue::from_str(value).unwrap(), total)
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(range("bytes=0-4", 10), Some(Some((0, 4))));
+        assert_eq!(range("bytes=2-2", 10), Some(Some((2, 2))));
+        // An end past the body is cut short.
+        assert_eq!(range("bytes=5-99", 10), Some(Some((5, 9))));
+    }
+
+    #[test]
+    fn parses_open_ended_ranges() {
+        assert_eq!(range("bytes=4-", 10), Some(Some((4, 9))));
+        assert_eq!(range("bytes=9-", 10), Some(Some((9, 9))));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(range("bytes=-3", 10), Some(Some((7, 9))));
+        assert_eq!(range("bytes=-10", 10), Some(Some((0, 9))));
+        assert_eq!(range("bytes=-50", 10), Some(Some((0, 9))));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(range("bytes=10-", 10), Some(None));
+        assert_eq!(range("bytes=10-20", 10), Some(None));
+        assert_eq!(range("bytes=-0", 10), Some(None));
+        assert_eq!(range("bytes=0-", 0), Some(None));
+        assert_eq!(range("bytes=-5", 0), Some(None));
+    }
+
+    #[test]
+    fn ignores_other_ranges() {
+        // Multiple ranges get the whole body rather than a multipart one.
+        assert_eq!(range("bytes=0-1,4-5", 10), None);
+        assert_eq!(range("bytes=5-2", 10), None);
+        assert_eq!(range("bytes=a-b", 10), None);
+        assert_eq!(range("items=0-4", 10), None);
+        assert_eq!(range("bytes=-", 10), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! Fixtures shared by the unit tests.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A scratch directory under the system's temporary one, removed with
+/// everything in it on drop.
+pub struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("shpeegle-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Writes an EPUB 3 titled `title`, with the identifier
+/// `urn:uuid:<title>-0000-0000` and a chapter for each body in `chapters`.
+pub fn write_epub(path: &Path, title: &str, chapters: &[&str]) {
+    let file = fs::File::create(path).unwrap();
+    let mut zip = zip::ZipWriter::new(file);
+    let stored =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let manifest: String = (1..=chapters.len())
+        .map(|n| {
+            format!(r#"<item id="ch{n}" href="ch{n}.xhtml" media-type="application/xhtml+xml"/>"#)
+        })
+        .collect();
+    let spine: String = (1..=chapters.len())
+        .map(|n| format!(r#"<itemref idref="ch{n}"/>"#))
+        .collect();
+    let mut entries = vec![
+        ("mimetype".to_string(), "application/epub+zip".to_string()),
+        (
+            "META-INF/container.xml".to_string(),
+            r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#
+                .to_string(),
+        ),
+        (
+            "OEBPS/content.opf".to_string(),
+            format!(
+                r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">urn:uuid:{title}-0000-0000</dc:identifier>
+    <dc:title>{title}</dc:title>
+  </metadata>
+  <manifest>{manifest}</manifest>
+  <spine>{spine}</spine>
+</package>"#
+            ),
+        ),
+    ];
+    for (n, body) in chapters.iter().enumerate() {
+        entries.push((
+            format!("OEBPS/ch{}.xhtml", n + 1),
+            format!(
+                "<html><head><title>{}</title></head><body>{body}</body></html>",
+                n + 1
+            ),
+        ));
+    }
+    for (name, contents) in entries {
+        zip.start_file(name, stored).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
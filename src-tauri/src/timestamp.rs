@@ -0,0 +1,94 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+/// Reads timestamps like `2024-05-01 09:30:00` (KOReader),
+/// `2024-05-01T09:30:00.000Z` (Calibre) or just `2024-05-01`. The zone is
+/// left to the caller; a trailing `Z` is ignored.
+fn parse(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim().trim_end_matches('Z');
+    let (date, time) = text.split_once(['T', ' ']).unwrap_or((text, "00:00:00"));
+    let mut date = date.splitn(3, '-');
+    let year = date.next()?.parse().ok()?;
+    let month = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(|p| p.parse::<u32>().ok());
+    let (hours, minutes) = (time.next()??, time.next()??);
+    let seconds = time.next().unwrap_or(Some(0))?;
+    let millis = format!("{:0<3}", fraction.chars().take(3).collect::<String>())
+        .parse()
+        .unwrap_or(0);
+    NaiveDate::from_ymd_opt(year, month, day)?.and_hms_milli_opt(hours, minutes, seconds, millis)
+}
+
+/// Milliseconds since the Unix epoch from a UTC timestamp like
+/// `2024-05-01T09:30:00.000Z`, as Calibre writes them.
+pub fn parse_utc(text: &str) -> Option<i64> {
+    parse(text).map(|t| t.and_utc().timestamp_millis())
+}
+
+/// Like [`parse_utc`], for a time without a zone like
+/// `2024-05-01 09:30:00`, as KOReader writes them in the device's local
+/// time. That zone isn't recorded, so this computer's is used, on the
+/// assumption the two agree. Times skipped by a clock change are taken as
+/// UTC.
+pub fn parse_local(text: &str) -> Option<i64> {
+    let naive = parse(text)?;
+    let local = Local.from_local_datetime(&naive).earliest();
+    Some(local.map_or_else(
+        || naive.and_utc().timestamp_millis(),
+        |t| t.timestamp_millis(),
+    ))
+}
+
+/// UTC timestamp like `2024-05-01T09:30:00Z`.
+pub fn iso_utc(millis: i64) -> String {
+    DateTime::from_timestamp_millis(millis)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_utc("1970-01-01 00:00:00"), Some(0));
+        assert_eq!(parse_utc("2024-05-01 09:30:00"), Some(1_714_555_800_000));
+        assert_eq!(
+            parse_utc("2024-05-01T09:30:00.25Z"),
+            Some(1_714_555_800_250)
+        );
+        assert_eq!(parse_utc("2024-05-01"), Some(1_714_521_600_000));
+        assert_eq!(parse_utc("2024-13-01 00:00:00"), None);
+        assert_eq!(parse_utc("2024-02-30 00:00:00"), None);
+        assert_eq!(parse_utc("yesterday"), None);
+    }
+
+    #[test]
+    fn reads_local_times() {
+        let millis = parse_local("2024-05-01 09:30:00").unwrap();
+        let local = Local.timestamp_millis_opt(millis).unwrap().naive_local();
+        assert_eq!(local.to_string(), "2024-05-01 09:30:00");
+        assert_eq!(parse_local("yesterday"), None);
+    }
+
+    #[test]
+    fn ignores_non_ascii_fractions() {
+        // A multi-byte fraction mustn't be cut mid-character.
+        assert_eq!(parse_utc("2024-05-01 09:30:00.5€"), Some(1_714_555_800_000));
+        assert_eq!(
+            parse_utc("2024-05-01 09:30:00.€€€€"),
+            Some(1_714_555_800_000)
+        );
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(iso_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_utc(1_714_555_800_250), "2024-05-01T09:30:00Z");
+        assert_eq!(iso_utc(-1000), "1969-12-31T23:59:59Z");
+        assert_eq!(parse_utc(&iso_utc(951_782_400_000)), Some(951_782_400_000));
+    }
+}
@@ -4,136 +4,82 @@ import Library from './components/Library';
 import Reader from './components/Reader';
 import useLibrary from './hooks/useLibrary';
 
-// Check if Tauri is available
-const isTauri = () => '__TAURI__' in window || '__TAURI_INTERNALS__' in window;
-
-async function openFileDialog() {
-  if (isTauri()) {
-    const { open } = await import('@tauri-apps/plugin-dialog');
-    const selected = await open({
-      multiple: false,
-      filters: [{ name: 'EPUB Files', extensions: ['epub'] }],
-    });
-    if (!selected) return null;
-    // Read file via Tauri command
-    const { invoke } = await import('@tauri-apps/api/core');
-    const bytes = await invoke('read_file_bytes', { path: selected });
-    return { path: selected, buffer: new Uint8Array(bytes).buffer };
-  } else {
-    // Browser fallback: use file input
-    return new Promise((resolve) => {
-      const input = document.createElement('input');
-      input.type = 'file';
-      input.accept = '.epub';
-      input.onchange = async (e) => {
-        const file = e.target.files?.[0];
-        if (!file) return resolve(null);
-        const buffer = await file.arrayBuffer();
-        resolve({ path: file.name, buffer, fileName: file.name });
-      };
-      input.click();
-    });
-  }
-}
-
-async function extractMetadata(buffer) {
-  // Clone the buffer — ePub consumes/detaches the ArrayBuffer internally,
-  // so the original would be unusable for the actual Reader
-  const bufferCopy = buffer.slice(0);
-  const ePub = (await import('epubjs')).default;
-  const book = ePub(bufferCopy);
-  await book.ready;
-
-  const meta = await book.loaded.metadata;
-  let cover = null;
-  try {
-    const coverUrl = await book.coverUrl();
-    if (coverUrl) {
-      const resp = await fetch(coverUrl);
-      const blob = await resp.blob();
-      cover = await new Promise((res) => {
-        const reader = new FileReader();
-        reader.onloadend = () => res(reader.result);
-        reader.readAsDataURL(blob);
-      });
-    }
-  } catch {}
-
-  book.destroy();
-
-  return {
-    title: meta.title || 'Untitled',
-    author: meta.creator || '',
-    cover,
-  };
-}
-
 export default function App() {
-  const { library, addBook, updateBook, removeBook } = useLibrary();
-  const [currentBook, setCurrentBook] = useState(null); // { path, buffer, meta }
+  const { library, openFile, updateBook, locateBook, removeBook, reload } = useLibrary();
+  const [currentBook, setCurrentBook] = useState(null); // { meta, match }
   const [loading, setLoading] = useState(false);
+  const [loadingText, setLoadingText] = useState('Loading book...');
 
   const handleOpenFile = useCallback(async () => {
-    setLoading(true);
     try {
-      const result = await openFileDialog();
-      if (!result) { setLoading(false); return; }
-
-      const meta = await extractMetadata(result.buffer);
-      const bookPath = result.path;
-
-      addBook({
-        path: bookPath,
-        title: meta.title,
-        author: meta.author,
-        cover: meta.cover,
-      });
-
-      setCurrentBook({
-        path: bookPath,
-        buffer: result.buffer,
-        meta: {
-          ...meta,
-          ...library.books.find((b) => b.path === bookPath),
-        },
-      });
+      // The backend shows the dialog, so only files picked there are read.
+      const saved = await openFile();
+      if (saved) setCurrentBook({ meta: saved });
     } catch (err) {
       console.error('Failed to open file:', err);
     }
-    setLoading(false);
-  }, [addBook, library.books]);
+  }, [openFile]);
 
-  const handleOpenBook = useCallback(async (book) => {
-    setLoading(true);
+  // Watched folders are also imported up front; the backend keeps them in
+  // sync afterwards and reports changes through `library://changed`. The
+  // backend shows the folder dialog itself, so the overlay only comes up
+  // once importing starts.
+  const importFolder = useCallback(async (watch) => {
+    const { invoke } = await import('@tauri-apps/api/core');
+    const { listen } = await import('@tauri-apps/api/event');
+    const unlisten = await listen('library://import-progress', ({ payload }) => {
+      setLoading(true);
+      setLoadingText(`Importing ${payload.processed} of ${payload.total}...`);
+    });
     try {
-      let buffer;
-      if (isTauri()) {
-        const { invoke } = await import('@tauri-apps/api/core');
-        const bytes = await invoke('read_file_bytes', { path: book.path });
-        buffer = new Uint8Array(bytes).buffer;
-      } else {
-        // Browser mode: need to re-select file
-        const result = await openFileDialog();
-        if (!result) { setLoading(false); return; }
-        buffer = result.buffer;
-      }
-
-      setCurrentBook({
-        path: book.path,
-        buffer,
-        meta: book,
-      });
+      const summary = watch
+        ? await invoke('library_add_root')
+        : await invoke('import_directory', { recursive: true });
+      if (!summary) return;
+      if (summary.failed.length) console.warn('Some books could not be imported:', summary.failed);
+      await reload();
     } catch (err) {
-      console.error('Failed to open book:', err);
+      console.error('Failed to import folder:', err);
+    } finally {
+      unlisten();
+      setLoading(false);
+      setLoadingText('Loading book...');
     }
-    setLoading(false);
+  }, [reload]);
+
+  const handleImportFolder = useCallback(() => importFolder(false), [importFolder]);
+  const handleWatchFolder = useCallback(() => importFolder(true), [importFolder]);
+
+  // `match` is an optional search hit to open the book at.
+  const handleOpenBook = useCallback((book, match) => {
+    setCurrentBook({ meta: book, match });
   }, []);
 
-  const handleUpdateProgress = useCallback((cfi, progress) => {
-    if (currentBook?.path) {
-      updateBook(currentBook.path, { currentCfi: cfi, progress });
+  const handleUpdateProgress = useCallback((progress, currentCfi) => {
+    if (currentBook?.meta?.id) {
+      updateBook(currentBook.meta.id, { progress, currentCfi }).catch((err) =>
+        console.error('Failed to save progress:', err)
+      );
+    }
+  }, [currentBook?.meta?.id, updateBook]);
+
+  const handleUpdateBook = useCallback((updates) => {
+    if (currentBook?.meta?.id) {
+      updateBook(currentBook.meta.id, updates).catch((err) =>
+        console.error('Failed to save book settings:', err)
+      );
+    }
+  }, [currentBook?.meta?.id, updateBook]);
+
+  const handleLocate = useCallback(async () => {
+    if (!currentBook?.meta?.id) return;
+    try {
+      const saved = await locateBook(currentBook.meta.id);
+      if (saved) setCurrentBook({ meta: saved });
+    } catch (err) {
+      console.error('Failed to locate book:', err);
     }
-  }, [currentBook?.path, updateBook]);
+  }, [currentBook?.meta?.id, locateBook]);
 
   const handleBack = useCallback(() => {
     setCurrentBook(null);
@@ -152,7 +98,7 @@ export default function App() {
           >
             <div className="flex flex-col items-center gap-3">
               <div className="w-8 h-8 border-2 border-purple/30 border-t-purple-glow rounded-full animate-spin" />
-              <p className="text-sm text-muted">Loading book...</p>
+              <p className="text-sm text-muted">{loadingText}</p>
             </div>
           </motion.div>
         )}
@@ -168,11 +114,15 @@ export default function App() {
             transition={{ duration: 0.2 }}
             className="h-full"
           >
+            {/* Keyed by path so that a located book opens afresh */}
             <Reader
-              bookData={{ buffer: currentBook.buffer }}
+              key={`${currentBook.meta.id}:${currentBook.meta.path}`}
               bookMeta={currentBook.meta}
+              initialMatch={currentBook.match}
               onBack={handleBack}
               onUpdateProgress={handleUpdateProgress}
+              onUpdateBook={handleUpdateBook}
+              onLocate={handleLocate}
             />
           </motion.div>
         ) : (
@@ -188,6 +138,8 @@ export default function App() {
               books={library.books}
               onOpenBook={handleOpenBook}
               onOpenFile={handleOpenFile}
+              onImportFolder={handleImportFolder}
+              onWatchFolder={handleWatchFolder}
               onRemoveBook={removeBook}
             />
           </motion.div>
@@ -0,0 +1,84 @@
+import { useState, useEffect, useRef } from 'react';
+import { motion } from 'framer-motion';
+import { invoke } from '@tauri-apps/api/core';
+import { Highlighter } from 'lucide-react';
+
+const FORMATS = [
+  { value: 'markdown', label: 'Markdown' },
+  { value: 'json', label: 'JSON' },
+  { value: 'csv', label: 'CSV' },
+];
+
+// Exports highlights and notes through the native save dialog, and imports
+// them from KOReader or Calibre: for one book when `bookId` is given,
+// otherwise for the whole library. `onImported` gets the import summary.
+export default function AnnotationsMenu({ bookId, onImported, className, children, ...buttonProps }) {
+  const [open, setOpen] = useState(false);
+  const menuRef = useRef(null);
+
+  useEffect(() => {
+    if (!open) return;
+    const close = (e) => {
+      if (!menuRef.current?.contains(e.target)) setOpen(false);
+    };
+    window.addEventListener('mousedown', close);
+    return () => window.removeEventListener('mousedown', close);
+  }, [open]);
+
+  const handleExport = async (format) => {
+    setOpen(false);
+    try {
+      await invoke('export_annotations', { bookId: bookId ?? null, format });
+    } catch (err) {
+      console.error('Export failed:', err);
+    }
+  };
+
+  const handleImport = async () => {
+    setOpen(false);
+    try {
+      const summary = await invoke('import_annotations', { bookId: bookId ?? null });
+      if (!summary) return;
+      if (summary.unplaced) console.warn(`${summary.unplaced} annotations could not be placed in the book`);
+      onImported?.(summary);
+    } catch (err) {
+      console.error('Import failed:', err);
+    }
+  };
+
+  return (
+    <div ref={menuRef} className="relative">
+      <motion.button
+        {...buttonProps}
+        onClick={() => setOpen(!open)}
+        className={className}
+        title="Import or export highlights and notes"
+      >
+        <Highlighter size={children ? 16 : 18} />
+        {children}
+      </motion.button>
+      {open && (
+        <div className="absolute right-0 top-full mt-1 w-56 py-1 rounded-xl bg-abyss border border-border shadow-lg z-30">
+          <p className="px-4 pt-1.5 pb-1 text-xs text-muted">Export as</p>
+          {FORMATS.map((f) => (
+            <button
+              key={f.value}
+              onClick={() => handleExport(f.value)}
+              className="block w-full text-left px-4 py-2 text-sm text-text hover:text-bright hover:bg-surface transition-colors"
+            >
+              {f.label}
+            </button>
+          ))}
+          <div className="my-1 border-t border-border" />
+          <button
+            onClick={handleImport}
+            title="KOReader doesn't record its time zone, so its times are read in this computer's"
+            className="block w-full text-left px-4 py-2 text-sm text-text hover:text-bright hover:bg-surface transition-colors"
+          >
+            Import from KOReader or Calibre…
+          </button>
+        </div>
+      )}
+    </div>
+  );
+}
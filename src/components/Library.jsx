@@ -1,5 +1,11 @@
+import { useCallback } from 'react';
 import { motion } from 'framer-motion';
-import { BookOpen, Plus, Trash2, Clock } from 'lucide-react';
+import { invoke } from '@tauri-apps/api/core';
+import { BookOpen, Plus, Trash2, Clock, FolderPlus, FolderSync, Search } from 'lucide-react';
+import useCover from '../hooks/useCover';
+import useSearch from '../hooks/useSearch';
+import SearchSnippet from './SearchSnippet';
+import AnnotationsMenu from './AnnotationsMenu';
 
 function timeAgo(ts) {
   if (!ts) return '';
@@ -13,7 +19,67 @@ function timeAgo(ts) {
   return `${days}d ago`;
 }
 
-export default function Library({ books, onOpenBook, onOpenFile, onRemoveBook }) {
+function BookCover({ book }) {
+  const cover = useCover(book.id);
+
+  if (cover) {
+    return <img src={cover} alt={book.title} className="w-full h-full object-cover" />;
+  }
+  return (
+    <div className="w-full h-full flex flex-col items-center justify-center p-4 bg-gradient-to-br from-surface to-raised">
+      <BookOpen size={28} className="text-muted mb-3" />
+      <span className="text-xs text-muted text-center font-medium leading-tight line-clamp-3">
+        {book.title || 'Untitled'}
+      </span>
+    </div>
+  );
+}
+
+// Library-wide hits arrive grouped by book, best match first.
+function SearchResults({ books, search, onOpenBook }) {
+  if (search.searching && !search.hits.length) {
+    return <p className="text-sm text-muted">Searching...</p>;
+  }
+  if (!search.hits.length) {
+    return <p className="text-sm text-muted">No matches for “{search.query.trim()}”</p>;
+  }
+  const groups = [];
+  for (const hit of search.hits) {
+    const last = groups[groups.length - 1];
+    if (last?.bookId === hit.bookId) last.hits.push(hit);
+    else groups.push({ bookId: hit.bookId, hits: [hit] });
+  }
+  return (
+    <div className="max-w-3xl space-y-6">
+      {groups.map(({ bookId, hits }) => {
+        const book = books.find((b) => b.id === bookId);
+        if (!book) return null;
+        return (
+          <div key={bookId}>
+            <h3 className="text-sm font-medium text-bright">{book.title || 'Untitled'}</h3>
+            {book.author && <p className="text-xs text-muted mb-2">{book.author}</p>}
+            <div className="space-y-1">
+              {hits.map((hit, i) => (
+                <button
+                  key={i}
+                  onClick={() => onOpenBook(book, hit)}
+                  className="block w-full text-left px-3 py-2 rounded-lg bg-surface/50 border border-transparent hover:border-border-light transition-colors"
+                >
+                  <SearchSnippet hit={hit} />
+                </button>
+              ))}
+            </div>
+          </div>
+        );
+      })}
+    </div>
+  );
+}
+
+export default function Library({ books, onOpenBook, onOpenFile, onImportFolder, onWatchFolder, onRemoveBook }) {
+  const searchLibrary = useCallback((query) => invoke('search_library', { query }), []);
+  const search = useSearch(searchLibrary);
+
   return (
     <div className="h-full flex flex-col bg-void">
       {/* Header */}
@@ -27,20 +93,64 @@ export default function Library({ books, onOpenBook, onOpenFile, onRemoveBook })
             <p className="text-xs text-muted">Your epub library</p>
           </div>
         </div>
-        <motion.button
-          whileHover={{ scale: 1.05 }}
-          whileTap={{ scale: 0.95 }}
-          onClick={onOpenFile}
-          className="flex items-center gap-2 px-5 py-2.5 rounded-xl bg-purple text-white text-sm font-medium hover:bg-purple-dim transition-colors"
-        >
-          <Plus size={16} />
-          Open Book
-        </motion.button>
+        <div className="flex items-center gap-2">
+          <AnnotationsMenu
+            whileHover={{ scale: 1.05 }}
+            whileTap={{ scale: 0.95 }}
+            className="flex items-center gap-2 px-5 py-2.5 rounded-xl bg-surface border border-border text-text text-sm font-medium hover:text-bright hover:border-border-light transition-colors"
+          >
+            Annotations
+          </AnnotationsMenu>
+          <motion.button
+            whileHover={{ scale: 1.05 }}
+            whileTap={{ scale: 0.95 }}
+            onClick={onWatchFolder}
+            title="Import a folder and keep the library in sync with it"
+            className="flex items-center gap-2 px-5 py-2.5 rounded-xl bg-surface border border-border text-text text-sm font-medium hover:text-bright hover:border-border-light transition-colors"
+          >
+            <FolderSync size={16} />
+            Watch Folder
+          </motion.button>
+          <motion.button
+            whileHover={{ scale: 1.05 }}
+            whileTap={{ scale: 0.95 }}
+            onClick={onImportFolder}
+            className="flex items-center gap-2 px-5 py-2.5 rounded-xl bg-surface border border-border text-text text-sm font-medium hover:text-bright hover:border-border-light transition-colors"
+          >
+            <FolderPlus size={16} />
+            Import Folder
+          </motion.button>
+          <motion.button
+            whileHover={{ scale: 1.05 }}
+            whileTap={{ scale: 0.95 }}
+            onClick={onOpenFile}
+            className="flex items-center gap-2 px-5 py-2.5 rounded-xl bg-purple text-white text-sm font-medium hover:bg-purple-dim transition-colors"
+          >
+            <Plus size={16} />
+            Open Book
+          </motion.button>
+        </div>
       </div>
 
+      {books.length > 0 && (
+        <div className="px-8 pt-6">
+          <div className="flex items-center gap-2 max-w-md px-3 py-2 rounded-xl bg-surface border border-border focus-within:border-purple/40 transition-colors">
+            <Search size={14} className="text-muted flex-shrink-0" />
+            <input
+              value={search.query}
+              onChange={(e) => search.setQuery(e.target.value)}
+              placeholder="Search inside your books"
+              className="flex-1 min-w-0 bg-transparent text-sm text-bright placeholder:text-muted outline-none"
+            />
+          </div>
+        </div>
+      )}
+
       {/* Book grid */}
       <div className="flex-1 overflow-y-auto p-8">
-        {books.length === 0 ? (
+        {search.query.trim() ? (
+          <SearchResults books={books} search={search} onOpenBook={onOpenBook} />
+        ) : books.length === 0 ? (
           <div className="flex flex-col items-center justify-center h-full text-center">
             <motion.div
               initial={{ opacity: 0, y: 20 }}
@@ -67,7 +177,7 @@ export default function Library({ books, onOpenBook, onOpenFile, onRemoveBook })
           <div className="grid grid-cols-2 sm:grid-cols-3 md:grid-cols-4 lg:grid-cols-5 gap-5">
             {books.map((book, i) => (
               <motion.div
-                key={book.path}
+                key={book.id}
                 initial={{ opacity: 0, y: 20 }}
                 animate={{ opacity: 1, y: 0 }}
                 transition={{ delay: i * 0.05 }}
@@ -79,20 +189,7 @@ export default function Library({ books, onOpenBook, onOpenFile, onRemoveBook })
                 >
                   {/* Book cover */}
                   <div className="aspect-[2/3] rounded-lg overflow-hidden mb-3 bg-surface border border-border group-hover:border-purple/40 transition-all duration-200 group-hover:shadow-lg group-hover:shadow-purple/5 relative">
-                    {book.cover ? (
-                      <img
-                        src={book.cover}
-                        alt={book.title}
-                        className="w-full h-full object-cover"
-                      />
-                    ) : (
-                      <div className="w-full h-full flex flex-col items-center justify-center p-4 bg-gradient-to-br from-surface to-raised">
-                        <BookOpen size={28} className="text-muted mb-3" />
-                        <span className="text-xs text-muted text-center font-medium leading-tight line-clamp-3">
-                          {book.title || 'Untitled'}
-                        </span>
-                      </div>
-                    )}
+                    <BookCover book={book} />
                     {/* Progress bar */}
                     {book.progress > 0 && (
                       <div className="absolute bottom-0 left-0 right-0 h-1 bg-abyss/80">
@@ -121,7 +218,7 @@ export default function Library({ books, onOpenBook, onOpenFile, onRemoveBook })
                 <button
                   onClick={(e) => {
                     e.stopPropagation();
-                    onRemoveBook(book.path);
+                    onRemoveBook(book.id);
                   }}
                   className="absolute top-2 right-2 p-1.5 rounded-lg bg-abyss/80 border border-border text-muted hover:text-crimson-glow hover:border-crimson/40 opacity-0 group-hover:opacity-100 transition-all"
                 >
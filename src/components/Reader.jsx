@@ -1,6 +1,6 @@
-import { useState, useEffect, useRef, useCallback, useMemo } from 'react';
+import { useState, useEffect, useLayoutEffect, useRef, useCallback, useMemo } from 'react';
 import { motion, AnimatePresence } from 'framer-motion';
-import JSZip from 'jszip';
+import { invoke } from '@tauri-apps/api/core';
 import {
   ArrowLeft,
   List,
@@ -12,7 +12,19 @@ import {
   X,
   AlertCircle,
   ChevronUp,
+  ChevronLeft,
+  ChevronRight,
+  Search,
+  Bookmark,
+  BookmarkPlus,
+  Trash2,
+  FolderOpen,
 } from 'lucide-react';
+import useSearch from '../hooks/useSearch';
+import useBookmarks from '../hooks/useBookmarks';
+import useReadingSession from '../hooks/useReadingSession';
+import SearchSnippet from './SearchSnippet';
+import AnnotationsMenu from './AnnotationsMenu';
 
 const FONTS = [
   { label: 'Serif', value: "'Lora', Georgia, serif" },
@@ -20,218 +32,124 @@ const FONTS = [
   { label: 'System', value: "system-ui, sans-serif" },
 ];
 
-/* ── Path helpers ─────────────────────────────────────────── */
+const CONTENT_TYPES = ['application/xhtml+xml', 'text/html'];
 
-function normalizePath(path) {
-  const parts = path.split('/').filter(Boolean);
-  const stack = [];
-  for (const p of parts) {
-    if (p === '..') stack.pop();
-    else if (p !== '.') stack.push(p);
-  }
-  return stack.join('/');
-}
-
-function dirOf(path) {
-  const i = path.lastIndexOf('/');
-  return i >= 0 ? path.substring(0, i + 1) : '';
-}
-
-function resolveHref(basePath, href) {
-  if (!href) return '';
-  if (href.startsWith('/')) return normalizePath(href);
-  return normalizePath(dirOf(basePath) + href);
-}
-
-/* ── Epub parsing ─────────────────────────────────────────── */
-
-async function parseStructure(zip) {
-  const containerXml = await zip.file('META-INF/container.xml')?.async('text');
-  if (!containerXml) throw new Error('Invalid epub: missing container.xml');
-  const containerDoc = new DOMParser().parseFromString(containerXml, 'application/xml');
-  const opfPath = containerDoc.querySelector('rootfile')?.getAttribute('full-path');
-  if (!opfPath) throw new Error('Invalid epub: no rootfile');
-
-  const opfXml = await zip.file(opfPath)?.async('text');
-  if (!opfXml) throw new Error('Invalid epub: missing OPF');
-  const opfDoc = new DOMParser().parseFromString(opfXml, 'application/xml');
-
-  const manifest = {};
-  for (const item of opfDoc.querySelectorAll('manifest item')) {
-    const id = item.getAttribute('id');
-    const href = item.getAttribute('href');
-    manifest[id] = {
-      href,
-      mediaType: item.getAttribute('media-type') || '',
-      properties: item.getAttribute('properties') || '',
-      fullPath: resolveHref(opfPath, href),
-    };
-  }
-
-  const spine = [];
-  for (const ref of opfDoc.querySelectorAll('spine itemref')) {
-    const idref = ref.getAttribute('idref');
-    if (manifest[idref]) spine.push({ id: idref, ...manifest[idref] });
-  }
-
-  let toc = [];
-  const ncxItem = Object.values(manifest).find(
-    (m) => m.mediaType === 'application/x-dtbncx+xml'
-  );
-  if (ncxItem) {
-    try {
-      const ncxXml = await zip.file(ncxItem.fullPath)?.async('text');
-      if (ncxXml) {
-        const ncxDoc = new DOMParser().parseFromString(ncxXml, 'application/xml');
-        toc = parseNcxPoints(ncxDoc.querySelectorAll('navMap > navPoint'), ncxItem.fullPath);
-      }
-    } catch {}
-  }
-  if (!toc.length) {
-    const navItem = Object.values(manifest).find((m) => m.properties.includes('nav'));
-    if (navItem) {
-      try {
-        const navXml = await zip.file(navItem.fullPath)?.async('text');
-        if (navXml) {
-          const navDoc = new DOMParser().parseFromString(navXml, 'application/xhtml+xml');
-          const navEl =
-            navDoc.querySelector('nav[epub\\:type="toc"]') ||
-            navDoc.querySelector('nav');
-          if (navEl) toc = parseNavOl(navEl.querySelector('ol'), navItem.fullPath);
-        }
-      } catch {}
+// DOM range covering `length` UTF-16 code units of `root`'s text content,
+// starting at `offset` — the coordinates search hits are reported in.
+function rangeAt(root, offset, length) {
+  const walker = document.createTreeWalker(root, NodeFilter.SHOW_TEXT);
+  const range = document.createRange();
+  let pos = 0;
+  let started = false;
+  for (let node = walker.nextNode(); node; node = walker.nextNode()) {
+    const end = pos + node.length;
+    if (!started && offset < end) {
+      range.setStart(node, offset - pos);
+      started = true;
+    }
+    if (started && offset + length <= end) {
+      range.setEnd(node, offset + length - pos);
+      return range;
     }
+    pos = end;
   }
-
-  return { spine, manifest, toc };
+  return null;
 }
 
-function parseNcxPoints(points, ncxPath) {
-  const items = [];
-  for (const np of points) {
-    const label = np.querySelector(':scope > navLabel > text')?.textContent?.trim() || '';
-    const src = np.querySelector(':scope > content')?.getAttribute('src') || '';
-    const children = np.querySelectorAll(':scope > navPoint');
-    items.push({
-      label,
-      href: resolveHref(ncxPath, src),
-      subitems: children.length ? parseNcxPoints(children, ncxPath) : [],
-    });
+// Offset, in the same coordinates, of the caret position nearest a point
+function offsetAtPoint(root, x, y) {
+  let node, offset;
+  if (document.caretPositionFromPoint) {
+    const pos = document.caretPositionFromPoint(x, y);
+    node = pos?.offsetNode;
+    offset = pos?.offset;
+  } else if (document.caretRangeFromPoint) {
+    const range = document.caretRangeFromPoint(x, y);
+    node = range?.startContainer;
+    offset = range?.startOffset;
   }
-  return items;
+  if (!node || !root.contains(node)) return null;
+  const range = document.createRange();
+  range.setStart(root, 0);
+  range.setEnd(node, offset);
+  return range.toString().length;
 }
 
-function parseNavOl(ol, navPath) {
-  if (!ol) return [];
-  const items = [];
-  for (const li of ol.querySelectorAll(':scope > li')) {
-    const a = li.querySelector(':scope > a');
-    if (!a) continue;
-    const subOl = li.querySelector(':scope > ol');
-    items.push({
-      label: a.textContent?.trim() || '',
-      href: resolveHref(navPath, a.getAttribute('href') || ''),
-      subitems: subOl ? parseNavOl(subOl, navPath) : [],
-    });
-  }
-  return items;
-}
+/* ── Reader Component ─────────────────────────────────────── */
 
-/* ── Content loading ──────────────────────────────────────── */
+// Headings for the error codes the backend reports, where there is
+// something more useful to say than that the book didn't open.
+const ERROR_TITLES = {
+  notFound: 'Book file is missing',
+  drmProtected: 'This book is DRM-protected',
+  invalidArchive: 'Not a valid EPUB',
+  missingOpf: 'Not a valid EPUB',
+};
 
-function blobToDataUrl(blob) {
-  return new Promise((res) => {
-    const r = new FileReader();
-    r.onloadend = () => res(r.result);
-    r.readAsDataURL(blob);
-  });
+// Commands reject with `{ code, message }`; anything else (a thrown Error,
+// or a plain string) gets a generic code.
+function toError(err, fallback) {
+  if (err?.code) return err;
+  return { code: 'unknown', message: err?.message || (typeof err === 'string' ? err : fallback) };
 }
 
-async function loadSpineItem(zip, item) {
-  const raw = await zip.file(item.fullPath)?.async('text');
-  if (!raw) return '';
-
-  let doc = new DOMParser().parseFromString(raw, 'application/xhtml+xml');
-  if (doc.querySelector('parsererror')) {
-    doc = new DOMParser().parseFromString(raw, 'text/html');
-  }
-
-  const body = doc.querySelector('body') || doc.documentElement;
-
-  // Strip all stylesheets and inline styles
-  for (const el of doc.querySelectorAll('style, link[rel="stylesheet"]')) el.remove();
-  for (const el of body.querySelectorAll('[style]')) el.removeAttribute('style');
-
-  // Convert images to data URIs
-  const itemDir = dirOf(item.fullPath);
-  for (const img of body.querySelectorAll('img')) {
-    const src = img.getAttribute('src');
-    if (!src || src.startsWith('data:')) continue;
-    const fullPath = normalizePath(itemDir + src);
-    const file = zip.file(fullPath);
-    if (file) {
-      try {
-        const blob = await file.async('blob');
-        img.setAttribute('src', await blobToDataUrl(blob));
-      } catch {}
-    }
-  }
-  for (const img of body.querySelectorAll('image')) {
-    const href = img.getAttribute('xlink:href') || img.getAttribute('href');
-    if (!href || href.startsWith('data:')) continue;
-    const fullPath = normalizePath(itemDir + href);
-    const file = zip.file(fullPath);
-    if (file) {
-      try {
-        const blob = await file.async('blob');
-        const dataUrl = await blobToDataUrl(blob);
-        img.setAttribute('xlink:href', dataUrl);
-        img.setAttribute('href', dataUrl);
-      } catch {}
-    }
-  }
-
-  return body.innerHTML;
+// Where the book's "bodymatter" landmark points, if it has one the reader
+// can show.
+function startOfText(structure) {
+  const landmark = structure.landmarks.find((l) => l.kind === 'bodymatter');
+  if (!landmark) return null;
+  const [path, fragment] = landmark.href.split('#');
+  const index = structure.spine.findIndex((s) => s.fullPath === path);
+  if (index < 0 || !CONTENT_TYPES.includes(structure.spine[index].mediaType)) return null;
+  return { index, fragment: fragment || null, href: landmark.href };
 }
 
-async function loadEntireBook(buffer) {
-  const buf = buffer.slice ? buffer.slice(0) : buffer;
-  const zip = await JSZip.loadAsync(buf);
-  const { spine, toc } = await parseStructure(zip);
-
-  // Load every spine item, build one giant HTML string
-  const sections = [];
-  for (let i = 0; i < spine.length; i++) {
-    const item = spine[i];
-    // Only render XHTML/HTML content documents
-    const mt = item.mediaType;
-    if (mt !== 'application/xhtml+xml' && mt !== 'text/html') continue;
-
-    const html = await loadSpineItem(zip, item);
-    if (!html.trim()) continue;
-    sections.push({ id: `sec-${i}`, html, fullPath: item.fullPath });
-  }
+// The backend prefixes chapter ids so they can't clash with the app's own;
+// hrefs from the book's structure name them without it.
+const ID_PREFIX = 'epub-';
 
-  const combinedHtml = sections
-    .map((s) => `<div id="${s.id}">${s.html}</div>`)
-    .join('\n<hr/>\n');
+// A URL scheme, as in `https:` or `mailto:`.
+const EXTERNAL_LINK = /^[a-z][a-z0-9+.-]*:/i;
 
-  return { html: combinedHtml, toc, sections };
+function formatDuration(minutes) {
+  if (minutes < 1) return 'Under a minute';
+  const total = Math.round(minutes);
+  if (total < 60) return `${total} min`;
+  const hours = Math.floor(total / 60);
+  const rest = total % 60;
+  return rest ? `${hours} h ${rest} min` : `${hours} h`;
 }
 
-/* ── Reader Component ─────────────────────────────────────── */
-
-export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress }) {
+export default function Reader({ bookMeta, initialMatch, onBack, onUpdateProgress, onUpdateBook, onLocate }) {
   const contentRef = useRef(null);
+  const bodyRef = useRef(null);
   const initRef = useRef(false);
+  const pendingFragmentRef = useRef(null);
+  const pendingMatchRef = useRef(initialMatch || null);
+  const anchorRef = useRef(null); // text offset at the top of the page
 
-  const [bookContent, setBookContent] = useState(null);
+  const [book, setBook] = useState(null); // { id, structure }
+  const [spineIndex, setSpineIndex] = useState(null);
+  const [chapter, setChapter] = useState(null);
   const [loading, setLoading] = useState(true);
   const [error, setError] = useState(null);
   const [progress, setProgress] = useState(0);
+  const [length, setLength] = useState(null); // word and character counts per spine item
+  const [location, setLocation] = useState(null);
+  const [pageMap, setPageMap] = useState([]); // print page starts, in reading order
+  const [page, setPage] = useState(null);
+  const [pageQuery, setPageQuery] = useState('');
+  const [pageMissing, setPageMissing] = useState(false);
   const [showToc, setShowToc] = useState(false);
   const [showSettings, setShowSettings] = useState(false);
+  const [showSearch, setShowSearch] = useState(false);
+  const [showBookmarks, setShowBookmarks] = useState(false);
+  const [bookmarkName, setBookmarkName] = useState('');
+  const [note, setNote] = useState(null); // footnote shown in a popover, with where its link is
+  const noteRef = useRef(null);
   const [isFullWidth, setIsFullWidth] = useState(false);
+  // Per book, unlike the settings below, which apply to every book.
+  const [publisherStyles, setPublisherStyles] = useState(bookMeta?.publisherStyles ?? true);
 
   const [fontSize, setFontSize] = useState(() => {
     try { return parseInt(localStorage.getItem('shpeegle-fontsize')) || 18; } catch { return 18; }
@@ -252,80 +170,368 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
     } catch {}
   }, [fontSize, fontFamily, lineHeight]);
 
-  // Parse and load the ENTIRE book once. initRef prevents re-runs.
+  // Only XHTML/HTML spine items are rendered; keep their spine indices.
+  const contentIndices = useMemo(
+    () =>
+      (book?.structure.spine || [])
+        .map((item, i) => (CONTENT_TYPES.includes(item.mediaType) ? i : -1))
+        .filter((i) => i >= 0),
+    [book]
+  );
+  const position = contentIndices.indexOf(spineIndex);
+
+  // Open the book once; the backend keeps the archive open until we leave.
   useEffect(() => {
-    if (initRef.current || !bookData?.buffer) return;
+    if (initRef.current || !bookMeta?.id) return;
     initRef.current = true;
     setLoading(true);
     setError(null);
 
-    loadEntireBook(bookData.buffer)
+    invoke('open_book', { bookId: bookMeta.id })
+      .then(async (opened) => {
+        setBook(opened);
+        invoke('book_length', { bookId: opened.id })
+          .then(setLength)
+          .catch((err) => console.error('Failed to measure book:', err));
+        if (opened.structure.pageList.length) {
+          invoke('page_map', { bookId: opened.id })
+            .then(setPageMap)
+            .catch((err) => console.error('Failed to map page numbers:', err));
+        }
+        const first = opened.structure.spine.findIndex((item) =>
+          CONTENT_TYPES.includes(item.mediaType)
+        );
+        if (first < 0) throw new Error('This book has no readable chapters.');
+        if (initialMatch) {
+          setSpineIndex(initialMatch.chapterIndex);
+          return;
+        }
+        // Resume where the reader left off
+        const saved = bookMeta?.currentCfi
+          ? await invoke('resolve_cfi', { bookId: opened.id, cfi: bookMeta.currentCfi }).catch(
+              () => null
+            )
+          : null;
+        const start = startOfText(opened.structure);
+        if (saved) {
+          pendingMatchRef.current = { chapterIndex: saved.spineIndex, offset: saved.offset, length: 0 };
+          setSpineIndex(saved.spineIndex);
+        } else if (start) {
+          // A new book opens where the text starts, not on its title page
+          pendingFragmentRef.current = start.fragment;
+          setSpineIndex(start.index);
+        } else {
+          setSpineIndex(first);
+        }
+      })
+      .catch((err) => {
+        console.error('Book load error:', err);
+        setError(toError(err, 'Could not load this book.'));
+        setLoading(false);
+      });
+  }, [bookMeta, initialMatch]);
+
+  useEffect(() => {
+    if (!book) return;
+    return () => { invoke('close_book', { bookId: book.id }); };
+  }, [book]);
+
+  // Load the current chapter on demand
+  useEffect(() => {
+    if (!book || spineIndex === null) return;
+    let cancelled = false;
+    setLoading(true);
+    invoke('get_spine_item', { bookId: book.id, index: spineIndex })
       .then((result) => {
-        setBookContent(result);
+        if (cancelled) return;
+        setChapter(result);
         setLoading(false);
       })
       .catch((err) => {
-        console.error('Book load error:', err);
-        setError(err.message || 'Could not load this book.');
+        if (cancelled) return;
+        console.error('Chapter load error:', err);
+        setError(toError(err, 'Could not load this chapter.'));
         setLoading(false);
       });
-  }, [bookData]);
+    return () => { cancelled = true; };
+  }, [book, spineIndex]);
 
-  // Track scroll progress
+  // Scroll so the text at `offset` sits at the top of the page
+  const scrollToOffset = useCallback((offset) => {
+    const el = contentRef.current;
+    const range = bodyRef.current && rangeAt(bodyRef.current, offset, 0);
+    if (!el || !range) return false;
+    const rect = range.getBoundingClientRect();
+    if (!rect.top && !rect.height) {
+      range.startContainer.parentElement?.scrollIntoView({ block: 'start' });
+    } else {
+      const top = el.scrollTop + rect.top - el.getBoundingClientRect().top;
+      el.scrollTo({ top, behavior: 'instant' });
+    }
+    return true;
+  }, []);
+
+  // Scroll a search hit into view and select it; empty matches are saved
+  // positions, which only scroll
+  const revealMatch = useCallback((match) => {
+    if (!match.length) return scrollToOffset(match.offset);
+    const range = bodyRef.current && rangeAt(bodyRef.current, match.offset, match.length);
+    if (!range) return false;
+    range.startContainer.parentElement?.scrollIntoView({ block: 'center' });
+    const selection = window.getSelection();
+    selection.removeAllRanges();
+    selection.addRange(range);
+    return true;
+  }, [scrollToOffset]);
+
+  // After a chapter renders, jump to the pending search hit, fragment, or the top
   useEffect(() => {
     const el = contentRef.current;
-    if (!el) return;
+    if (!el || !chapter) return;
+    const match = pendingMatchRef.current;
+    pendingMatchRef.current = null;
+    if (match?.chapterIndex === chapter.index && revealMatch(match)) return;
+    const fragment = pendingFragmentRef.current;
+    pendingFragmentRef.current = null;
+    const target = fragment && el.querySelector(`[id="${CSS.escape(ID_PREFIX + fragment)}"]`);
+    if (target) target.scrollIntoView();
+    else el.scrollTo({ top: 0 });
+  }, [chapter, revealMatch]);
+
+  // Track scroll progress across the whole book
+  useEffect(() => {
+    const el = contentRef.current;
+    if (!el || !contentIndices.length || position < 0) return;
     let ticking = false;
     const onScroll = () => {
       if (ticking) return;
       ticking = true;
       requestAnimationFrame(() => {
         const max = el.scrollHeight - el.clientHeight;
-        const pct = max > 0 ? el.scrollTop / max : 0;
-        setProgress(Math.min(1, Math.max(0, pct)));
+        const pct = Math.min(1, Math.max(0, max > 0 ? el.scrollTop / max : 0));
+        const body = bodyRef.current;
+        if (body) {
+          const box = body.getBoundingClientRect();
+          const top = Math.max(box.top, el.getBoundingClientRect().top);
+          anchorRef.current = offsetAtPoint(body, box.left + box.width / 2, top + 1);
+        }
+        // Measured by the text at the top of the page, so the same place
+        // reads the same at any window size. Until the book is measured,
+        // every chapter counts the same.
+        const measured = chapter && length?.chars > 0 && length.spine[chapter.index];
+        if (measured) {
+          const within = pct >= 1 ? measured.chars : Math.min(anchorRef.current ?? 0, measured.chars);
+          const chars = measured.start + within;
+          setProgress(chars / length.chars);
+          setLocation(Math.min(length.locations, Math.floor(chars / length.locationChars) + 1));
+        } else {
+          setProgress((position + pct) / contentIndices.length);
+        }
+        if (chapter && pageMap.length) {
+          const offset = anchorRef.current ?? 0;
+          const current = pageMap.findLast(
+            (mark) => mark.spineIndex < chapter.index || (mark.spineIndex === chapter.index && mark.offset <= offset)
+          );
+          setPage(current?.label ?? null);
+        }
         ticking = false;
       });
     };
+    onScroll();
     el.addEventListener('scroll', onScroll, { passive: true });
     return () => el.removeEventListener('scroll', onScroll);
-  }, [bookContent]);
+  }, [chapter, position, contentIndices, length, pageMap]);
+
+  // CFI of the text at the top of the page, or null before anything renders
+  const currentCfi = useCallback(async () => {
+    const offset = anchorRef.current;
+    if (!book || !chapter || offset === null) return null;
+    return invoke('cfi_at', { bookId: book.id, index: chapter.index, offset }).catch(() => null);
+  }, [book, chapter]);
 
-  // Save progress periodically (debounced via effect)
+  // Save progress periodically (debounced via effect), with a CFI so the
+  // position survives layout changes
   useEffect(() => {
-    if (!bookContent) return;
-    const timer = setTimeout(() => {
-      onUpdateProgress?.(progress, progress);
+    if (!chapter) return;
+    const timer = setTimeout(async () => {
+      onUpdateProgress?.(progress, await currentCfi());
     }, 500);
     return () => clearTimeout(timer);
-  }, [progress, bookContent]);
+  }, [progress, chapter, currentCfi]);
 
-  // TOC navigation: scroll to the section
+  // Keep the same text at the top of the page when the layout changes
+  useLayoutEffect(() => {
+    const anchor = anchorRef.current;
+    if (anchor === null) return;
+    scrollToOffset(anchor);
+    // Width changes animate; settle again once the transition is over.
+    const timer = setTimeout(() => scrollToOffset(anchor), 350);
+    return () => clearTimeout(timer);
+  }, [fontSize, fontFamily, lineHeight, isFullWidth, scrollToOffset]);
+
+  const goToPosition = useCallback(
+    (pos) => {
+      if (pos < 0 || pos >= contentIndices.length) return;
+      setSpineIndex(contentIndices[pos]);
+    },
+    [contentIndices]
+  );
+
+  // TOC navigation: load the target chapter, then scroll to its fragment
   const goToHref = useCallback(
     (href) => {
-      if (!bookContent) return;
-      const basePath = href.split('#')[0];
-      const section = bookContent.sections.find(
-        (s) => s.fullPath.split('#')[0] === basePath
-      );
-      if (section) {
-        document.getElementById(section.id)?.scrollIntoView({ behavior: 'smooth' });
+      if (!book || !href) return;
+      const [path, fragment] = href.split('#');
+      const index = book.structure.spine.findIndex((s) => s.fullPath === path);
+      if (index >= 0) {
+        pendingFragmentRef.current = fragment || null;
+        if (index === spineIndex) {
+          const target = fragment && contentRef.current?.querySelector(`[id="${CSS.escape(ID_PREFIX + fragment)}"]`);
+          if (target) target.scrollIntoView({ behavior: 'smooth' });
+          else contentRef.current?.scrollTo({ top: 0, behavior: 'smooth' });
+        } else {
+          setSpineIndex(index);
+        }
       }
       setShowToc(false);
     },
-    [bookContent]
+    [book, spineIndex]
+  );
+
+  // Links within the book are resolved by the backend: notes open in a
+  // popover, anything else is navigated to. Links out of the book are left be.
+  const handleContentClick = useCallback(
+    async (e) => {
+      const link = e.target.closest('a[href]');
+      const href = link?.getAttribute('href');
+      if (!href || !book || !chapter || EXTERNAL_LINK.test(href)) return;
+      e.preventDefault();
+      try {
+        const target = await invoke('resolve_link', { bookId: book.id, index: chapter.index, href });
+        if (!target) return;
+        if (target.note) {
+          const rect = link.getBoundingClientRect();
+          setNote({ ...target.note, href: target.href, x: rect.left, y: rect.bottom, top: rect.top });
+        } else {
+          goToHref(target.href);
+        }
+      } catch (err) {
+        console.error('Failed to follow link:', err);
+      }
+    },
+    [book, chapter, goToHref]
+  );
+
+  const handleNoteClick = useCallback(
+    (e) => {
+      const href = e.target.closest('a[href]')?.getAttribute('href');
+      if (!href || EXTERNAL_LINK.test(href)) return;
+      e.preventDefault();
+      setNote(null);
+      goToHref(href);
+    },
+    [goToHref]
+  );
+
+  // The popover is placed against the link, so it goes once the page moves,
+  // as well as on a click anywhere else.
+  useEffect(() => {
+    if (!note) return;
+    const el = contentRef.current;
+    const close = () => setNote(null);
+    const onPointerDown = (e) => {
+      if (!noteRef.current?.contains(e.target)) close();
+    };
+    el?.addEventListener('scroll', close, { passive: true });
+    window.addEventListener('resize', close);
+    window.addEventListener('pointerdown', onPointerDown);
+    return () => {
+      el?.removeEventListener('scroll', close);
+      window.removeEventListener('resize', close);
+      window.removeEventListener('pointerdown', onPointerDown);
+    };
+  }, [note]);
+
+  useEffect(() => setNote(null), [chapter]);
+
+  const start = useMemo(() => book && startOfText(book.structure), [book]);
+  const inFrontMatter = start && chapter && chapter.index < start.index;
+
+  const searchBook = useCallback(
+    (query) => invoke('search_book', { bookId: bookMeta.id, query }),
+    [bookMeta?.id]
+  );
+  const search = useSearch(searchBook);
+
+  const goToMatch = useCallback(
+    (hit) => {
+      if (hit.chapterIndex === spineIndex) {
+        revealMatch(hit);
+      } else {
+        pendingMatchRef.current = hit;
+        setSpineIndex(hit.chapterIndex);
+      }
+    },
+    [spineIndex, revealMatch]
+  );
+
+  // Print page numbers are labels, so "xiv" and "214" alike match exactly.
+  const goToPage = useCallback(() => {
+    const label = pageQuery.trim().toLowerCase();
+    const mark = label && pageMap.find((m) => m.label.toLowerCase() === label);
+    setPageMissing(Boolean(label) && !mark);
+    if (!mark) return;
+    goToMatch({ chapterIndex: mark.spineIndex, offset: mark.offset, length: 0 });
+    setShowToc(false);
+  }, [pageQuery, pageMap, goToMatch]);
+
+  const { bookmarks, addBookmark, removeBookmark, reload: reloadBookmarks } = useBookmarks(bookMeta?.id);
+  const timeLeft = useReadingSession(book?.id, chapter?.index ?? null, anchorRef, progress);
+
+  const handleAddBookmark = useCallback(async () => {
+    const cfi = await currentCfi();
+    if (!cfi) return;
+    try {
+      await addBookmark(cfi, bookmarkName.trim());
+      setBookmarkName('');
+    } catch (err) {
+      console.error('Failed to add bookmark:', err);
+    }
+  }, [currentCfi, addBookmark, bookmarkName]);
+
+  const goToCfi = useCallback(
+    async (cfi) => {
+      if (!book) return;
+      try {
+        const loc = await invoke('resolve_cfi', { bookId: book.id, cfi });
+        goToMatch({ chapterIndex: loc.spineIndex, offset: loc.offset, length: 0 });
+      } catch (err) {
+        console.error('Failed to open bookmark:', err);
+      }
+    },
+    [book, goToMatch]
   );
 
   // Keyboard
   useEffect(() => {
     const handleKey = (e) => {
       if (e.key === 'Escape') {
-        if (showToc) setShowToc(false);
+        if (note) setNote(null);
+        else if (showToc) setShowToc(false);
         else if (showSettings) setShowSettings(false);
+        else if (showSearch) setShowSearch(false);
+        else if (showBookmarks) setShowBookmarks(false);
+      } else if (e.key === 'f' && (e.ctrlKey || e.metaKey) && bookMeta?.id) {
+        e.preventDefault();
+        setShowSearch(true);
+        setShowToc(false);
+        setShowSettings(false);
+        setShowBookmarks(false);
       }
     };
     window.addEventListener('keydown', handleKey);
     return () => window.removeEventListener('keydown', handleKey);
-  }, [showToc, showSettings]);
+  }, [note, showToc, showSettings, showSearch, showBookmarks, bookMeta?.id]);
 
   // Dynamic CSS for epub content
   const contentStyles = useMemo(
@@ -339,6 +545,10 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
       max-width: 100%;
       word-wrap: break-word;
       overflow-wrap: break-word;
+      /* Publisher positioning stays inside the chapter. */
+      position: relative;
+      contain: layout paint;
+      isolation: isolate;
     }
     .epub-body * { max-width: 100%; box-sizing: border-box; }
     .epub-body p, .epub-body li, .epub-body td, .epub-body th,
@@ -392,6 +602,7 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
     .epub-body figcaption { font-size: 0.85em; color: #9090a0; margin-top: 0.5em; }
     .epub-body em { font-style: italic; }
     .epub-body strong, .epub-body b { color: #f4f4fa; font-weight: 600; }
+    .epub-body.epub-note { font-size: 0.85em; padding: 12px 16px 0; }
   `,
     [fontFamily, fontSize, lineHeight]
   );
@@ -410,11 +621,27 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
             <div className="w-14 h-14 rounded-2xl bg-crimson-muted/50 border border-crimson/30 flex items-center justify-center mx-auto mb-4">
               <AlertCircle size={24} className="text-crimson-glow" />
             </div>
-            <h3 className="text-lg font-semibold text-bright mb-2">Couldn't open book</h3>
-            <p className="text-sm text-muted mb-6">{error}</p>
-            <button onClick={onBack} className="inline-flex items-center gap-2 px-5 py-2.5 rounded-xl bg-purple text-white text-sm font-medium hover:bg-purple-dim transition-colors">
-              <ArrowLeft size={16} /> Back to Library
-            </button>
+            <h3 className="text-lg font-semibold text-bright mb-2">
+              {ERROR_TITLES[error.code] || "Couldn't open book"}
+            </h3>
+            <p className="text-sm text-muted mb-6">{error.message}</p>
+            <div className="flex flex-col items-center gap-2">
+              {error.code === 'notFound' && onLocate && (
+                <button onClick={onLocate} className="inline-flex items-center gap-2 px-5 py-2.5 rounded-xl bg-purple text-white text-sm font-medium hover:bg-purple-dim transition-colors">
+                  <FolderOpen size={16} /> Locate File…
+                </button>
+              )}
+              <button
+                onClick={onBack}
+                className={`inline-flex items-center gap-2 px-5 py-2.5 rounded-xl text-sm font-medium transition-colors ${
+                  error.code === 'notFound' && onLocate
+                    ? 'text-muted hover:text-bright hover:bg-surface'
+                    : 'bg-purple text-white hover:bg-purple-dim'
+                }`}
+              >
+                <ArrowLeft size={16} /> Back to Library
+              </button>
+            </div>
           </div>
         </div>
       </div>
@@ -424,6 +651,10 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
   return (
     <div className="h-full flex flex-col bg-void">
       <style dangerouslySetInnerHTML={{ __html: contentStyles }} />
+      {/* Already filtered and scoped to .epub-body by the backend */}
+      {publisherStyles && chapter?.styles && (
+        <style dangerouslySetInnerHTML={{ __html: chapter.styles }} />
+      )}
 
       {/* Top bar */}
       <div className="flex items-center justify-between px-4 py-2.5 border-b border-border bg-abyss/80 backdrop-blur-sm z-20 flex-shrink-0">
@@ -442,15 +673,40 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
         </div>
 
         <div className="flex items-center gap-1">
+          {bookMeta?.id && (
+            <button
+              onClick={() => { setShowSearch(!showSearch); setShowToc(false); setShowSettings(false); setShowBookmarks(false); }}
+              className={`p-2 rounded-lg transition-colors ${showSearch ? 'text-purple-glow bg-purple-muted/30' : 'text-muted hover:text-bright hover:bg-surface'}`}
+              title="Search in book"
+            >
+              <Search size={18} />
+            </button>
+          )}
+          {bookMeta?.id && (
+            <button
+              onClick={() => { setShowBookmarks(!showBookmarks); setShowToc(false); setShowSettings(false); setShowSearch(false); }}
+              className={`p-2 rounded-lg transition-colors ${showBookmarks ? 'text-purple-glow bg-purple-muted/30' : 'text-muted hover:text-bright hover:bg-surface'}`}
+              title="Bookmarks"
+            >
+              <Bookmark size={18} />
+            </button>
+          )}
+          {bookMeta?.id && (
+            <AnnotationsMenu
+              bookId={bookMeta.id}
+              onImported={reloadBookmarks}
+              className="p-2 rounded-lg text-muted hover:text-bright hover:bg-surface transition-colors"
+            />
+          )}
           <button
-            onClick={() => { setShowToc(!showToc); setShowSettings(false); }}
+            onClick={() => { setShowToc(!showToc); setShowSettings(false); setShowSearch(false); setShowBookmarks(false); }}
             className={`p-2 rounded-lg transition-colors ${showToc ? 'text-purple-glow bg-purple-muted/30' : 'text-muted hover:text-bright hover:bg-surface'}`}
             title="Table of Contents"
           >
             <List size={18} />
           </button>
           <button
-            onClick={() => { setShowSettings(!showSettings); setShowToc(false); }}
+            onClick={() => { setShowSettings(!showSettings); setShowToc(false); setShowSearch(false); setShowBookmarks(false); }}
             className={`p-2 rounded-lg transition-colors ${showSettings ? 'text-purple-glow bg-purple-muted/30' : 'text-muted hover:text-bright hover:bg-surface'}`}
             title="Reading settings"
           >
@@ -484,8 +740,146 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
                   <X size={16} />
                 </button>
               </div>
+              {pageMap.length > 0 && (
+                <form
+                  onSubmit={(e) => { e.preventDefault(); goToPage(); }}
+                  className="px-4 py-3 border-b border-border"
+                >
+                  <div className="flex items-center gap-2">
+                    <input
+                      value={pageQuery}
+                      onChange={(e) => { setPageQuery(e.target.value); setPageMissing(false); }}
+                      placeholder="Go to print page"
+                      className="flex-1 min-w-0 bg-transparent text-sm text-bright placeholder:text-muted outline-none"
+                    />
+                    <button
+                      type="submit"
+                      className="p-1.5 rounded-lg text-muted hover:text-bright hover:bg-surface transition-colors"
+                      title="Go to page"
+                    >
+                      <ChevronRight size={16} />
+                    </button>
+                  </div>
+                  {pageMissing && (
+                    <p className="mt-1 text-xs text-muted">No page “{pageQuery.trim()}” in this edition</p>
+                  )}
+                </form>
+              )}
+              <div className="flex-1 overflow-y-auto py-2">
+                <TocTree items={book?.structure.toc || []} onSelect={goToHref} level={0} />
+              </div>
+            </motion.div>
+          )}
+        </AnimatePresence>
+
+        {/* Search panel */}
+        <AnimatePresence>
+          {showSearch && (
+            <motion.div
+              initial={{ x: -320, opacity: 0 }}
+              animate={{ x: 0, opacity: 1 }}
+              exit={{ x: -320, opacity: 0 }}
+              transition={{ duration: 0.2, ease: 'easeOut' }}
+              className="absolute left-0 top-0 bottom-0 w-80 bg-abyss border-r border-border z-10 flex flex-col"
+            >
+              <div className="flex items-center gap-2 px-4 py-3 border-b border-border">
+                <Search size={14} className="text-muted flex-shrink-0" />
+                <input
+                  autoFocus
+                  value={search.query}
+                  onChange={(e) => search.setQuery(e.target.value)}
+                  placeholder="Search this book"
+                  className="flex-1 min-w-0 bg-transparent text-sm text-bright placeholder:text-muted outline-none"
+                />
+                <button onClick={() => setShowSearch(false)} className="p-1 rounded text-muted hover:text-bright">
+                  <X size={16} />
+                </button>
+              </div>
+              <div className="flex-1 overflow-y-auto py-2">
+                {search.searching && <p className="px-4 py-2 text-xs text-muted">Searching...</p>}
+                {!search.searching && search.query.trim() && search.hits.length === 0 && (
+                  <p className="px-4 py-2 text-xs text-muted">
+                    {search.error?.code === 'notIndexed'
+                      ? 'This book is still being indexed. Try again in a moment.'
+                      : 'No matches'}
+                  </p>
+                )}
+                {search.hits.map((hit, i) => (
+                  <button
+                    key={i}
+                    onClick={() => goToMatch(hit)}
+                    className="w-full text-left px-4 py-2 hover:bg-surface/50 transition-colors"
+                  >
+                    <span className="block text-[11px] uppercase tracking-wider text-muted mb-0.5">
+                      Chapter {contentIndices.indexOf(hit.chapterIndex) + 1}
+                    </span>
+                    <SearchSnippet hit={hit} />
+                  </button>
+                ))}
+              </div>
+            </motion.div>
+          )}
+        </AnimatePresence>
+
+        {/* Bookmarks panel */}
+        <AnimatePresence>
+          {showBookmarks && (
+            <motion.div
+              initial={{ x: -320, opacity: 0 }}
+              animate={{ x: 0, opacity: 1 }}
+              exit={{ x: -320, opacity: 0 }}
+              transition={{ duration: 0.2, ease: 'easeOut' }}
+              className="absolute left-0 top-0 bottom-0 w-80 bg-abyss border-r border-border z-10 flex flex-col"
+            >
+              <div className="flex items-center justify-between px-4 py-3 border-b border-border">
+                <h3 className="text-sm font-semibold text-bright">Bookmarks</h3>
+                <button onClick={() => setShowBookmarks(false)} className="p-1 rounded text-muted hover:text-bright">
+                  <X size={16} />
+                </button>
+              </div>
+              <form
+                onSubmit={(e) => { e.preventDefault(); handleAddBookmark(); }}
+                className="flex items-center gap-2 px-4 py-3 border-b border-border"
+              >
+                <input
+                  value={bookmarkName}
+                  onChange={(e) => setBookmarkName(e.target.value)}
+                  placeholder="Name (optional)"
+                  className="flex-1 min-w-0 bg-transparent text-sm text-bright placeholder:text-muted outline-none"
+                />
+                <button
+                  type="submit"
+                  className="p-1.5 rounded-lg text-muted hover:text-bright hover:bg-surface transition-colors"
+                  title="Bookmark this page"
+                >
+                  <BookmarkPlus size={16} />
+                </button>
+              </form>
               <div className="flex-1 overflow-y-auto py-2">
-                <TocTree items={bookContent?.toc || []} onSelect={goToHref} level={0} />
+                {bookmarks.length === 0 && (
+                  <p className="px-4 py-2 text-xs text-muted">No bookmarks yet</p>
+                )}
+                {bookmarks.map((mark) => (
+                  <div key={mark.id} className="group flex items-start gap-1 px-4 py-2 hover:bg-surface/50 transition-colors">
+                    <button onClick={() => goToCfi(mark.cfi)} className="flex-1 min-w-0 text-left">
+                      <span className="block text-sm text-bright line-clamp-1">{mark.name}</span>
+                      <span className="block text-[11px] uppercase tracking-wider text-muted mb-0.5">
+                        {mark.chapterTitle && mark.chapterTitle !== mark.name ? `${mark.chapterTitle} · ` : ''}
+                        {new Date(mark.createdAt).toLocaleDateString()}
+                      </span>
+                      {mark.excerpt && (
+                        <span className="block text-xs text-text line-clamp-2">{mark.excerpt}</span>
+                      )}
+                    </button>
+                    <button
+                      onClick={() => removeBookmark(mark.id).catch((err) => console.error('Failed to remove bookmark:', err))}
+                      className="p-1 rounded text-muted opacity-0 group-hover:opacity-100 hover:text-crimson-glow transition-opacity"
+                      title="Remove bookmark"
+                    >
+                      <Trash2 size={14} />
+                    </button>
+                  </div>
+                ))}
               </div>
             </motion.div>
           )}
@@ -538,6 +932,22 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
                   <input type="range" min="1.2" max="2.4" step="0.1" value={lineHeight} onChange={(e) => setLineHeight(parseFloat(e.target.value))} className="w-full accent-purple" />
                   <div className="flex justify-between text-xs text-muted mt-1"><span>Tight</span><span>{lineHeight.toFixed(1)}</span><span>Loose</span></div>
                 </div>
+                <div>
+                  <label className="text-xs text-muted uppercase tracking-wider font-medium block mb-2">This Book</label>
+                  <button
+                    onClick={() => {
+                      const next = !publisherStyles;
+                      setPublisherStyles(next);
+                      onUpdateBook?.({ publisherStyles: next });
+                    }}
+                    className={`w-full text-left px-3 py-2 rounded-lg text-sm transition-colors ${
+                      publisherStyles
+                        ? 'bg-purple-muted/30 border border-purple/40 text-purple-glow'
+                        : 'bg-surface border border-border text-text hover:border-border-light'
+                    }`}
+                    title="Use the book's own layout and typography, minus colors and fixed font sizes"
+                  >Publisher styles: {publisherStyles ? 'On' : 'Off'}</button>
+                </div>
               </div>
             </motion.div>
           )}
@@ -559,19 +969,105 @@ export default function Reader({ bookData, bookMeta, onBack, onUpdateProgress })
               </div>
             </div>
           ) : (
-            <div
-              className="epub-body"
-              dangerouslySetInnerHTML={{ __html: bookContent?.html || '' }}
-            />
+            <>
+              <div
+                ref={bodyRef}
+                className="epub-body"
+                onClick={handleContentClick}
+                dangerouslySetInnerHTML={{ __html: chapter?.html || '' }}
+              />
+              {position < contentIndices.length - 1 && (
+                <div className="flex justify-center pb-16">
+                  <button
+                    onClick={() => goToPosition(position + 1)}
+                    className="inline-flex items-center gap-2 px-5 py-2.5 rounded-xl bg-surface border border-border text-sm text-text hover:text-bright hover:border-border-light transition-colors"
+                  >
+                    Next chapter <ChevronRight size={16} />
+                  </button>
+                </div>
+              )}
+            </>
           )}
         </div>
       </div>
 
-      {/* Bottom bar — progress only */}
+      {/* Footnote popover */}
+      {note && (
+        <div
+          ref={noteRef}
+          className="fixed z-50 w-80 max-h-64 overflow-y-auto rounded-xl bg-abyss border border-border shadow-xl"
+          style={{
+            left: Math.max(8, Math.min(note.x, window.innerWidth - 328)),
+            // Above the link when there's no room below it
+            ...(note.y + 272 > window.innerHeight
+              ? { bottom: window.innerHeight - note.top + 8 }
+              : { top: note.y + 8 }),
+          }}
+        >
+          <div
+            className="epub-body epub-note"
+            onClick={handleNoteClick}
+            dangerouslySetInnerHTML={{ __html: note.html }}
+          />
+          <div className="flex justify-end px-3 pb-2">
+            <button
+              onClick={() => {
+                setNote(null);
+                goToHref(note.href);
+              }}
+              className="text-xs text-muted hover:text-bright transition-colors"
+            >
+              Go to note
+            </button>
+          </div>
+        </div>
+      )}
+
+      {/* Bottom bar — chapter navigation and progress */}
       <div className="flex items-center gap-3 px-4 py-2 border-t border-border bg-abyss/80 flex-shrink-0">
-        <span className="text-xs text-muted w-10 text-right">
+        <button
+          onClick={() => goToPosition(position - 1)}
+          disabled={position <= 0}
+          className="p-1.5 rounded text-muted hover:text-bright disabled:opacity-30 transition-colors"
+          title="Previous chapter"
+        >
+          <ChevronLeft size={14} />
+        </button>
+        <button
+          onClick={() => goToPosition(position + 1)}
+          disabled={position < 0 || position >= contentIndices.length - 1}
+          className="p-1.5 rounded text-muted hover:text-bright disabled:opacity-30 transition-colors"
+          title="Next chapter"
+        >
+          <ChevronRight size={14} />
+        </button>
+        <span
+          className="text-xs text-muted w-10 text-right"
+          title={location ? `Location ${location} of ${length.locations}` : undefined}
+        >
           {Math.round(progress * 100)}%
         </span>
+        {inFrontMatter && (
+          <button
+            onClick={() => goToHref(start.href)}
+            className="text-xs text-muted hover:text-bright whitespace-nowrap transition-colors"
+          >
+            Skip front matter
+          </button>
+        )}
+        {page && (
+          <span className="text-xs text-muted whitespace-nowrap" title="Page in the print edition">
+            p. {page}
+          </span>
+        )}
+        {timeLeft && (
+          <span
+            className="text-xs text-muted whitespace-nowrap"
+            title={`${formatDuration(timeLeft.bookMinutes)} left in book, at ${Math.round(timeLeft.wordsPerMinute)} words a minute`}
+          >
+            {formatDuration(timeLeft.chapterMinutes)} left in chapter
+          </span>
+        )}
         <div className="flex-1 h-1 rounded-full bg-surface overflow-hidden">
           <motion.div
             className="h-full bg-gradient-to-r from-purple to-purple-glow rounded-full"
@@ -0,0 +1,14 @@
+// Renders a search hit's snippet with the match highlighted. Offsets are
+// UTF-16 code units, which is what JavaScript string indices count.
+export default function SearchSnippet({ hit }) {
+  const { snippet, snippetOffset, length } = hit;
+  return (
+    <span className="text-xs text-muted leading-relaxed">
+      {snippet.slice(0, snippetOffset)}
+      <mark className="bg-purple-muted/60 text-bright rounded px-0.5">
+        {snippet.slice(snippetOffset, snippetOffset + length)}
+      </mark>
+      {snippet.slice(snippetOffset + length)}
+    </span>
+  );
+}
@@ -0,0 +1,36 @@
+import { useState, useEffect, useCallback } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+
+// A book's bookmarks, in reading order. `bookId` is the library id.
+export default function useBookmarks(bookId) {
+  const [bookmarks, setBookmarks] = useState([]);
+
+  const reload = useCallback(async () => {
+    if (!bookId) return;
+    try {
+      setBookmarks(await invoke('bookmark_list', { bookId }));
+    } catch (err) {
+      console.error('Failed to load bookmarks:', err);
+    }
+  }, [bookId]);
+
+  useEffect(() => {
+    reload();
+  }, [reload]);
+
+  const addBookmark = useCallback(
+    async (cfi, name) => {
+      const saved = await invoke('bookmark_add', { bookId, cfi, name: name || null });
+      await reload();
+      return saved;
+    },
+    [bookId, reload]
+  );
+
+  const removeBookmark = useCallback(async (id) => {
+    await invoke('bookmark_remove', { id });
+    setBookmarks((prev) => prev.filter((b) => b.id !== id));
+  }, []);
+
+  return { bookmarks, addBookmark, removeBookmark, reload };
+}
@@ -0,0 +1,23 @@
+import { useState, useEffect } from 'react';
+import { invoke, convertFileSrc } from '@tauri-apps/api/core';
+
+// Resolves a book's cached cover thumbnail to a URL the webview can load.
+// `size` is one of 'small', 'medium' or 'large'.
+export default function useCover(bookId, size = 'medium') {
+  const [src, setSrc] = useState(null);
+
+  useEffect(() => {
+    let cancelled = false;
+    setSrc(null);
+    invoke('library_cover', { id: bookId, size })
+      .then((path) => {
+        if (!cancelled) setSrc(path ? convertFileSrc(path) : null);
+      })
+      .catch((err) => console.error('Failed to load cover:', err));
+    return () => {
+      cancelled = true;
+    };
+  }, [bookId, size]);
+
+  return src;
+}
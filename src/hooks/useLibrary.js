@@ -1,58 +1,88 @@
-import { useState, useEffect } from 'react';
+import { useState, useEffect, useCallback } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 
-const STORAGE_KEY = 'shpeegle-library';
+// Pre-SQLite builds kept the whole library in this localStorage key.
+const LEGACY_STORAGE_KEY = 'shpeegle-library';
 
-function load() {
-  try {
-    const data = localStorage.getItem(STORAGE_KEY);
-    return data ? JSON.parse(data) : { books: [], lastRead: null };
-  } catch {
-    return { books: [], lastRead: null };
-  }
-}
+let migration = null;
 
-function save(library) {
-  try {
-    localStorage.setItem(STORAGE_KEY, JSON.stringify(library));
-  } catch {}
+// Move books from localStorage into the backend store, once.
+function migrateLegacyLibrary() {
+  migration ??= (async () => {
+    let legacy;
+    try {
+      legacy = JSON.parse(localStorage.getItem(LEGACY_STORAGE_KEY) || 'null');
+    } catch {
+      return;
+    }
+    const books = (legacy?.books || []).map((book) => ({
+      path: book.path,
+      title: book.title || 'Untitled',
+      author: book.author || '',
+      progress: book.progress || 0,
+      currentCfi: book.currentCfi ?? null,
+      lastOpened: book.lastOpened ?? null,
+    }));
+    // Only accepted once, into an empty library; calling with nothing to
+    // import closes it for good.
+    await invoke('library_import_legacy', { books });
+    try { localStorage.removeItem(LEGACY_STORAGE_KEY); } catch {}
+  })();
+  return migration;
 }
 
 export default function useLibrary() {
-  const [library, setLibrary] = useState(load);
+  const [books, setBooks] = useState([]);
+
+  const reload = useCallback(
+    () =>
+      invoke('library_list')
+        .then(setBooks)
+        .catch((err) => console.error('Failed to load library:', err)),
+    []
+  );
 
   useEffect(() => {
-    save(library);
-  }, [library]);
-
-  const addBook = (book) => {
-    setLibrary((prev) => {
-      const existing = prev.books.findIndex((b) => b.path === book.path);
-      const books = [...prev.books];
-      if (existing >= 0) {
-        books[existing] = { ...books[existing], ...book, lastOpened: Date.now() };
-      } else {
-        books.unshift({ ...book, addedAt: Date.now(), lastOpened: Date.now(), progress: 0, currentCfi: null });
-      }
-      return { ...prev, books, lastRead: book.path };
-    });
-  };
-
-  const updateBook = (path, updates) => {
-    setLibrary((prev) => {
-      const books = prev.books.map((b) =>
-        b.path === path ? { ...b, ...updates } : b
-      );
-      return { ...prev, books };
-    });
-  };
-
-  const removeBook = (path) => {
-    setLibrary((prev) => ({
-      ...prev,
-      books: prev.books.filter((b) => b.path !== path),
-      lastRead: prev.lastRead === path ? null : prev.lastRead,
-    }));
-  };
+    migrateLegacyLibrary()
+      .catch((err) => console.error('Library migration failed:', err))
+      .then(reload);
+  }, [reload]);
+
+  // Watched folders change underneath us; the payload lists what happened,
+  // but a full reload keeps ordering and counts consistent.
+  useEffect(() => {
+    const unlisten = listen('library://changed', reload);
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [reload]);
+
+  // Resolves to the picked book, or null if the dialog was cancelled.
+  const openFile = useCallback(async () => {
+    const saved = await invoke('library_open_file');
+    if (saved) setBooks((prev) => [saved, ...prev.filter((b) => b.id !== saved.id)]);
+    return saved;
+  }, []);
+
+  const updateBook = useCallback(async (id, updates) => {
+    const saved = await invoke('library_update', { id, updates });
+    setBooks((prev) => prev.map((b) => (b.id === id ? saved : b)));
+    return saved;
+  }, []);
+
+  // Asks where a missing book's file went. Resolves to the updated book, or
+  // null if the dialog was cancelled.
+  const locateBook = useCallback(async (id) => {
+    const saved = await invoke('library_locate', { id });
+    if (saved) setBooks((prev) => prev.map((b) => (b.id === id ? saved : b)));
+    return saved;
+  }, []);
+
+  const removeBook = useCallback(async (id) => {
+    await invoke('library_remove', { id });
+    setBooks((prev) => prev.filter((b) => b.id !== id));
+  }, []);
 
-  return { library, addBook, updateBook, removeBook };
+  return { library: { books }, openFile, updateBook, locateBook, removeBook, reload };
 }
@@ -0,0 +1,69 @@
+import { useState, useEffect, useRef } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+
+const HEARTBEAT_MS = 30 * 1000;
+// No scrolling, typing or pointer movement for this long counts as having
+// walked away, and stops the session until the reader is used again.
+const IDLE_MS = 5 * 60 * 1000;
+const ACTIVITY_EVENTS = ['scroll', 'wheel', 'keydown', 'pointermove', 'pointerdown'];
+
+// Records reading sessions for the open book `bookId` and estimates the time
+// left. `index` is the chapter on screen, `anchorRef` holds the text offset
+// at the top of the page, and `progress` is the fraction of the book read.
+export default function useReadingSession(bookId, index, anchorRef, progress) {
+  const sessionRef = useRef(null);
+  const activeAtRef = useRef(Date.now());
+  const progressRef = useRef(progress);
+  progressRef.current = progress;
+  const [timeLeft, setTimeLeft] = useState(null);
+
+  useEffect(() => {
+    const onActivity = () => { activeAtRef.current = Date.now(); };
+    // Captured, since scroll events on elements don't bubble.
+    ACTIVITY_EVENTS.forEach((type) =>
+      window.addEventListener(type, onActivity, { capture: true, passive: true })
+    );
+    return () => ACTIVITY_EVENTS.forEach((type) =>
+      window.removeEventListener(type, onActivity, { capture: true })
+    );
+  }, []);
+
+  useEffect(() => {
+    if (!bookId || index === null) return;
+    const timer = setInterval(async () => {
+      const offset = anchorRef.current;
+      const idle = Date.now() - activeAtRef.current > IDLE_MS;
+      if (offset === null || idle || document.hidden) return;
+      try {
+        const session = await invoke('reading_heartbeat', {
+          heartbeat: { sessionId: sessionRef.current, bookId, index, offset, progress: progressRef.current },
+        });
+        sessionRef.current = session.id;
+      } catch (err) {
+        console.error('Failed to record reading session:', err);
+      }
+    }, HEARTBEAT_MS);
+    return () => clearInterval(timer);
+  }, [bookId, index, anchorRef]);
+
+  // Counting the words left reads the rest of the book, so wait for
+  // scrolling to settle.
+  useEffect(() => {
+    if (!bookId || index === null) return;
+    let cancelled = false;
+    const timer = setTimeout(async () => {
+      try {
+        const left = await invoke('reading_time_left', { bookId, index, offset: anchorRef.current ?? 0 });
+        if (!cancelled) setTimeLeft(left);
+      } catch (err) {
+        console.error('Failed to estimate time left:', err);
+      }
+    }, 2000);
+    return () => {
+      cancelled = true;
+      clearTimeout(timer);
+    };
+  }, [bookId, index, anchorRef, progress]);
+
+  return timeLeft;
+}
@@ -0,0 +1,46 @@
+import { useState, useEffect } from 'react';
+
+// Debounced search-as-you-type. `search` takes the trimmed query and
+// resolves to a list of hits; keep it stable with useCallback. `error` is
+// the last search's error, if it failed.
+export default function useSearch(search) {
+  const [query, setQuery] = useState('');
+  const [hits, setHits] = useState([]);
+  const [searching, setSearching] = useState(false);
+  const [error, setError] = useState(null);
+
+  useEffect(() => {
+    const trimmed = query.trim();
+    if (!trimmed) {
+      setHits([]);
+      setSearching(false);
+      setError(null);
+      return;
+    }
+    let cancelled = false;
+    const timer = setTimeout(() => {
+      setSearching(true);
+      search(trimmed)
+        .then((results) => {
+          if (cancelled) return;
+          setHits(results);
+          setError(null);
+        })
+        .catch((err) => {
+          console.error('Search failed:', err);
+          if (cancelled) return;
+          setHits([]);
+          setError(err);
+        })
+        .finally(() => {
+          if (!cancelled) setSearching(false);
+        });
+    }, 250);
+    return () => {
+      cancelled = true;
+      clearTimeout(timer);
+    };
+  }, [query, search]);
+
+  return { query, setQuery, hits, searching, error };
+}